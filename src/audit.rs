@@ -0,0 +1,470 @@
+//! The `audit` module runs statistical tests on the cards observed in
+//! recorded rounds
+//!
+//! Commitments prove that the cards came from the revealed seeds, but not
+//! that the card source itself is unbiased. These tests compare what was
+//! actually dealt against the values expected for the declared `Ruleset`,
+//! so that a player or regulator can detect a rigged card source from the
+//! outside, without access to the server
+use super::game::dealer_hits;
+use super::stats::{chi_square, chi_square_p, TestResult};
+use super::verify::{Log, Round};
+use super::*;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Ranks standing in for each of the ten card values, 2 through Ace
+const VALUES: [Rank; 10] = [
+    Rank::Two,
+    Rank::Three,
+    Rank::Four,
+    Rank::Five,
+    Rank::Six,
+    Rank::Seven,
+    Rank::Eight,
+    Rank::Nine,
+    Rank::Ten,
+    Rank::Ace,
+];
+
+/// Index into `VALUES` for a card
+fn value_index(card: Card) -> usize {
+    card.value() as usize - 2
+}
+
+/// Observed and expected dealer bust rate for one upcard value
+#[derive(Clone, Debug, PartialEq)]
+pub struct BustRate {
+    pub upcard: Rank,
+    pub rounds: u64,
+    pub observed: f64,
+    pub expected: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub rounds: u64,
+    pub tests: Vec<TestResult>,
+    pub bust: Vec<BustRate>,
+}
+
+impl Report {
+    /// Tests rejecting the null hypothesis of a fair source at level `alpha`
+    pub fn failures(&self, alpha: f64) -> Vec<&TestResult> {
+        self.tests.iter().filter(|t| t.p < alpha).collect()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} rounds", self.rounds)?;
+        for t in &self.tests {
+            writeln!(
+                f,
+                "{:<24} n {:>10}\tchi2 {:>10.3}\tdf {:>3}\tp {:.4}",
+                t.name, t.n, t.statistic, t.df, t.p
+            )?;
+        }
+        for b in &self.bust {
+            writeln!(
+                f,
+                "dealer bust, {:?} up      n {:>10}\tobserved {:.4}\texpected {:.4}",
+                b.upcard, b.rounds, b.observed, b.expected
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The four cards dealt before the player makes any decision: the player's
+/// first two cards, then the dealer's hole card and upcard
+fn initial_deal(view: &View) -> [Card; 4] {
+    let hands = &view.player.hands;
+    // Splitting moves the player's second card to the start of a new hand
+    let second = match hands.get(1) {
+        Some(hand) => hand.cards[0],
+        None => hands[0].cards[1],
+    };
+    [
+        hands[0].cards[0],
+        second,
+        view.dealer.cards[0],
+        view.dealer.cards[1],
+    ]
+}
+
+/// Accumulates observed cards over many rounds
+pub struct Audit {
+    rules: Ruleset,
+    rounds: u64,
+    ranks: [u64; 13],
+    suits: [u64; 4],
+    upcards: [u64; 13],
+    dealer_played: [u64; 10],
+    dealer_bust: [u64; 10],
+    /// Sum of the expected bust probabilities, and of their variances
+    dealer_expected: [f64; 10],
+    dealer_variance: [f64; 10],
+    blackjacks: u64,
+    /// Expected bust probability by upcard and the player's cards
+    cache: HashMap<(usize, [u8; 10]), f64>,
+}
+
+impl Audit {
+    pub fn new(rules: Ruleset) -> Audit {
+        Audit {
+            rules,
+            rounds: 0,
+            ranks: [0; 13],
+            suits: [0; 4],
+            upcards: [0; 13],
+            dealer_played: [0; 10],
+            dealer_bust: [0; 10],
+            dealer_expected: [0.0; 10],
+            dealer_variance: [0.0; 10],
+            blackjacks: 0,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Add the cards of a finished round. Rounds that did not reach the
+    /// `Final` state are ignored
+    pub fn record(&mut self, round: &Round) {
+        let view = &round.view;
+        if view.state != State::Final || view.dealer.cards.len() < 2 {
+            return;
+        }
+        self.rounds += 1;
+
+        // Whether later cards are drawn depends on their values, which skews
+        // the ranks of a finite shoe towards the cards that stop drawing.
+        // Only the initial deal is free of this, but suits are unaffected
+        for card in &initial_deal(view) {
+            self.ranks[card.rank as usize] += 1;
+        }
+        let player = view.player.hands.iter().flat_map(|h| h.cards.iter());
+        for card in player.clone().chain(view.dealer.cards.iter()) {
+            self.suits[card.suit as usize] += 1;
+        }
+
+        // The dealer's first card is the hole card
+        let upcard = view.dealer.cards[1];
+        self.upcards[upcard.rank as usize] += 1;
+
+        // A pair can't be a blackjack, so after a split there was none
+        if view.player.hands.len() == 1 && view.player.hands[0].blackjack() {
+            self.blackjacks += 1;
+        }
+
+        // The dealer only plays out the hand if the player has a live hand
+        let surrendered = round.actions.contains(&Action::Surrender);
+        if !surrendered && view.player.hands.iter().any(|h| !h.bust()) {
+            let idx = value_index(upcard);
+            let seen = player.copied().collect::<Vec<_>>();
+            let p = self.expected_bust(upcard, &seen);
+            self.dealer_played[idx] += 1;
+            self.dealer_expected[idx] += p;
+            self.dealer_variance[idx] += p * (1.0 - p);
+            if view.dealer.bust() {
+                self.dealer_bust[idx] += 1;
+            }
+        }
+    }
+
+    fn expected_bust(&mut self, upcard: Card, seen: &[Card]) -> f64 {
+        let mut key = [0u8; 10];
+        if self.rules.decks.count().is_some() {
+            for &card in seen {
+                key[value_index(card)] += 1;
+            }
+        }
+        let rules = self.rules;
+        *self
+            .cache
+            .entry((value_index(upcard), key))
+            .or_insert_with(|| dealer_bust_probability(&rules, upcard.rank, seen))
+    }
+
+    pub fn report(&self) -> Report {
+        let mut tests = Vec::new();
+
+        let (stat, df) = chi_square(&self.ranks, &[1.0 / 13.0; 13]);
+        tests.push(TestResult {
+            name: "rank frequency (deal)",
+            n: self.ranks.iter().sum(),
+            statistic: stat,
+            df,
+            p: chi_square_p(stat, df),
+        });
+
+        let (stat, df) = chi_square(&self.suits, &[0.25; 4]);
+        tests.push(TestResult {
+            name: "suit frequency",
+            n: self.suits.iter().sum(),
+            statistic: stat,
+            df,
+            p: chi_square_p(stat, df),
+        });
+
+        let (stat, df) = chi_square(&self.upcards, &[1.0 / 13.0; 13]);
+        tests.push(TestResult {
+            name: "dealer upcard",
+            n: self.rounds,
+            statistic: stat,
+            df,
+            p: chi_square_p(stat, df),
+        });
+
+        // Each upcard contributes an independent term, comparing the number
+        // of busts against the sum of each round's expected bust probability
+        let mut bust = Vec::new();
+        let mut stat = 0.0;
+        let mut df = 0;
+        for (idx, &rank) in VALUES.iter().enumerate() {
+            let n = self.dealer_played[idx];
+            if n == 0 {
+                continue;
+            }
+            let var = self.dealer_variance[idx];
+            if var > 0.0 {
+                stat += (self.dealer_bust[idx] as f64 - self.dealer_expected[idx]).powi(2) / var;
+                df += 1;
+            }
+            bust.push(BustRate {
+                upcard: rank,
+                rounds: n,
+                observed: self.dealer_bust[idx] as f64 / n as f64,
+                expected: self.dealer_expected[idx] / n as f64,
+            });
+        }
+        tests.push(TestResult {
+            name: "dealer bust by upcard",
+            n: self.dealer_played.iter().sum(),
+            statistic: stat,
+            df,
+            p: chi_square_p(stat, df),
+        });
+
+        let p = blackjack_probability(&self.rules);
+        let (stat, df) = chi_square(
+            &[self.blackjacks, self.rounds - self.blackjacks],
+            &[p, 1.0 - p],
+        );
+        tests.push(TestResult {
+            name: "player blackjack",
+            n: self.rounds,
+            statistic: stat,
+            df,
+            p: chi_square_p(stat, df),
+        });
+
+        Report {
+            rounds: self.rounds,
+            tests,
+            bust,
+        }
+    }
+}
+
+/// Audit every round in a session log
+pub fn audit(log: &Log) -> Report {
+    let mut audit = Audit::new(log.rules);
+    for round in &log.rounds {
+        audit.record(round);
+    }
+    audit.report()
+}
+
+/// Number of cards of each value in a full shoe, and whether cards are
+/// removed from the shoe as they are dealt
+fn composition(rules: &Ruleset) -> ([u64; 10], bool) {
+    let decks = rules.decks.count().unwrap_or(1) as u64;
+    let mut counts = [4 * decks; 10];
+    counts[8] = 16 * decks;
+    (counts, rules.decks.count().is_some())
+}
+
+/// Probability of the player's first two cards being a blackjack
+pub fn blackjack_probability(rules: &Ruleset) -> f64 {
+    let (counts, finite) = composition(rules);
+    let total: u64 = counts.iter().sum();
+    let removed = if finite { 1 } else { 0 };
+    2.0 * (counts[9] as f64 / total as f64) * (counts[8] as f64 / (total - removed) as f64)
+}
+
+/// Probability that the dealer busts given `upcard`, when the cards in
+/// `seen` have also been removed from the shoe, under the engine's own
+/// drawing rule. `seen` is ignored for an infinite deck
+pub fn dealer_bust_probability(rules: &Ruleset, upcard: Rank, seen: &[Card]) -> f64 {
+    let (mut counts, finite) = composition(rules);
+    let card = Card {
+        rank: upcard,
+        suit: Suit::Hearts,
+    };
+    if finite {
+        for &c in seen.iter().chain(std::iter::once(&card)) {
+            counts[value_index(c)] = counts[value_index(c)].saturating_sub(1);
+        }
+    }
+    let mut dealer = Dealer {
+        rules,
+        hand: Hand { cards: vec![card] },
+        drawn: [0; 10],
+        counts,
+        finite,
+        memo: HashMap::new(),
+    };
+    dealer.bust()
+}
+
+/// Exhaustive search over the dealer's draws. The outcome only depends on
+/// which cards have been drawn and not their order, so results are memoized
+/// on the multiset of drawn values
+struct Dealer<'a> {
+    rules: &'a Ruleset,
+    hand: Hand,
+    drawn: [u8; 10],
+    counts: [u64; 10],
+    finite: bool,
+    memo: HashMap<[u8; 10], f64>,
+}
+
+impl Dealer<'_> {
+    fn bust(&mut self) -> f64 {
+        if !dealer_hits(&self.hand, self.rules) {
+            return if self.hand.bust() { 1.0 } else { 0.0 };
+        }
+        if let Some(&p) = self.memo.get(&self.drawn) {
+            return p;
+        }
+        let total: u64 = self.counts.iter().sum();
+        let mut p = 0.0;
+        for (idx, &rank) in VALUES.iter().enumerate() {
+            if self.counts[idx] == 0 {
+                continue;
+            }
+            let weight = self.counts[idx] as f64 / total as f64;
+            self.hand.deal(Card {
+                rank,
+                suit: Suit::Hearts,
+            });
+            self.drawn[idx] += 1;
+            if self.finite {
+                self.counts[idx] -= 1;
+            }
+            p += weight * self.bust();
+            if self.finite {
+                self.counts[idx] += 1;
+            }
+            self.drawn[idx] -= 1;
+            self.hand.cards.pop();
+        }
+        self.memo.insert(self.drawn, p);
+        p
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(notation: &str) -> Card {
+        Card::from_notation(notation).unwrap()
+    }
+
+    /// Play a round from a stacked shoe, standing or hitting as given
+    fn round(cards: &str, actions: &[Action]) -> Round {
+        let shoe = Shoe::Deck(Deck::stacked(cards));
+        let mut game = Game::with_shoe(Ruleset::default(), Player::new(Money::chips(10)), shoe);
+        game.bet(Money::chips(2)).unwrap();
+        for &action in actions {
+            game.player(action).unwrap();
+        }
+        if game.view().state == State::Dealer {
+            game.dealer().unwrap();
+        }
+        Round {
+            deck: game.commitment().into(),
+            bet: Money::chips(2),
+            actions: actions.to_vec(),
+            view: game.view(),
+            chips: game.finish().unwrap().chips,
+        }
+    }
+
+    #[test]
+    fn known_probabilities() {
+        // Published values for an infinite deck, dealer standing on soft 17
+        // and not peeking for blackjack
+        let infinite = Ruleset::default().infinite();
+        for &(upcard, expected) in &[
+            (Rank::Two, 0.353608),
+            (Rank::Six, 0.423150),
+            (Rank::King, 0.212109),
+            (Rank::Ace, 0.115286),
+        ] {
+            let p = dealer_bust_probability(&infinite, upcard, &[]);
+            assert!((p - expected).abs() < 1e-6, "{:?}: {}", upcard, p);
+        }
+        let hits = Ruleset {
+            stand: false,
+            ..infinite
+        };
+        let p = dealer_bust_probability(&hits, Rank::Six, &[]);
+        assert!((p - 0.439467).abs() < 1e-6, "{}", p);
+
+        // An ace and then a ten valued card, in either order
+        let one = blackjack_probability(&Ruleset::default());
+        assert!((one - 2.0 * (4.0 / 52.0) * (16.0 / 51.0)).abs() < 1e-12);
+        let p = blackjack_probability(&infinite);
+        assert!((p - 2.0 * (1.0 / 13.0) * (4.0 / 13.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn report_counts_recorded_rounds() {
+        let mut audit = Audit::new(Ruleset::default());
+        // Dealer 2 6 draws to 18, dealer 6 6 busts, and the player busts
+        audit.record(&round("10h 2c 9s 6d 10c", &[Action::Stand]));
+        audit.record(&round("10h 6c 9s 6d Kc", &[Action::Stand]));
+        audit.record(&round("10h 7c 6s 9d Kc", &[Action::Hit]));
+        // Rounds that didn't finish are left out
+        let mut error = round("10h 2c 9s 6d 10c", &[Action::Stand]);
+        error.view.state = State::Error;
+        audit.record(&error);
+
+        let report = audit.report();
+        assert_eq!(report.rounds, 3);
+        let n = report
+            .tests
+            .iter()
+            .map(|t| (t.name, t.n))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            n,
+            vec![
+                ("rank frequency (deal)", 12),
+                ("suit frequency", 15),
+                ("dealer upcard", 3),
+                ("dealer bust by upcard", 2),
+                ("player blackjack", 3),
+            ]
+        );
+
+        // The dealer only played out a hand against a 6 up, and both times
+        // after the player's 10 and 9
+        let expected =
+            dealer_bust_probability(&Ruleset::default(), Rank::Six, &[card("10h"), card("9s")]);
+        assert_eq!(
+            report.bust,
+            vec![BustRate {
+                upcard: Rank::Six,
+                rounds: 2,
+                observed: 0.5,
+                expected,
+            }]
+        );
+        assert_eq!(report.tests[3].df, 1);
+        let z = (1.0 - 2.0 * expected).powi(2) / (2.0 * expected * (1.0 - expected));
+        assert!((report.tests[3].statistic - z).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,327 @@
+//! The `battery` module runs a battery of standard statistical tests against
+//! the raw output of any `RngCore`
+//!
+//! Each test is run twice: once on words from `next_u32`, and once on the
+//! high and low halves of words from `next_u64`, since a generator may
+//! implement the two independently. The tests follow NIST SP 800-22
+//! (monobit, runs) and Knuth, TAOCP vol. 2, 3.3.2 (serial, poker, gap), with
+//! Marsaglia's birthday spacings test from DIEHARD.
+//!
+//! A test given too few words for its statistic to be approximately normal
+//! or chi-squared reports a p-value of NaN, and is listed by
+//! `Report::insufficient` rather than passed or failed
+use super::stats::{chi_square, chi_square_p, erfc, normal_p, TestResult};
+use rand_core::RngCore;
+use std::fmt;
+
+/// Which method of the generator a set of words was drawn from
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Width {
+    U32,
+    U64,
+}
+
+impl fmt::Display for Width {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Width::U32 => write!(f, "next_u32"),
+            Width::U64 => write!(f, "next_u64"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    /// Number of 32-bit words tested for each width
+    pub words: usize,
+    pub results: Vec<(Width, TestResult)>,
+}
+
+impl Report {
+    /// Tests rejecting the null hypothesis of a random source at level `alpha`
+    pub fn failures(&self, alpha: f64) -> Vec<&(Width, TestResult)> {
+        self.results.iter().filter(|(_, t)| t.p < alpha).collect()
+    }
+
+    /// Tests that had too few words to give a p-value
+    pub fn insufficient(&self) -> Vec<&(Width, TestResult)> {
+        self.results.iter().filter(|(_, t)| t.p.is_nan()).collect()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} words", self.words)?;
+        for (width, t) in &self.results {
+            if t.p.is_nan() {
+                writeln!(
+                    f,
+                    "{} {:<18} n {:>10}\tinsufficient data",
+                    width, t.name, t.n
+                )?;
+                continue;
+            }
+            writeln!(
+                f,
+                "{} {:<18} n {:>10}\tstat {:>10.3}\tdf {:>3}\tp {:.4}",
+                width, t.name, t.n, t.statistic, t.df, t.p
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Draw `words` 32-bit words from `rng`. For `Width::U64`, each call to
+/// `next_u64` contributes its high then its low half
+pub fn sample<R: RngCore + ?Sized>(rng: &mut R, width: Width, words: usize) -> Vec<u32> {
+    match width {
+        Width::U32 => (0..words).map(|_| rng.next_u32()).collect(),
+        Width::U64 => (0..words.div_ceil(2))
+            .flat_map(|_| {
+                let x = rng.next_u64();
+                vec![(x >> 32) as u32, x as u32]
+            })
+            .take(words)
+            .collect(),
+    }
+}
+
+/// Every test in the battery
+pub const TESTS: [fn(&[u32]) -> TestResult; 6] =
+    [monobit, runs, serial, poker, birthday_spacings, gap];
+
+/// Run every test on `words` words of each width
+pub fn run<R: RngCore + ?Sized>(rng: &mut R, words: usize) -> Report {
+    let mut results = Vec::new();
+    for &width in &[Width::U32, Width::U64] {
+        let w = sample(rng, width, words);
+        for test in &TESTS {
+            results.push((width, test(&w)));
+        }
+    }
+    Report { words, results }
+}
+
+/// Bits needed by the normal approximations of monobit and runs, as in NIST
+/// SP 800-22
+const MIN_BITS: u64 = 100;
+
+/// Expected count needed in every class of a chi-squared test
+const MIN_EXPECTED: f64 = 5.0;
+
+/// Result of a test run on too little data to give a p-value
+fn insufficient(name: &'static str, n: u64) -> TestResult {
+    TestResult {
+        name,
+        n,
+        statistic: f64::NAN,
+        df: 0,
+        p: f64::NAN,
+    }
+}
+
+/// Pearson's test of `counts` against `expected` probabilities, if every
+/// class is expected to be seen often enough
+fn chi_square_test(name: &'static str, counts: &[u64], expected: &[f64]) -> TestResult {
+    let n: u64 = counts.iter().sum();
+    let rarest = expected
+        .iter()
+        .copied()
+        .filter(|&p| p > 0.0)
+        .fold(f64::INFINITY, f64::min);
+    if n as f64 * rarest < MIN_EXPECTED {
+        return insufficient(name, n);
+    }
+    let (stat, df) = chi_square(counts, expected);
+    TestResult {
+        name,
+        n,
+        statistic: stat,
+        df,
+        p: chi_square_p(stat, df),
+    }
+}
+
+fn bits(words: &[u32]) -> impl Iterator<Item = bool> + '_ {
+    words
+        .iter()
+        .flat_map(|&w| (0..32).map(move |i| (w >> i) & 1 == 1))
+}
+
+/// Proportion of one bits, as a normal statistic
+pub fn monobit(words: &[u32]) -> TestResult {
+    let n = words.len() as u64 * 32;
+    if n < MIN_BITS {
+        return insufficient("monobit", n);
+    }
+    let ones: u64 = words.iter().map(|w| w.count_ones() as u64).sum();
+    let z = (2.0 * ones as f64 - n as f64) / (n as f64).sqrt();
+    TestResult {
+        name: "monobit",
+        n,
+        statistic: z,
+        df: 0,
+        p: normal_p(z),
+    }
+}
+
+/// Number of uninterrupted runs of identical bits, as a normal statistic
+pub fn runs(words: &[u32]) -> TestResult {
+    let n = words.len() as u64 * 32;
+    if n < MIN_BITS {
+        return insufficient("runs", n);
+    }
+    let ones: u64 = words.iter().map(|w| w.count_ones() as u64).sum();
+    let pi = ones as f64 / n as f64;
+    let mut runs = 1u64;
+    let mut prev = None;
+    for bit in bits(words) {
+        if prev.is_some_and(|p| p != bit) {
+            runs += 1;
+        }
+        prev = Some(bit);
+    }
+    let expected = 2.0 * n as f64 * pi * (1.0 - pi);
+    let z = (runs as f64 - expected) / (2.0 * (n as f64).sqrt() * pi * (1.0 - pi));
+    // A source failing the monobit precondition fails this test outright
+    let p = if (pi - 0.5).abs() < 2.0 / (n as f64).sqrt() {
+        erfc(z.abs() / std::f64::consts::SQRT_2)
+    } else {
+        0.0
+    };
+    TestResult {
+        name: "runs",
+        n,
+        statistic: z,
+        df: 0,
+        p,
+    }
+}
+
+/// Frequencies of non-overlapping pairs of 4-bit nibbles
+pub fn serial(words: &[u32]) -> TestResult {
+    let mut counts = [0u64; 256];
+    for &w in words {
+        for i in 0..4 {
+            counts[((w >> (8 * i)) & 0xff) as usize] += 1;
+        }
+    }
+    chi_square_test("serial", &counts, &[1.0 / 256.0; 256])
+}
+
+/// Number of distinct values in hands of five 3-bit values, taken from the
+/// top bits of each word
+pub fn poker(words: &[u32]) -> TestResult {
+    // Stirling numbers of the second kind, S(5, r)
+    const STIRLING: [f64; 5] = [1.0, 15.0, 25.0, 10.0, 1.0];
+    let d = 8.0;
+    let mut expected = [0.0; 5];
+    let mut falling = 1.0;
+    for r in 0..5 {
+        falling *= d - r as f64;
+        expected[r] = falling * STIRLING[r] / d.powi(5);
+    }
+
+    let mut counts = [0u64; 5];
+    for hand in words.chunks_exact(5) {
+        let mut seen = 0u8;
+        for &w in hand {
+            seen |= 1 << (w >> 29);
+        }
+        counts[seen.count_ones() as usize - 1] += 1;
+    }
+    chi_square_test("poker", &counts, &expected)
+}
+
+/// Marsaglia's birthday spacings: 512 birthdays in a year of 2^24 days,
+/// taken from the top 24 bits of each word. The number of repeated spacings
+/// between sorted birthdays is asymptotically Poisson with mean 2
+pub fn birthday_spacings(words: &[u32]) -> TestResult {
+    const BIRTHDAYS: usize = 512;
+    const CLASSES: usize = 7;
+    let lambda = (BIRTHDAYS as f64).powi(3) / (4.0 * (1u64 << 24) as f64);
+
+    let mut counts = [0u64; CLASSES];
+    for year in words.chunks_exact(BIRTHDAYS) {
+        let mut days = year.iter().map(|w| w >> 8).collect::<Vec<_>>();
+        days.sort_unstable();
+        let mut spacings = days
+            .iter()
+            .zip(days.iter().skip(1))
+            .map(|(a, b)| b - a)
+            .collect::<Vec<_>>();
+        spacings.push(days[0]);
+        spacings.sort_unstable();
+        let mut distinct = spacings.clone();
+        distinct.dedup();
+        let repeats = spacings.len() - distinct.len();
+        counts[repeats.min(CLASSES - 1)] += 1;
+    }
+
+    // Poisson probabilities, with the last class holding the tail
+    let mut expected = [0.0; CLASSES];
+    let mut pmf = (-lambda).exp();
+    for (j, e) in expected.iter_mut().enumerate().take(CLASSES - 1) {
+        *e = pmf;
+        pmf *= lambda / (j + 1) as f64;
+    }
+    expected[CLASSES - 1] = 1.0 - expected.iter().sum::<f64>();
+
+    chi_square_test("birthday spacings", &counts, &expected)
+}
+
+/// Lengths of the gaps between words falling in the bottom quarter of the
+/// range, with gaps of 16 or more words counted together
+pub fn gap(words: &[u32]) -> TestResult {
+    const CLASSES: usize = 17;
+    let p: f64 = 0.25;
+    let mut counts = [0u64; CLASSES];
+    let mut len = 0;
+    for &w in words {
+        if w < 1 << 30 {
+            counts[len.min(CLASSES - 1)] += 1;
+            len = 0;
+        } else {
+            len += 1;
+        }
+    }
+
+    let mut expected = [0.0; CLASSES];
+    for (r, e) in expected.iter_mut().enumerate() {
+        *e = if r < CLASSES - 1 {
+            p * (1.0 - p).powi(r as i32)
+        } else {
+            (1.0 - p).powi(r as i32)
+        };
+    }
+    chi_square_test("gap", &counts, &expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcg::PCG32;
+
+    #[test]
+    fn too_few_words() {
+        let report = run(&mut PCG32::new(1, 2), 0);
+        assert_eq!(report.insufficient().len(), report.results.len());
+        assert!(report.failures(0.001).is_empty());
+
+        // Enough for the bit tests, but not a single birthday year
+        let words = sample(&mut PCG32::new(1, 2), Width::U32, 500);
+        assert!(!monobit(&words).p.is_nan());
+        assert!(!runs(&words).p.is_nan());
+        assert!(birthday_spacings(&words).p.is_nan());
+    }
+
+    #[test]
+    fn pcg32_passes() {
+        let report = run(
+            &mut PCG32::new(0x853c49e6748fea9b, 0xda3e39cb94b95bdb),
+            200_000,
+        );
+        assert!(report.insufficient().is_empty(), "{}", report);
+        assert!(report.failures(0.0001).is_empty(), "{}", report);
+    }
+}
@@ -0,0 +1,74 @@
+//! Run the statistical test battery against one of the RNG backends
+//!
+//! usage: blackjack-battery [--backend BACKEND] [--seed HEX] [--words N] [--alpha A]
+use blackjack::battery;
+use blackjack::rng::Backend;
+use std::process::exit;
+
+const USAGE: &str =
+    "usage: blackjack-battery [--backend BACKEND] [--seed HEX] [--words N] [--alpha A]";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    exit(2)
+}
+
+fn parse<T: std::str::FromStr>(s: &str) -> T
+where
+    T::Err: std::fmt::Display,
+{
+    s.parse()
+        .unwrap_or_else(|e| fail(&format!("{}: {}\n{}", s, e, USAGE)))
+}
+
+fn main() {
+    let mut backend = Backend::Pcg32;
+    let mut seed = None;
+    let mut words = 1_000_000;
+    let mut alpha = 0.001;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+        match arg.as_str() {
+            "--backend" => backend = parse(&value()),
+            "--seed" => {
+                let s = value();
+                seed = Some(hex::decode(&s).unwrap_or_else(|e| fail(&format!("{}: {}", s, e))))
+            }
+            "--words" => words = parse(&value()),
+            "--alpha" => alpha = parse(&value()),
+            _ => fail(USAGE),
+        }
+    }
+
+    let seed = seed.unwrap_or_else(|| backend.generate_seed());
+    let mut rng = backend.seeded(&seed).unwrap_or_else(|| {
+        fail(&format!(
+            "{} expects a seed of {} bytes",
+            backend,
+            backend.seed_len()
+        ))
+    });
+
+    // Print the seed so that a failing run can be reproduced
+    println!("{} seed {}", backend, hex::encode(&seed));
+    let report = battery::run(&mut rng, words);
+    print!("{}", report);
+
+    let failures = report.failures(alpha);
+    let insufficient = report.insufficient();
+    for (width, t) in &insufficient {
+        println!("SKIP: {} {} needs more than {} words", width, t.name, words);
+    }
+    if failures.is_empty() && !insufficient.is_empty() {
+        exit(2);
+    } else if failures.is_empty() {
+        println!("PASS: {} tests at alpha {}", report.results.len(), alpha);
+    } else {
+        for (width, t) in &failures {
+            println!("FAIL: {} {} p {:.6}", width, t.name, t.p);
+        }
+        exit(1);
+    }
+}
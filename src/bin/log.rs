@@ -0,0 +1,59 @@
+//! Convert a session's round log between JSON and the compact binary format
+//!
+//! usage: blackjack-log --to-json LOG.bin > LOG.json
+//!        blackjack-log --to-binary LOG.json > LOG.bin
+use blackjack::binlog;
+use blackjack::verify::Log;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::process::exit;
+
+const USAGE: &str = "usage: blackjack-log --to-json LOG.bin > LOG.json
+       blackjack-log --to-binary LOG.json > LOG.bin";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    exit(2)
+}
+
+/// Write the rounds as a JSON `Log`, each as soon as it is read
+fn to_json<R: Read, W: Write>(out: &mut W, reader: binlog::Reader<R>) -> io::Result<()> {
+    write!(out, "{{\"rules\":")?;
+    serde_json::to_writer(&mut *out, &reader.rules())?;
+    write!(out, ",\"server\":")?;
+    serde_json::to_writer(&mut *out, reader.server())?;
+    write!(out, ",\"chips\":")?;
+    serde_json::to_writer(&mut *out, &reader.chips())?;
+    write!(out, ",\"rounds\":[")?;
+    for (i, round) in reader.enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        serde_json::to_writer(&mut *out, &round?)?;
+    }
+    writeln!(out, "]}}")
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (mode, path) = match args.as_slice() {
+        [mode, path] => (mode.as_str(), path),
+        _ => fail(USAGE),
+    };
+    let file = std::fs::File::open(path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    match mode {
+        "--to-json" => {
+            let reader = binlog::Reader::new(BufReader::new(file))
+                .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+            to_json(&mut out, reader).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+        }
+        "--to-binary" => {
+            let log: Log = serde_json::from_reader(BufReader::new(file))
+                .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+            binlog::write(&mut out, &log).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+        }
+        _ => fail(USAGE),
+    }
+}
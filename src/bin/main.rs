@@ -1,6 +1,14 @@
-use blackjack;
+use blackjack::binlog;
+use blackjack::chain::{self, AppendError, AuditLog};
+use blackjack::history::HandHistory;
+use blackjack::pcg::PCG32Seed;
+use blackjack::replay::RoundRecord;
+use blackjack::rng::{Backend, Seed};
+use blackjack::session::Session;
+use blackjack::verify;
 use blackjack::*;
-use rand::prelude::*;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
 
 fn basic_strategy(view: &View, idx: usize) -> Action {
     let d = view.dealer.score();
@@ -12,8 +20,8 @@ fn basic_strategy(view: &View, idx: usize) -> Action {
         }
     } else if view.player.hands[idx].soft() {
         match view.player.hands[idx].score() {
-            13 | 14 | 15 | 16 => {
-                if d >= 4 && d <= 6 {
+            13..=16 => {
+                if (4..=6).contains(&d) {
                     Action::Double
                 } else {
                     Action::Hit
@@ -27,9 +35,9 @@ fn basic_strategy(view: &View, idx: usize) -> Action {
                 }
             }
             18 => {
-                if d >= 3 && d <= 6 {
+                if (3..=6).contains(&d) {
                     Action::Double
-                } else if d >= 9 && d <= 10 {
+                } else if (9..=10).contains(&d) {
                     Action::Hit
                 } else {
                     Action::Stand
@@ -46,7 +54,7 @@ fn basic_strategy(view: &View, idx: usize) -> Action {
         }
     } else {
         match view.player.hands[idx].score() {
-            2 | 3 | 4 | 5 | 6 | 7 => Action::Hit,
+            2..=7 => Action::Hit,
             8 => {
                 if d == 5 || d == 6 {
                     Action::Double
@@ -70,13 +78,13 @@ fn basic_strategy(view: &View, idx: usize) -> Action {
             }
             11 => Action::Double,
             12 => {
-                if d >= 4 && d <= 6 {
+                if (4..=6).contains(&d) {
                     Action::Stand
                 } else {
                     Action::Hit
                 }
             }
-            13 | 14 | 15 | 16 => {
+            13..=16 => {
                 if d < 7 {
                     Action::Stand
                 } else {
@@ -102,56 +110,119 @@ fn display_view(view: &View) {
     );
 }
 
+/// Files a displayed simulation is recorded to: the audit chain, the binary
+/// round log and the hand histories
+struct Recorder {
+    audit: AuditLog<File>,
+    rounds: binlog::Writer<BufWriter<File>>,
+    hands: BufWriter<File>,
+}
+
+impl Recorder {
+    fn create(rules: Ruleset, server: &str, chips: Money) -> io::Result<Recorder> {
+        let rounds = BufWriter::new(File::create("rounds.bin")?);
+        Ok(Recorder {
+            audit: AuditLog::open("audit.jsonl")?,
+            rounds: binlog::Writer::new(rounds, rules, server, chips)?,
+            hands: BufWriter::new(File::create("hands.txt")?),
+        })
+    }
+
+    /// Record a round that has been played out, before it is finished
+    fn round(
+        &mut self,
+        game: &Game,
+        server: &str,
+        client: &str,
+        history: HandHistory,
+    ) -> io::Result<()> {
+        self.audit
+            .append(game, server, client)
+            .map_err(|e| match e {
+                AppendError::Io(e) => e,
+                AppendError::Game(e) => {
+                    io::Error::other(format!("hand {} not logged: {:?}", history.number, e))
+                }
+            })?;
+        writeln!(self.hands, "{}", history)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.rounds.flush()?;
+        self.hands.flush()
+    }
+}
+
 fn simulate(
     rules: Ruleset,
-    bankroll: usize,
-    bet: usize,
+    bankroll: Money,
+    bet: Money,
     occurrences: usize,
     display: bool,
-) -> String {
-    let mut player = Player::new(bankroll);
+) -> io::Result<String> {
     let mut wins = 0;
     let mut bj = 0;
     let mut total = 0;
 
-    let mut server_rng =
-        blackjack::pcg::PCG32::new(thread_rng().next_u64(), thread_rng().next_u64());
-    let server_seed = server_rng.to_seed();
+    let server_seed = Seed::generate(Backend::ChaCha20);
+    let player_seed = Seed::from(PCG32Seed::new(44, 54));
 
-    let mut player_rng = blackjack::pcg::PCG32::new(44, 54);
-    let player_seed = player_rng.to_seed();
+    let mut session = Session::new(
+        rules,
+        Player::new(bankroll),
+        server_seed.rng(),
+        player_seed.rng(),
+        server_seed.commit(),
+    );
+    let mut recorder = if display {
+        Some(Recorder::create(rules, &session.log().server, bankroll)?)
+    } else {
+        None
+    };
 
-    for _ in 0..occurrences {
-        let mut game = Game::init(rules, player, &mut server_rng);
-        game.player_shuffle(&mut player_rng);
-        if display {
-            dbg!(game.sha256());
-        }
+    for number in 1..=occurrences {
+        // Enough to reproduce the round with `Game::replay` if it fails
+        let (server_rng, player_rng) = session.rngs();
+        let mut record = RoundRecord {
+            rules,
+            server: server_rng.seed(),
+            client: player_rng.seed(),
+            chips: session.chips(),
+            carry: session.carry(),
+            bet,
+            actions: Vec::new(),
+            round: number as u64 - 1,
+        };
 
-        let mut view = game.bet(bet).unwrap();
+        let mut view = session.start(bet).unwrap();
 
         while let State::Player(idx) = view.state {
             if display {
                 display_view(&view);
             }
             let action = basic_strategy(&view, idx);
-            view = match game.player(action) {
+            record.actions.push(action);
+            view = match session.player(action) {
                 Ok(view) => view,
                 Err(e) => panic!(
-                    "Player error encountered!: {:?} {:?} {} {:#?}",
-                    e, action, idx, view
+                    "Player error encountered!: {:?} {:?} {}\nreplay: {}",
+                    e,
+                    action,
+                    idx,
+                    serde_json::to_string(&record).unwrap()
                 ),
             };
         }
 
-        view = match game.dealer() {
-            Ok(view) => view,
-            Err(e) => panic!("Dealer error encountered!: {:?} {:#?}", e, view),
-        };
+        if view.state == State::Dealer {
+            view = match session.dealer() {
+                Ok(view) => view,
+                Err(e) => panic!("Dealer error encountered!: {:?} {:#?}", e, view),
+            };
+        }
 
-        println!("{}", serde_json::to_string_pretty(&view).unwrap());
         total += view.scores.len();
-        for score in view.scores {
+        for score in &view.scores {
             match score {
                 Outcome::Win(_) => wins += 1,
                 Outcome::Blackjack(_) => bj += 1,
@@ -159,32 +230,67 @@ fn simulate(
             }
         }
 
-        player = game.finish().unwrap();
+        if let Some(recorder) = &mut recorder {
+            let game = session.game().expect("round is in progress");
+            let history = HandHistory::new(number, game)
+                .expect("round has been played out")
+                .seeds(record.server, record.client);
+            let server = &session.log().server;
+            recorder.round(game, server, &player_seed.commit(), history)?;
+        }
+        session.finish().unwrap();
+        assert!(
+            session.reconciles(),
+            "ledger disagrees with the player's chips"
+        );
+        if let Some(recorder) = &mut recorder {
+            let round = session.log().rounds.last().expect("round was logged");
+            recorder.rounds.write(round)?;
+        }
+    }
+
+    if let Some(recorder) = &mut recorder {
+        // Reveal the seeds so that the session can be checked with
+        // `blackjack-verify --server .. --client .. rounds.bin`
+        recorder.flush()?;
+        let log = session.log();
+        let (s, c) = (&server_seed, &player_seed);
+        println!("server seed {}\nclient seed {}", s, c);
+        match verify::verify(log, s, c) {
+            Ok(n) => println!("verified {} rounds", n),
+            Err(m) => println!("verification failed: {}", m),
+        }
 
-        // Now make a mock deck to check everything was fair
-        let mut deck = Deck::new(rules.decks);
-        server_rng = blackjack::pcg::PCG32::from_seed(server_seed);
-        player_rng = blackjack::pcg::PCG32::from_seed(player_seed);
-        deck.shuffle(&mut server_rng);
-        deck.shuffle(&mut player_rng);
-        dbg!(deck.sha256());
+        let entries = chain::read(BufReader::new(File::open("audit.jsonl")?))?;
+        let head = recorder.audit.head();
+        match chain::verify(&entries, Some(head)) {
+            Ok(()) => println!("audit log intact, head {}", head),
+            Err(e) => println!("audit log broken: {}", e),
+        }
     }
 
-    format!(
+    let chips = session.chips();
+    Ok(format!(
         "wins {:8}\tbj {:8}\ttotal {:8}\tcash {:8}\tP/L per wager {}",
         wins,
         bj,
         total,
-        player.chips,
-        (player.chips as f64 - bankroll as f64) / total as f64,
-    )
+        chips,
+        (chips.as_minor() as f64 - bankroll.as_minor() as f64) / Money::SCALE as f64 / total as f64,
+    ))
 }
 
 fn main() {
     let rules = Ruleset::default().decks(6);
     println!("{}", serde_json::to_string_pretty(&rules).unwrap());
     // println!("{}", simulate(10000, 10, 100));
-    println!("{}", simulate(rules, 1_000_000, 1, 1, true));
+    match simulate(rules, Money::chips(1_000_000), Money::chips(1), 1, true) {
+        Ok(summary) => println!("{}", summary),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
     // println!(
     //     "{}",
     //     simulate(rules.decks(1).stand(false), 1_000_000, 1, 50_000)
@@ -0,0 +1,14 @@
+//! Print the JSON Schema of the wire format. `cargo test` checks the copy
+//! stored in `schema/`, so that a change to the encoding can't go unnoticed
+//!
+//! usage: blackjack-schema
+use blackjack::wire;
+use std::process::exit;
+
+fn main() {
+    if std::env::args().nth(1).is_some() {
+        eprintln!("usage: blackjack-schema");
+        exit(2);
+    }
+    println!("{}", serde_json::to_string_pretty(&wire::schema()).unwrap());
+}
@@ -0,0 +1,160 @@
+//! Verify a session of recorded rounds once the seeds have been revealed,
+//! or the signature on a single round receipt
+//!
+//! The log may be JSON, or in the binary format of `binlog`, which is read
+//! and verified a round at a time
+//!
+//! usage: blackjack-verify --server SEED --client SEED [--rules RULES.json] LOG.json
+//!        blackjack-verify --key PUBLIC_KEY [--rules RULES.json] RECEIPT.json
+//!        blackjack-verify --replay RECORD.json
+use blackjack::binlog;
+use blackjack::receipt::{self, RoundReceipt};
+use blackjack::replay::RoundRecord;
+use blackjack::rng::Seed;
+use blackjack::verify::{self, Log, Verifier};
+use blackjack::{Game, Ruleset};
+use ed25519_dalek::PublicKey;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process::exit;
+
+const USAGE: &str =
+    "usage: blackjack-verify --server SEED --client SEED [--rules RULES.json] LOG.json
+       blackjack-verify --key PUBLIC_KEY [--rules RULES.json] RECEIPT.json
+       blackjack-verify --replay RECORD.json";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    exit(2)
+}
+
+fn parse_seed(s: &str) -> Seed {
+    s.parse()
+        .unwrap_or_else(|e| fail(&format!("{}: {}\n{}", s, e, USAGE)))
+}
+
+fn parse_key(s: &str) -> PublicKey {
+    hex::decode(s)
+        .ok()
+        .and_then(|bytes| PublicKey::from_bytes(&bytes).ok())
+        .unwrap_or_else(|| fail(&format!("{}: invalid public key\n{}", s, USAGE)))
+}
+
+fn check_rules(expected: Option<Ruleset>, found: Ruleset) {
+    // The player should only accept the rules they agreed to before play
+    if let Some(rules) = expected {
+        if rules != found {
+            println!("FAIL: dealt with different rules {:?}", found);
+            exit(1);
+        }
+    }
+}
+
+fn read(path: &str) -> String {
+    std::fs::read_to_string(path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)))
+}
+
+fn main() {
+    let mut server = None;
+    let mut client = None;
+    let mut key = None;
+    let mut replay = false;
+    let mut rules: Option<Ruleset> = None;
+    let mut path = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+        match arg.as_str() {
+            "--server" => server = Some(parse_seed(&value())),
+            "--client" => client = Some(parse_seed(&value())),
+            "--key" => key = Some(parse_key(&value())),
+            "--replay" => replay = true,
+            "--rules" => {
+                let path = value();
+                rules = Some(
+                    serde_json::from_str(&read(&path))
+                        .unwrap_or_else(|e| fail(&format!("{}: {}", path, e))),
+                )
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => fail(USAGE),
+        }
+    }
+
+    let path = path.unwrap_or_else(|| fail(USAGE));
+
+    if replay {
+        let record: RoundRecord = serde_json::from_str(&read(&path))
+            .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+        check_rules(rules, record.rules);
+        match Game::replay(&record) {
+            Ok(replay) => {
+                for view in &replay.views {
+                    println!("{}", serde_json::to_string(view).unwrap());
+                }
+                println!("OK: replayed {} actions", record.actions.len());
+            }
+            Err(d) => {
+                println!("FAIL: {}", d);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(key) = key {
+        let receipt: RoundReceipt = serde_json::from_str(&read(&path))
+            .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+        check_rules(rules, receipt.rules);
+        match receipt::verify(&receipt, &key) {
+            Ok(()) => println!("OK: receipt signed by {}", hex::encode(key.as_bytes())),
+            Err(e) => {
+                println!("FAIL: {}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    let (server, client) = match (server, client) {
+        (Some(s), Some(c)) => (s, c),
+        _ => fail(USAGE),
+    };
+
+    // A binary log is verified round by round as it is read, so it never
+    // has to fit in memory
+    let file = File::open(&path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+    let mut input = BufReader::new(file);
+    let binary = input
+        .fill_buf()
+        .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)))
+        .starts_with(binlog::MAGIC);
+    let result = if binary {
+        let reader =
+            binlog::Reader::new(input).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+        check_rules(rules, reader.rules());
+        let commitment = reader.server().to_string();
+        let mut verifier = Verifier::new(reader.rules(), &server, &client, reader.chips());
+        verify::check_seed(&commitment, &server).and_then(|()| {
+            for round in reader {
+                let round = round.unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+                verifier.verify(&round)?;
+            }
+            Ok(verifier.verified())
+        })
+    } else {
+        let log: Log =
+            serde_json::from_reader(input).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+        check_rules(rules, log.rules);
+        verify::verify(&log, &server, &client)
+    };
+
+    match result {
+        Ok(n) => println!("OK: verified {} rounds", n),
+        Err(m) => {
+            println!("FAIL: {}", m);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,545 @@
+//! Compact binary encoding of a session's `Log`
+//!
+//! A JSON log takes over a kilobyte a round, too much to keep for a
+//! simulation of millions of rounds. The binary form holds the same rounds in
+//! a few dozen bytes each: integers and amounts are LEB128 varints, each card
+//! is its one-byte `Card::code`, each action a single byte, and the deck
+//! commitment its 32 raw bytes. The rules, server commitment and starting
+//! chips are written once, in a header after the magic bytes.
+//!
+//! `Writer` appends rounds one at a time as they are played, and `Reader`
+//! yields them back in order, so neither needs the whole log in memory.
+//! `write` and `read` convert a whole `Log`, and the result is exactly the
+//! log that was written, so it can still be checked round by round with
+//! `verify::verify` once the seeds are revealed
+use super::game::Last;
+use super::verify::{Log, Round};
+use super::wallet::AccountId;
+use super::*;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+/// Start of every binary log, ending in the format version
+pub const MAGIC: &[u8; 6] = b"BJLOG\x01";
+
+/// Longest rules header accepted, far more than any `Ruleset` takes as JSON
+const MAX_RULES_LEN: usize = 4096;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn put_varint<W: Write>(out: &mut W, mut n: u64) -> io::Result<()> {
+    let mut buf = [0u8; 10];
+    let mut len = 0;
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    out.write_all(&buf[..len])
+}
+
+fn put_len<W: Write>(out: &mut W, len: usize) -> io::Result<()> {
+    put_varint(out, len as u64)
+}
+
+fn put_money<W: Write>(out: &mut W, amount: Money) -> io::Result<()> {
+    put_varint(out, amount.as_minor())
+}
+
+/// A Sha256 hex digest as its 32 bytes
+fn put_hash<W: Write>(out: &mut W, hash: &str) -> io::Result<()> {
+    match hex::decode(hash) {
+        Ok(bytes) if bytes.len() == 32 => out.write_all(&bytes),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "commitment is not a Sha256 digest",
+        )),
+    }
+}
+
+fn put_cards<W: Write>(out: &mut W, hand: &Hand) -> io::Result<()> {
+    put_len(out, hand.cards.len())?;
+    let codes: Vec<u8> = hand.cards.iter().map(|card| card.code()).collect();
+    out.write_all(&codes)
+}
+
+fn action_code(action: Action) -> u8 {
+    match action {
+        Action::Hit => 0,
+        Action::Stand => 1,
+        Action::Split => 2,
+        Action::Double => 3,
+        Action::Surrender => 4,
+    }
+}
+
+fn put_round<W: Write>(out: &mut W, rules: &Ruleset, round: &Round) -> io::Result<()> {
+    let view = &round.view;
+    if view.rules != *rules {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "round was played with different rules",
+        ));
+    }
+    put_hash(out, &round.deck)?;
+    put_money(out, round.bet)?;
+    put_len(out, round.actions.len())?;
+    let actions: Vec<u8> = round.actions.iter().map(|a| action_code(*a)).collect();
+    out.write_all(&actions)?;
+
+    put_money(out, view.bet)?;
+    match view.state {
+        State::Ready => out.write_all(&[0])?,
+        State::Player(idx) => {
+            out.write_all(&[1])?;
+            put_len(out, idx)?;
+        }
+        State::Dealer => out.write_all(&[2])?,
+        State::Error => out.write_all(&[3])?,
+        State::Final => out.write_all(&[4])?,
+    }
+    // Codes are below 64, leaving the top bits for the seat
+    out.write_all(&[match view.last {
+        Last::Dealer(card) => card.code(),
+        Last::Player(card) => card.code() | 0x40,
+    }])?;
+    put_cards(out, &view.dealer)?;
+    put_len(out, view.player.hands.len())?;
+    for hand in &view.player.hands {
+        put_cards(out, hand)?;
+    }
+    put_len(out, view.scores.len())?;
+    for score in &view.scores {
+        let (code, amount) = match *score {
+            Outcome::Lose(amount) => (0, amount),
+            Outcome::Win(amount) => (1, amount),
+            Outcome::Blackjack(amount) => (2, amount),
+            Outcome::Push(amount) => (3, amount),
+            Outcome::Surrender(amount) => (4, amount),
+        };
+        out.write_all(&[code])?;
+        put_money(out, amount)?;
+    }
+    put_money(out, view.player.chips)?;
+    put_money(out, view.player.carry)?;
+    match (view.player.account, view.player.lock) {
+        (None, None) => out.write_all(&[0])?,
+        (Some(AccountId(id)), None) => {
+            out.write_all(&[1])?;
+            put_varint(out, id)?;
+        }
+        (Some(AccountId(id)), Some(lock)) => {
+            out.write_all(&[2])?;
+            put_varint(out, id)?;
+            put_varint(out, lock)?;
+        }
+        (None, Some(_)) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "player holds a lock without an account",
+            ))
+        }
+    }
+    put_money(out, round.chips)
+}
+
+/// Streams rounds to `out` in the binary format
+pub struct Writer<W: Write> {
+    out: W,
+    rules: Ruleset,
+}
+
+impl<W: Write> Writer<W> {
+    /// Write the header for a log of rounds dealt under `rules`, from a
+    /// server seed with Sha256 `server`, to a player starting with `chips`
+    pub fn new(mut out: W, rules: Ruleset, server: &str, chips: Money) -> io::Result<Writer<W>> {
+        out.write_all(MAGIC)?;
+        let json = serde_json::to_vec(&rules)?;
+        put_len(&mut out, json.len())?;
+        out.write_all(&json)?;
+        put_hash(&mut out, server)?;
+        put_money(&mut out, chips)?;
+        Ok(Writer { out, rules })
+    }
+
+    /// Append a round. Every round must have been played under the rules in
+    /// the header
+    pub fn write(&mut self, round: &Round) -> io::Result<()> {
+        put_round(&mut self.out, &self.rules, round)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+fn get_byte<R: Read>(input: &mut R) -> io::Result<u8> {
+    let mut byte = [0u8];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn get_varint<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = get_byte(input)?;
+        let bits = (byte & 0x7f) as u64;
+        if bits << shift >> shift != bits {
+            return Err(invalid("varint overflows 64 bits"));
+        }
+        n |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(invalid("varint overflows 64 bits"))
+}
+
+fn get_len<R: Read>(input: &mut R) -> io::Result<usize> {
+    usize::try_from(get_varint(input)?).map_err(|_| invalid("length too large"))
+}
+
+fn get_money<R: Read>(input: &mut R) -> io::Result<Money> {
+    Ok(Money::minor(get_varint(input)?))
+}
+
+fn get_hash<R: Read>(input: &mut R) -> io::Result<String> {
+    let mut bytes = [0u8; 32];
+    input.read_exact(&mut bytes)?;
+    Ok(hex::encode(bytes))
+}
+
+fn get_card<R: Read>(input: &mut R) -> io::Result<Card> {
+    Card::from_code(get_byte(input)?).ok_or_else(|| invalid("invalid card code"))
+}
+
+fn get_cards<R: Read>(input: &mut R) -> io::Result<Hand> {
+    let len = get_len(input)?;
+    let cards = (0..len)
+        .map(|_| get_card(input))
+        .collect::<io::Result<_>>()?;
+    Ok(Hand { cards })
+}
+
+fn get_action<R: Read>(input: &mut R) -> io::Result<Action> {
+    Ok(match get_byte(input)? {
+        0 => Action::Hit,
+        1 => Action::Stand,
+        2 => Action::Split,
+        3 => Action::Double,
+        4 => Action::Surrender,
+        _ => return Err(invalid("invalid action code")),
+    })
+}
+
+/// The rest of a round, after the first byte of its deck commitment
+fn get_round<R: Read>(input: &mut R, rules: Ruleset, first: u8) -> io::Result<Round> {
+    let mut deck = [0u8; 32];
+    deck[0] = first;
+    input.read_exact(&mut deck[1..])?;
+    let bet = get_money(input)?;
+    let len = get_len(input)?;
+    let actions = (0..len)
+        .map(|_| get_action(input))
+        .collect::<io::Result<_>>()?;
+
+    let view_bet = get_money(input)?;
+    let state = match get_byte(input)? {
+        0 => State::Ready,
+        1 => State::Player(get_len(input)?),
+        2 => State::Dealer,
+        3 => State::Error,
+        4 => State::Final,
+        _ => return Err(invalid("invalid state code")),
+    };
+    let last = get_byte(input)?;
+    let card = Card::from_code(last & 0x3f).ok_or_else(|| invalid("invalid card code"))?;
+    let last = match last & 0xc0 {
+        0 => Last::Dealer(card),
+        0x40 => Last::Player(card),
+        _ => return Err(invalid("invalid seat")),
+    };
+    let dealer = get_cards(input)?;
+    let len = get_len(input)?;
+    let hands = (0..len)
+        .map(|_| get_cards(input))
+        .collect::<io::Result<_>>()?;
+    let len = get_len(input)?;
+    let mut scores = Vec::new();
+    for _ in 0..len {
+        let code = get_byte(input)?;
+        let amount = get_money(input)?;
+        scores.push(match code {
+            0 => Outcome::Lose(amount),
+            1 => Outcome::Win(amount),
+            2 => Outcome::Blackjack(amount),
+            3 => Outcome::Push(amount),
+            4 => Outcome::Surrender(amount),
+            _ => return Err(invalid("invalid outcome code")),
+        });
+    }
+    let player_chips = get_money(input)?;
+    let carry = get_money(input)?;
+    let (account, lock) = match get_byte(input)? {
+        0 => (None, None),
+        1 => (Some(AccountId(get_varint(input)?)), None),
+        2 => (
+            Some(AccountId(get_varint(input)?)),
+            Some(get_varint(input)?),
+        ),
+        _ => return Err(invalid("invalid account flag")),
+    };
+    let chips = get_money(input)?;
+
+    Ok(Round {
+        deck: hex::encode(deck),
+        bet,
+        actions,
+        view: View {
+            bet: view_bet,
+            rules,
+            dealer,
+            player: Player {
+                hands,
+                chips: player_chips,
+                carry,
+                account,
+                lock,
+            },
+            state,
+            last,
+            scores,
+        },
+        chips,
+    })
+}
+
+/// Streams rounds back from a binary log. Wrap files in a `BufReader`, as
+/// rounds are read a byte at a time
+pub struct Reader<R: Read> {
+    input: R,
+    rules: Ruleset,
+    server: String,
+    chips: Money,
+}
+
+impl<R: Read> Reader<R> {
+    /// Read the header, leaving the rounds to be read by iterating
+    pub fn new(mut input: R) -> io::Result<Reader<R>> {
+        let mut magic = [0u8; 6];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a binary round log"));
+        }
+        let len = get_len(&mut input)?;
+        if len > MAX_RULES_LEN {
+            return Err(invalid("rules header too long"));
+        }
+        let mut json = vec![0u8; len];
+        input.read_exact(&mut json)?;
+        let rules = serde_json::from_slice(&json)?;
+        let server = get_hash(&mut input)?;
+        let chips = get_money(&mut input)?;
+        Ok(Reader {
+            input,
+            rules,
+            server,
+            chips,
+        })
+    }
+
+    pub fn rules(&self) -> Ruleset {
+        self.rules
+    }
+
+    /// Sha256 of the server's seed
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Player's chips before the first round
+    pub fn chips(&self) -> Money {
+        self.chips
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<Round>;
+
+    fn next(&mut self) -> Option<io::Result<Round>> {
+        // The log may only end between rounds
+        let mut first = [0u8];
+        loop {
+            match self.input.read(&mut first) {
+                Ok(0) => return None,
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(get_round(&mut self.input, self.rules, first[0]))
+    }
+}
+
+/// Write a whole log in the binary format
+pub fn write<W: Write>(out: W, log: &Log) -> io::Result<()> {
+    let mut writer = Writer::new(out, log.rules, &log.server, log.chips)?;
+    for round in &log.rounds {
+        writer.write(round)?;
+    }
+    writer.flush()
+}
+
+/// Read a whole binary log
+pub fn read<R: Read>(input: R) -> io::Result<Log> {
+    let mut reader = Reader::new(input)?;
+    let rounds = reader.by_ref().collect::<io::Result<_>>()?;
+    Ok(Log {
+        rules: reader.rules,
+        server: reader.server,
+        chips: reader.chips,
+        rounds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::{Backend, Seed};
+    use crate::session::Session;
+
+    /// A session of `rounds` rounds, splitting and doubling where allowed
+    fn played(rounds: usize) -> Log {
+        let server = Seed::new(Backend::ChaCha20, &[5; 32]).unwrap();
+        let client = Seed::new(Backend::ChaCha20, &[6; 32]).unwrap();
+        let rules = Ruleset::default().decks(2).surrender(true);
+        let player = Player::new(Money::chips(1000));
+        let mut session = Session::new(rules, player, server.rng(), client.rng(), server.commit());
+        for _ in 0..rounds {
+            let mut view = session.start(Money::chips(5)).unwrap();
+            while let State::Player(idx) = view.state {
+                let score = view.player.hands[idx].score();
+                let action = if view.valid_action(Action::Split).is_ok() {
+                    Action::Split
+                } else if score == 11 && view.valid_action(Action::Double).is_ok() {
+                    Action::Double
+                } else if score < 17 {
+                    Action::Hit
+                } else {
+                    Action::Stand
+                };
+                view = session.player(action).unwrap();
+            }
+            if view.state == State::Dealer {
+                session.dealer().unwrap();
+            }
+            session.finish().unwrap();
+        }
+        session.log().clone()
+    }
+
+    fn encode(log: &Log) -> Vec<u8> {
+        let mut out = Vec::new();
+        write(&mut out, log).unwrap();
+        out
+    }
+
+    /// Offset of the first round, just past the header
+    fn header_len(log: &Log) -> usize {
+        let writer = Writer::new(Vec::new(), log.rules, &log.server, log.chips);
+        writer.unwrap().into_inner().len()
+    }
+
+    #[test]
+    fn round_trips_a_log() {
+        let log = played(50);
+        let actions: Vec<_> = log.rounds.iter().flat_map(|r| &r.actions).collect();
+        assert!(actions.contains(&&Action::Split));
+        assert!(actions.contains(&&Action::Double));
+
+        // Written a round at a time, and read back as a stream
+        let mut writer = Writer::new(Vec::new(), log.rules, &log.server, log.chips).unwrap();
+        for round in &log.rounds {
+            writer.write(round).unwrap();
+        }
+        let bytes = writer.into_inner();
+        assert_eq!(bytes, encode(&log));
+        let reader = Reader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.rules(), log.rules);
+        assert_eq!(reader.server(), log.server);
+        assert_eq!(reader.chips(), log.chips);
+        let rounds = reader.collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(rounds, log.rounds);
+        assert_eq!(read(&bytes[..]).unwrap(), log);
+    }
+
+    #[test]
+    fn converts_to_and_from_json() {
+        let log = played(20);
+        let json = serde_json::to_string(&read(&encode(&log)[..]).unwrap()).unwrap();
+        let from_json: Log = serde_json::from_str(&json).unwrap();
+        assert_eq!(from_json, log);
+        assert_eq!(encode(&from_json), encode(&log));
+    }
+
+    #[test]
+    fn rejects_truncated_rounds() {
+        let log = played(3);
+        let bytes = encode(&log);
+        // Ending between rounds is fine, anywhere else is not
+        assert_eq!(read(&bytes[..header_len(&log)]).unwrap().rounds, vec![]);
+        for cut in 1..20 {
+            let err = read(&bytes[..bytes.len() - cut]).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "cut {}", cut);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_codes() {
+        let mut log = played(1);
+        // Only encoded, so the round needn't replay
+        let card = log.rounds[0].view.dealer.cards[0];
+        log.rounds[0].actions = vec![Action::Stand];
+        log.rounds[0].view.last = Last::Dealer(card);
+        let bytes = encode(&log);
+
+        // The deck commitment, the bet of 5 chips in two bytes and then one
+        // action, followed by the view's bet and state
+        let action = header_len(&log) + 32 + 2 + 1;
+        let last = action + 1 + 2 + 1;
+        assert_eq!(bytes[action], action_code(Action::Stand));
+        assert_eq!(bytes[last], card.code());
+
+        let mut bad = bytes.clone();
+        bad[action] = 9;
+        let err = read(&bad[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "invalid action code");
+
+        let mut bad = bytes;
+        bad[last] = 63;
+        let err = read(&bad[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "invalid card code");
+    }
+
+    #[test]
+    fn rejects_long_rules_header() {
+        let mut log = MAGIC.to_vec();
+        put_varint(&mut log, u64::MAX >> 1).unwrap();
+        let err = Reader::new(&log[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
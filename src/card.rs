@@ -33,6 +33,12 @@ pub enum Rank {
     Ace,
 }
 
+pub const SUITS: [Suit; 4] = [Hearts, Spades, Clubs, Diamonds];
+
+pub const RANKS: [Rank; 13] = [
+    Two, Three, Four, Five, Six, Seven, Eight, Nine, Ten, Jack, Queen, King, Ace,
+];
+
 impl std::ops::Deref for Card {
     type Target = Rank;
     fn deref(&self) -> &Self::Target {
@@ -41,6 +47,18 @@ impl std::ops::Deref for Card {
 }
 
 impl Card {
+    /// Compact code in the range 0..52, in the same order as a fresh `Deck`
+    pub fn code(self) -> u8 {
+        self.suit as u8 * 13 + self.rank as u8
+    }
+
+    pub fn from_code(code: u8) -> Option<Card> {
+        Some(Card {
+            suit: *SUITS.get(code as usize / 13)?,
+            rank: RANKS[code as usize % 13],
+        })
+    }
+
     pub fn notation(self) -> String {
         format!(
             "{}{}",
@@ -67,14 +85,43 @@ impl Card {
             }
         )
     }
+
+    /// Parse a card written by `notation`, such as `10h` or `As`
+    pub fn from_notation(s: &str) -> Option<Card> {
+        if s.len() < 2 || !s.is_char_boundary(s.len() - 1) {
+            return None;
+        }
+        let (rank, suit) = s.split_at(s.len() - 1);
+        let rank = match rank {
+            "2" => Two,
+            "3" => Three,
+            "4" => Four,
+            "5" => Five,
+            "6" => Six,
+            "7" => Seven,
+            "8" => Eight,
+            "9" => Nine,
+            "10" => Ten,
+            "J" => Jack,
+            "Q" => Queen,
+            "K" => King,
+            "A" => Ace,
+            _ => return None,
+        };
+        let suit = match suit {
+            "h" => Hearts,
+            "c" => Clubs,
+            "s" => Spades,
+            "d" => Diamonds,
+            _ => return None,
+        };
+        Some(Card { rank, suit })
+    }
 }
 
 impl Rank {
     pub fn is_face(self) -> bool {
-        match self {
-            Jack | Queen | King | Ace => true,
-            _ => false,
-        }
+        matches!(self, Jack | Queen | King | Ace)
     }
 
     pub fn value(self) -> u8 {
@@ -96,10 +143,7 @@ impl Rank {
     }
 
     pub fn soft(self) -> bool {
-        match self {
-            Ace => true,
-            _ => false,
-        }
+        matches!(self, Ace)
     }
 }
 
@@ -0,0 +1,391 @@
+//! Tamper-evident audit log of every round played
+//!
+//! Each `Entry` records a finished round along with the Sha256 of the entry
+//! before it, so that deleting, reordering or editing any entry breaks the
+//! chain from that point on. Entries are written as one JSON object per
+//! line, and synced to disk as soon as they are appended. `AuditLog::open`
+//! continues an existing log, so restarting the server never starts a new
+//! chain over the old one.
+//!
+//! The chain alone can't detect entries dropped from the end, or a history
+//! rewritten from scratch with fresh hashes. The operator should publish the
+//! `head` hash regularly, and the log checked against the latest one
+use super::game::Error;
+use super::receipt::Settlement;
+use super::wallet::SyncWrite;
+use super::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// `prev` hash of the first entry
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Entry {
+    /// Position in the log, starting from 0
+    pub index: u64,
+    /// Hash of the previous entry, or `GENESIS`
+    pub prev: String,
+    /// Sha256 commitment to the server's seed
+    pub server: String,
+    /// Sha256 commitment to the client's seed
+    pub client: String,
+    /// Sha256 of the shoe when the bet was placed
+    pub deck: String,
+    /// Final view of the table, with the dealer's hole card revealed
+    pub view: View,
+    pub settlement: Settlement,
+    /// Sha256 over every other field
+    pub hash: String,
+}
+
+/// Fields covered by the hash, borrowed from an `Entry`
+#[derive(Serialize)]
+struct Body<'a> {
+    index: u64,
+    prev: &'a str,
+    server: &'a str,
+    client: &'a str,
+    deck: &'a str,
+    view: &'a View,
+    settlement: &'a Settlement,
+}
+
+impl Entry {
+    /// Sha256 of the entry's contents, excluding its own `hash`
+    pub fn sha256(&self) -> String {
+        let body = serde_json::to_vec(&Body {
+            index: self.index,
+            prev: &self.prev,
+            server: &self.server,
+            client: &self.client,
+            deck: &self.deck,
+            view: &self.view,
+            settlement: &self.settlement,
+        })
+        .expect("entry body is always serializable");
+        let mut hasher = Sha256::default();
+        hasher.input(body);
+        format!("{:0x}", hasher.result())
+    }
+}
+
+#[derive(Debug)]
+pub enum AppendError {
+    /// The game has not reached the `Final` state
+    Game(Error),
+    Io(io::Error),
+}
+
+impl From<io::Error> for AppendError {
+    fn from(e: io::Error) -> AppendError {
+        AppendError::Io(e)
+    }
+}
+
+/// Writes entries to the end of the log, chaining each to the last
+pub struct AuditLog<W: SyncWrite> {
+    out: W,
+    len: u64,
+    head: String,
+}
+
+impl AuditLog<File> {
+    /// Open the log at `path` for appending, creating it if it doesn't exist,
+    /// and continue the chain from its last entry. Fails if the entries
+    /// already there don't form an unbroken chain
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<AuditLog<File>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let entries = read(BufReader::new(&file))?;
+        verify(&entries, None).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(match entries.last() {
+            Some(last) => AuditLog::resume(file, last),
+            None => AuditLog::new(file),
+        })
+    }
+}
+
+impl<W: SyncWrite> AuditLog<W> {
+    /// Start a new, empty log
+    pub fn new(out: W) -> AuditLog<W> {
+        AuditLog {
+            out,
+            len: 0,
+            head: GENESIS.into(),
+        }
+    }
+
+    /// Continue an existing log, whose last entry is `last`
+    pub fn resume(out: W, last: &Entry) -> AuditLog<W> {
+        AuditLog {
+            out,
+            len: last.index + 1,
+            head: last.hash.clone(),
+        }
+    }
+
+    /// Number of entries in the log
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Hash of the last entry, or `GENESIS` if the log is empty
+    pub fn head(&self) -> &str {
+        &self.head
+    }
+
+    /// Append a finished round. `server` and `client` are the seed
+    /// commitments that were published before play began
+    pub fn append(
+        &mut self,
+        game: &Game,
+        server: &str,
+        client: &str,
+    ) -> Result<Entry, AppendError> {
+        let view = game.view();
+        if view.state != State::Final {
+            return Err(AppendError::Game(Error::InvalidAction));
+        }
+        let mut entry = Entry {
+            index: self.len,
+            prev: self.head.clone(),
+            server: server.into(),
+            client: client.into(),
+            deck: game.commitment().into(),
+            view,
+            settlement: Settlement::new(game),
+            hash: String::new(),
+        };
+        entry.hash = entry.sha256();
+
+        serde_json::to_writer(&mut self.out, &entry).map_err(io::Error::from)?;
+        self.out.write_all(b"\n")?;
+        self.out.sync()?;
+
+        self.len += 1;
+        self.head = entry.hash.clone();
+        Ok(entry)
+    }
+}
+
+/// Read every entry from a log written by `AuditLog`
+pub fn read<R: BufRead>(reader: R) -> serde_json::Result<Vec<Entry>> {
+    serde_json::Deserializer::from_reader(reader)
+        .into_iter()
+        .collect()
+}
+
+/// The first break found in the chain
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChainError {
+    /// The entry's contents don't match its hash
+    Edited { index: u64 },
+    /// Entries are missing before `found`
+    Deleted { expected: u64, found: u64 },
+    /// An entry appears after one that should follow it
+    Reordered { expected: u64, found: u64 },
+    /// The entry doesn't point at the hash of the one before it
+    Broken { index: u64 },
+    /// The last entry doesn't match the published head
+    Head { expected: String, found: String },
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChainError::Edited { index } => write!(f, "entry {} has been edited", index),
+            ChainError::Deleted { expected, found } => {
+                write!(f, "entries {} to {} are missing", expected, found - 1)
+            }
+            ChainError::Reordered { expected, found } => {
+                write!(f, "expected entry {}, found entry {}", expected, found)
+            }
+            ChainError::Broken { index } => {
+                write!(f, "entry {} does not follow the entry before it", index)
+            }
+            ChainError::Head { expected, found } => {
+                write!(f, "log ends at {}, but {} was published", found, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChainError {}
+
+/// Check that `entries` form an unbroken chain from the first round. If the
+/// operator published a `head` hash, the log must also end there
+pub fn verify(entries: &[Entry], head: Option<&str>) -> Result<(), ChainError> {
+    let mut prev = GENESIS;
+    for (expected, entry) in entries.iter().enumerate() {
+        let expected = expected as u64;
+        if entry.hash != entry.sha256() {
+            return Err(ChainError::Edited { index: entry.index });
+        }
+        if entry.index != expected {
+            // A swapped entry looks like a gap until the missing one turns up
+            let later = entries[expected as usize..]
+                .iter()
+                .any(|e| e.index == expected);
+            if entry.index > expected && !later {
+                return Err(ChainError::Deleted {
+                    expected,
+                    found: entry.index,
+                });
+            }
+            return Err(ChainError::Reordered {
+                expected,
+                found: entry.index,
+            });
+        }
+        if entry.prev != prev {
+            return Err(ChainError::Broken { index: entry.index });
+        }
+        prev = &entry.hash;
+    }
+    match head {
+        Some(head) if head != prev => Err(ChainError::Head {
+            expected: head.into(),
+            found: prev.into(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A finished round, won by the player's 19 against the dealer's 17
+    fn finished() -> Game {
+        let shoe = Shoe::Deck(Deck::stacked("10h 2c 9s 7d 8c"));
+        let mut game = Game::with_shoe(Ruleset::default(), Player::new(Money::chips(10)), shoe);
+        game.bet(Money::chips(2)).unwrap();
+        game.player(Action::Stand).unwrap();
+        game.dealer().unwrap();
+        game
+    }
+
+    fn entries(n: usize) -> Vec<Entry> {
+        let mut log = AuditLog::new(Vec::new());
+        let game = finished();
+        for i in 0..n {
+            log.append(&game, &format!("server {}", i), "client")
+                .unwrap();
+        }
+        read(&log.out[..]).unwrap()
+    }
+
+    #[test]
+    fn intact_chain() {
+        let entries = entries(4);
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].prev, GENESIS);
+        assert_eq!(verify(&entries, None), Ok(()));
+        assert_eq!(verify(&entries, Some(&entries[3].hash)), Ok(()));
+        // Dropping entries from the end is only caught by the published head
+        assert_eq!(
+            verify(&entries[..3], Some(&entries[3].hash)),
+            Err(ChainError::Head {
+                expected: entries[3].hash.clone(),
+                found: entries[2].hash.clone(),
+            })
+        );
+
+        let mut unfinished = finished();
+        unfinished.state = State::Dealer;
+        assert!(matches!(
+            AuditLog::new(Vec::new()).append(&unfinished, "server", "client"),
+            Err(AppendError::Game(Error::InvalidAction))
+        ));
+    }
+
+    #[test]
+    fn deleted_entry() {
+        let mut entries = entries(4);
+        entries.remove(1);
+        assert_eq!(
+            verify(&entries, None),
+            Err(ChainError::Deleted {
+                expected: 1,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    fn reordered_entries() {
+        let mut entries = entries(4);
+        entries.swap(1, 2);
+        assert_eq!(
+            verify(&entries, None),
+            Err(ChainError::Reordered {
+                expected: 1,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    fn edited_entry() {
+        let mut entries = entries(4);
+        entries[2].settlement.paid = Money::chips(40);
+        assert_eq!(verify(&entries, None), Err(ChainError::Edited { index: 2 }));
+
+        // Rehashing the edit only moves the break to the next entry
+        entries[2].hash = entries[2].sha256();
+        assert_eq!(verify(&entries, None), Err(ChainError::Broken { index: 3 }));
+    }
+
+    #[test]
+    fn resumes_the_chain() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let game = finished();
+
+        let mut log = AuditLog::open(&path).unwrap();
+        assert!(log.is_empty());
+        log.append(&game, "server", "client").unwrap();
+        log.append(&game, "server", "client").unwrap();
+        let head = log.head().to_string();
+        drop(log);
+
+        // Reopening continues after the last entry rather than starting over
+        let mut log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.head(), head);
+        let entry = log.append(&game, "server", "client").unwrap();
+        assert_eq!(entry.index, 2);
+        assert_eq!(entry.prev, head);
+        drop(log);
+
+        let entries = read(BufReader::new(File::open(&path).unwrap())).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(verify(&entries, Some(&entry.hash)), Ok(()));
+
+        // A log that is already broken isn't extended
+        let mut edited = entries;
+        edited[0].deck = "edited".into();
+        let mut text = String::new();
+        for entry in &edited {
+            text += &serde_json::to_string(entry).unwrap();
+            text += "\n";
+        }
+        std::fs::write(&path, text).unwrap();
+        let err = AuditLog::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
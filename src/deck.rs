@@ -1,4 +1,7 @@
 use super::card::{Card, Rank::*, Suit::*};
+use super::infinite::InfiniteDeck;
+use super::mental::{Client, MentalDeck, Phase};
+use num_bigint::BigUint;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
@@ -32,20 +35,26 @@ impl Deck {
 
     /// Fisher-Yates shuffling algorithim
     pub fn shuffle<R: Rng>(&mut self, rng: &mut R) {
-        let n = self.cards.len();
-        for i in (0..n).rev() {
-            self.cards.swap(i, rng.gen_range(0, n));
-        }
+        shuffle(&mut self.cards, rng)
     }
 
     pub fn count(&self) -> usize {
         self.cards.len()
     }
 
+    pub fn cards(&self) -> &[Card] {
+        &self.cards
+    }
+
     pub fn draw(&mut self) -> Option<Card> {
         self.cards.pop()
     }
 
+    /// Next card to be drawn, without removing it
+    pub fn peek(&self) -> Option<Card> {
+        self.cards.last().copied()
+    }
+
     pub fn notation(&self) -> String {
         self.cards
             .iter()
@@ -61,3 +70,147 @@ impl Deck {
         format!("{:0x}", hasher.result())
     }
 }
+
+#[cfg(test)]
+impl Deck {
+    /// A deck that deals `cards`, written as in `notation`, in the order given
+    pub(crate) fn stacked(cards: &str) -> Deck {
+        let mut cards: Vec<Card> = cards
+            .split_whitespace()
+            .map(|card| Card::from_notation(card).expect("valid card notation"))
+            .collect();
+        cards.reverse();
+        Deck { cards }
+    }
+}
+
+/// Fisher-Yates shuffling algorithim, shared by every card source so that
+/// the same RNG always produces the same permutation
+pub(crate) fn shuffle<T, R: Rng>(cards: &mut [T], rng: &mut R) {
+    for i in (1..cards.len()).rev() {
+        cards.swap(i, rng.gen_range(0, i + 1));
+    }
+}
+
+/// The source of cards for a `Game`
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Shoe {
+    /// A physical shoe, shuffled up front by the server and player
+    Deck(Deck),
+    /// A shoe jointly shuffled and encrypted by both parties, so that neither
+    /// knows the order of the cards. See the `mental` module
+    Mental(MentalDeck),
+    /// An infinite deck, where every card is derived from a hash. See the
+    /// `infinite` module
+    Infinite(InfiniteDeck),
+}
+
+impl Shoe {
+    pub fn draw(&mut self) -> Option<Card> {
+        match self {
+            Shoe::Deck(deck) => deck.draw(),
+            Shoe::Mental(deck) => deck.draw(),
+            Shoe::Infinite(deck) => deck.draw(),
+        }
+    }
+
+    /// Next card to be drawn, without removing it from the shoe
+    pub fn peek(&self) -> Option<Card> {
+        match self {
+            Shoe::Deck(deck) => deck.peek(),
+            Shoe::Mental(deck) => deck.peek(),
+            Shoe::Infinite(deck) => Some(deck.card_at(deck.cursor())),
+        }
+    }
+
+    /// Cards remaining in the shoe, or `usize::MAX` for an infinite deck
+    pub fn count(&self) -> usize {
+        match self {
+            Shoe::Deck(deck) => deck.count(),
+            Shoe::Mental(deck) => deck.count(),
+            Shoe::Infinite(_) => usize::MAX,
+        }
+    }
+
+    /// Whether cards can be dealt. A `MentalDeck` can't be until the player
+    /// has shuffled it
+    pub fn ready(&self) -> bool {
+        match self {
+            Shoe::Mental(deck) => deck.phase() == Phase::Shuffled,
+            Shoe::Deck(_) | Shoe::Infinite(_) => true,
+        }
+    }
+
+    /// Player's shuffle. For a `MentalDeck` this is the client's half of
+    /// the protocol, so it needs the `Client`, and for an `InfiniteDeck` it
+    /// picks the client seed. Returns false if a `MentalDeck` has no client
+    pub fn shuffle<R: Rng>(&mut self, rng: &mut R, client: Option<&mut dyn Client>) -> bool {
+        match (self, client) {
+            (Shoe::Deck(deck), _) => deck.shuffle(rng),
+            (Shoe::Mental(deck), Some(client)) => deck.client_shuffle(client, rng),
+            (Shoe::Mental(_), None) => return false,
+            (Shoe::Infinite(deck), _) => deck.shuffle(rng),
+        }
+        true
+    }
+
+    /// The next card of a `MentalDeck`, still encrypted, if the client must
+    /// reveal it before it can be dealt
+    pub fn unrevealed(&self) -> Option<&BigUint> {
+        match self {
+            Shoe::Mental(deck) => deck.unrevealed(),
+            Shoe::Deck(_) | Shoe::Infinite(_) => None,
+        }
+    }
+
+    /// Commitment to the current order of the shoe
+    pub fn sha256(&self) -> String {
+        match self {
+            Shoe::Deck(deck) => deck.sha256(),
+            Shoe::Mental(deck) => deck.sha256(),
+            Shoe::Infinite(deck) => deck.sha256(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcg::PCG32;
+
+    #[test]
+    fn shuffle_is_a_permutation() {
+        let mut rng = PCG32::new(1, 2);
+        let mut deck = Deck::new(2);
+        deck.shuffle(&mut rng);
+        let mut shuffled = deck.cards().to_vec();
+        let mut sorted = Deck::new(2).cards().to_vec();
+        shuffled.sort_by_key(|card| card.code());
+        sorted.sort_by_key(|card| card.code());
+        assert_eq!(shuffled, sorted);
+    }
+
+    #[test]
+    fn shuffle_is_uniform() {
+        // Swapping with any position gives 27 equally likely outcomes for 6
+        // orders, so some would come up 4/27 of the time and others 5/27
+        let mut rng = PCG32::new(3, 4);
+        let orders = [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ];
+        let mut counts = [0u32; 6];
+        for _ in 0..60_000 {
+            let mut items = [0, 1, 2];
+            shuffle(&mut items, &mut rng);
+            counts[orders.iter().position(|o| *o == items).unwrap()] += 1;
+        }
+        for &count in &counts {
+            assert!((9_600..10_400).contains(&count), "{:?}", counts);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Incremental updates to a player's `View`
+//!
+//! Rather than a full `View` after every call, a client can be sent a
+//! `ViewDelta` with only what changed since its last update: the cards
+//! dealt, splits, outcomes, and the new state, bet and chips. Deltas are
+//! built from the game's events, and redact the dealer's hole card exactly
+//! as `Game::view` does, so applying every delta in turn to the view a
+//! client started from gives the same `View` as `Game::view`.
+//!
+//! A delta starts from an event `cursor`. After a rewind the events the
+//! client saw may no longer exist, so it should start over from a full view
+use super::event::{GameEvent, Seat};
+use super::game::{Last, Outcome};
+use super::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Change {
+    /// The bet was placed, and the player's hands cleared for the deal
+    Bet,
+    Card(Seat, Card),
+    /// The hand at this index was split, moving its second card to a new
+    /// hand at the end
+    Split(usize),
+    /// The dealer's hole card was turned over
+    Reveal(Card),
+    /// The dealer's hole card was hidden again, when a round that had
+    /// reached the dealer ended in `Error`
+    Hide,
+    Settled(Vec<Outcome>),
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ViewDelta {
+    /// Number of events covered, where the next delta starts from
+    pub cursor: usize,
+    pub changes: Vec<Change>,
+    pub state: State,
+    pub bet: Money,
+    pub chips: Money,
+    pub carry: Money,
+    pub last: Last,
+}
+
+/// Is the hole card shown in this state?
+fn revealed(state: State) -> bool {
+    matches!(state, State::Dealer | State::Final)
+}
+
+impl Game {
+    /// Everything that changed in the player's view since `cursor`, which is
+    /// the `cursor` of the last delta, or `Game::events().len()` when the
+    /// client took its full view. `None` if `cursor` is past the end
+    pub fn delta(&self, cursor: usize) -> Option<ViewDelta> {
+        let events = self.events.get(cursor..)?;
+        let mut state = self.events[..cursor]
+            .iter()
+            .rev()
+            .find_map(|event| match event {
+                GameEvent::StateChanged { state } => Some(*state),
+                GameEvent::Exhausted => Some(State::Error),
+                _ => None,
+            })
+            .unwrap_or(State::Ready);
+        let before = state;
+        // Only the first card dealt to the dealer is ever hidden
+        let mut hole = self.events[..cursor].iter().any(|event| {
+            matches!(
+                event,
+                GameEvent::CardDealt {
+                    to: Seat::Dealer,
+                    ..
+                }
+            )
+        });
+
+        let mut changes = Vec::new();
+        for event in events {
+            match event {
+                GameEvent::BetPlaced { .. } => changes.push(Change::Bet),
+                GameEvent::CardDealt {
+                    to: Seat::Dealer, ..
+                } if !hole => hole = true,
+                GameEvent::CardDealt { to, card } => changes.push(Change::Card(*to, *card)),
+                GameEvent::DealerDrew { card } => changes.push(Change::Card(Seat::Dealer, *card)),
+                GameEvent::ActionTaken {
+                    action: Action::Split,
+                } => {
+                    if let State::Player(idx) = state {
+                        changes.push(Change::Split(idx));
+                    }
+                }
+                GameEvent::StateChanged { state: next } => state = *next,
+                GameEvent::Exhausted => state = State::Error,
+                GameEvent::Settled { scores, .. } => changes.push(Change::Settled(scores.clone())),
+                GameEvent::Created { .. }
+                | GameEvent::Shuffled { .. }
+                | GameEvent::Revealed { .. }
+                | GameEvent::ActionTaken { .. } => {}
+            }
+        }
+
+        match (revealed(before), revealed(self.state)) {
+            (false, true) => changes.push(Change::Reveal(self.dealer.cards[0])),
+            (true, false) => changes.push(Change::Hide),
+            _ => {}
+        }
+
+        Some(ViewDelta {
+            cursor: self.events.len(),
+            changes,
+            state: self.state,
+            bet: self.current_bet(),
+            chips: self.player.chips,
+            carry: self.player.carry,
+            last: self.last,
+        })
+    }
+}
+
+impl View {
+    /// Bring the view up to date with a delta from the same game
+    pub fn apply(&mut self, delta: &ViewDelta) {
+        for change in &delta.changes {
+            match change {
+                Change::Bet => self.player.hands = vec![Hand::default()],
+                Change::Card(Seat::Dealer, card) => self.dealer.deal(*card),
+                Change::Card(Seat::Player(idx), card) => self.player.hands[*idx].deal(*card),
+                Change::Split(idx) => {
+                    let card = self.player.hands[*idx].cards.pop();
+                    self.player.hands.push(Hand {
+                        cards: card.into_iter().collect(),
+                    });
+                }
+                Change::Reveal(card) => self.dealer.cards.insert(0, *card),
+                Change::Hide => {
+                    self.dealer.cards.remove(0);
+                }
+                Change::Settled(scores) => self.scores.extend(scores),
+            }
+        }
+        self.state = delta.state;
+        self.bet = delta.bet;
+        self.player.chips = delta.chips;
+        self.player.carry = delta.carry;
+        self.last = delta.last;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum Step {
+        Bet(u64),
+        Player(Action),
+        Dealer,
+    }
+
+    /// Play `steps` on a stacked shoe, applying a delta to the client's view
+    /// after each one
+    fn follow(rules: Ruleset, cards: &str, steps: &[Step]) -> View {
+        let shoe = Shoe::Deck(Deck::stacked(cards));
+        let mut game = Game::with_shoe(rules, Player::new(Money::chips(20)), shoe);
+        let mut view = game.view();
+        let mut cursor = game.events().len();
+        for step in steps {
+            match *step {
+                Step::Bet(chips) => game.bet(Money::chips(chips)),
+                Step::Player(action) => game.player(action),
+                Step::Dealer => game.dealer(),
+            }
+            .unwrap();
+            let delta = game.delta(cursor).unwrap();
+            view.apply(&delta);
+            cursor = delta.cursor;
+            assert_eq!(view, game.view());
+        }
+        assert_eq!(game.state, State::Final);
+        view
+    }
+
+    #[test]
+    fn split_and_double() {
+        let steps = [
+            Step::Bet(3),
+            Step::Player(Action::Split),
+            Step::Player(Action::Double),
+            Step::Player(Action::Double),
+            Step::Dealer,
+        ];
+        let view = follow(Ruleset::default(), "8h 6c 8s 10d 3h 2s 9c Jd 2h", &steps);
+        assert_eq!(view.player.hands.len(), 2);
+        assert_eq!(view.dealer.cards.len(), 3);
+    }
+
+    #[test]
+    fn hit_and_dealer_draws() {
+        let steps = [
+            Step::Bet(2),
+            Step::Player(Action::Hit),
+            Step::Player(Action::Stand),
+            Step::Dealer,
+        ];
+        let view = follow(Ruleset::default(), "10h 2c 5s 3d 4h 5c 2h 3c Kd", &steps);
+        assert_eq!(view.dealer.cards.len(), 6);
+        assert!(view.dealer.bust());
+    }
+
+    #[test]
+    fn surrender() {
+        let rules = Ruleset::default().surrender(true);
+        let steps = [Step::Bet(2), Step::Player(Action::Surrender)];
+        let view = follow(rules, "10h 6c 6s 10d", &steps);
+        assert_eq!(view.scores, vec![Outcome::Surrender(Money::chips(1))]);
+    }
+
+    #[test]
+    fn blackjack() {
+        let view = follow(
+            Ruleset::default(),
+            "Ah 9c Ks 10d",
+            &[Step::Bet(2), Step::Dealer],
+        );
+        assert_eq!(view.scores, vec![Outcome::Blackjack(Money::chips(5))]);
+    }
+}
@@ -0,0 +1,344 @@
+//! The `event` module records every transition of a `Game` as a `GameEvent`
+//!
+//! The engine never changes a `Game` directly: each command decides what
+//! happens, emits the events describing it, and the game is updated only by
+//! applying those events. Folding a game's events from the start therefore
+//! rebuilds it exactly, including the order of the shoe.
+//!
+//! `Created` and `Shuffled` hold the whole shoe, so the event stream is
+//! server-side state, just like a `Snapshot`. A `MentalDeck` keeps only the
+//! server's key, and `Revealed` only the client's decryption of a card that
+//! is about to be dealt, so the stream never holds the client's key
+use super::game::{Error, Last, Outcome};
+use super::observer::notify;
+use super::*;
+use crate::mental::RevealError;
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+
+/// Where a card was dealt to
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Seat {
+    Dealer,
+    /// One of the player's hands, by index
+    Player(usize),
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum GameEvent {
+    /// A new game was opened with these rules for this player. Always the
+    /// first event of a game
+    Created {
+        rules: Ruleset,
+        player: Player,
+    },
+    /// The shoe was replaced by a freshly shuffled one, either when the game
+    /// was created or by the player's shuffle
+    Shuffled {
+        shoe: Shoe,
+    },
+    /// The player placed a bet, and the server committed to the shoe
+    BetPlaced {
+        bet: Money,
+        commitment: String,
+    },
+    /// The client removed its layer of encryption from the next card of a
+    /// `MentalDeck`, so that it can be dealt
+    Revealed {
+        share: BigUint,
+    },
+    /// A card was drawn during the deal or the player's turn
+    CardDealt {
+        to: Seat,
+        card: Card,
+    },
+    /// The player took an action on the current hand. Doubling and
+    /// splitting take the hand's bet again from the player's chips
+    ActionTaken {
+        action: Action,
+    },
+    /// The dealer drew a card while playing out their hand
+    DealerDrew {
+        card: Card,
+    },
+    StateChanged {
+        state: State,
+    },
+    /// Outcomes of the player's hands were decided, leaving the player
+    /// owed `carry` under `Rounding::Carry`
+    Settled {
+        scores: Vec<Outcome>,
+        carry: Money,
+    },
+    /// The shoe ran out of cards, and every bet was returned to the player,
+    /// leaving nothing staked
+    Exhausted,
+}
+
+impl Game {
+    /// A game with no shoe yet, before any events other than `Created`
+    fn created(rules: Ruleset, player: Player) -> Game {
+        Game {
+            rules,
+            shoe: Shoe::Deck(Deck::new(0)),
+            dealer: Hand::default(),
+            player,
+            bets: Vec::new(),
+            state: State::Ready,
+            last: Last::Player(Card {
+                rank: Rank::Three,
+                suit: Suit::Clubs,
+            }),
+            scores: Vec::new(),
+            commitment: String::new(),
+            wager: Money::ZERO,
+            stake: Money::ZERO,
+            actions: Vec::new(),
+            events: Vec::new(),
+            observers: Vec::new(),
+            journal: None,
+            client: None,
+        }
+    }
+
+    /// Start the event stream of a new game
+    pub(crate) fn open(rules: Ruleset, player: Player, shoe: Shoe) -> Game {
+        let mut game = Game::created(rules, player.clone());
+        game.events.push(GameEvent::Created { rules, player });
+        game.emit(GameEvent::Shuffled { shoe })
+            .expect("a shuffle can always be applied");
+        game
+    }
+
+    /// Rebuild a game by applying `events` in order. Fails if the events
+    /// don't start with `Created`, or describe a transition that isn't
+    /// possible, such as dealing a card that isn't next in the shoe
+    pub fn from_events(events: &[GameEvent]) -> Result<Game, Error> {
+        let mut game = match events.first() {
+            Some(GameEvent::Created { rules, player }) => Game::created(*rules, player.clone()),
+            _ => return Err(Error::InvalidAction),
+        };
+        game.events.push(events[0].clone());
+        for event in &events[1..] {
+            game.emit(event.clone())?;
+        }
+        Ok(game)
+    }
+
+    /// Every event of the game so far, starting with `Created`
+    pub fn events(&self) -> &[GameEvent] {
+        &self.events
+    }
+
+    /// Write an event to the journal, if there is one, then apply it, notify
+    /// any observers and add it to the stream
+    pub(crate) fn emit(&mut self, event: GameEvent) -> Result<(), Error> {
+        let mark = match &mut self.journal {
+            Some(journal) => Some(journal.write(&event).map_err(|_| Error::Journal)?),
+            None => None,
+        };
+        if let Err(e) = self.apply(&event) {
+            // The event never happened, so it mustn't be recovered either
+            if let (Some(journal), Some(mark)) = (&mut self.journal, mark) {
+                journal.truncate(mark).map_err(|_| Error::Journal)?;
+            }
+            return Err(e);
+        }
+        for observer in &mut self.observers {
+            notify(observer.as_mut(), &event, self.state);
+        }
+        self.events.push(event);
+        Ok(())
+    }
+
+    /// Remove `card` from the shoe, which must be the next card in it
+    fn take(&mut self, card: Card) -> Result<(), Error> {
+        match self.shoe.draw() {
+            Some(c) if c == card => Ok(()),
+            _ => Err(Error::Fatal),
+        }
+    }
+
+    fn charge(&mut self, amount: Money) -> Result<(), Error> {
+        self.player.chips = self
+            .player
+            .chips
+            .checked_sub(amount)
+            .ok_or(Error::Money(amount.saturating_sub(self.player.chips)))?;
+        self.stake += amount;
+        Ok(())
+    }
+
+    fn apply(&mut self, event: &GameEvent) -> Result<(), Error> {
+        match event {
+            GameEvent::Created { .. } => return Err(Error::InvalidAction),
+            GameEvent::Shuffled { shoe } => self.shoe = shoe.clone(),
+            GameEvent::BetPlaced { bet, commitment } => {
+                self.charge(*bet)?;
+                self.player.hands = vec![Hand::default()];
+                self.commitment = commitment.clone();
+                self.bets = vec![*bet];
+                self.wager = *bet;
+            }
+            GameEvent::Revealed { share } => match &mut self.shoe {
+                Shoe::Mental(deck) => match deck.reveal(share.clone()) {
+                    Ok(_) => {}
+                    Err(RevealError::NotReady) => return Err(Error::InvalidAction),
+                    Err(RevealError::Integrity) => return Err(Error::Integrity),
+                },
+                Shoe::Deck(_) | Shoe::Infinite(_) => return Err(Error::InvalidAction),
+            },
+            GameEvent::CardDealt { to, card } => {
+                if let Seat::Player(idx) = *to {
+                    if idx >= self.player.hands.len() {
+                        return Err(Error::InvalidAction);
+                    }
+                }
+                self.take(*card)?;
+                match *to {
+                    Seat::Dealer => {
+                        self.dealer.deal(*card);
+                        self.last = Last::Dealer(*card);
+                    }
+                    Seat::Player(idx) => {
+                        self.player.hands[idx].deal(*card);
+                        self.last = Last::Player(*card);
+                    }
+                }
+            }
+            GameEvent::ActionTaken { action } => {
+                let idx = match self.state {
+                    State::Player(idx) => idx,
+                    _ => return Err(Error::InvalidAction),
+                };
+                let bet = *self.bets.get(idx).ok_or(Error::InvalidAction)?;
+                match action {
+                    Action::Double => {
+                        self.charge(bet)?;
+                        self.bets[idx] = bet * 2;
+                    }
+                    Action::Split => {
+                        let hand = self.player.hands.get(idx).ok_or(Error::InvalidAction)?;
+                        let split = *hand.cards.last().ok_or(Error::Fatal)?;
+                        self.charge(bet)?;
+                        self.player.hands[idx].cards.pop();
+                        self.player.hands.push(Hand { cards: vec![split] });
+                        self.bets.push(bet);
+                    }
+                    Action::Hit | Action::Stand | Action::Surrender => {}
+                }
+                self.actions.push(*action);
+            }
+            GameEvent::DealerDrew { card } => {
+                self.take(*card)?;
+                self.dealer.deal(*card);
+                self.last = Last::Dealer(*card);
+            }
+            GameEvent::StateChanged { state } => self.state = *state,
+            GameEvent::Settled { scores, carry } => {
+                self.scores.extend(scores);
+                self.player.carry = *carry;
+            }
+            GameEvent::Exhausted => {
+                self.player.chips += self.stake;
+                self.stake = Money::ZERO;
+                self.state = State::Error;
+                self.bets.clear();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stacked(cards: &str, chips: u64) -> Game {
+        let shoe = Shoe::Deck(Deck::stacked(cards));
+        Game::with_shoe(Ruleset::default(), Player::new(Money::chips(chips)), shoe)
+    }
+
+    #[test]
+    fn fold_matches_live_game() {
+        // Split 8s against a 16, double the first hand and stand on the second
+        let mut game = stacked("8h 10c 8s 6d 3h 2c Kh 9d", 20);
+        let mut snapshots = vec![(game.events().len(), game.snapshot())];
+        game.bet(Money::chips(2)).unwrap();
+        snapshots.push((game.events().len(), game.snapshot()));
+        for &action in &[Action::Split, Action::Double, Action::Stand] {
+            game.player(action).unwrap();
+            snapshots.push((game.events().len(), game.snapshot()));
+        }
+        game.dealer().unwrap();
+        snapshots.push((game.events().len(), game.snapshot()));
+        assert_eq!(game.state, State::Final);
+        assert_eq!(game.stake(), Money::chips(6));
+
+        // Folding the events up to any command gives the game as it was then
+        for (len, snapshot) in &snapshots {
+            let folded = Game::from_events(&game.events()[..*len]).unwrap();
+            assert_eq!(folded.snapshot(), *snapshot);
+        }
+        let folded = Game::from_events(game.events()).unwrap();
+        assert_eq!(folded.view(), game.view());
+        assert_eq!(folded.finish().unwrap(), game.finish().unwrap());
+    }
+
+    #[test]
+    fn fold_rejects_impossible_events() {
+        let mut game = stacked("8h 10c 8s 6d", 20);
+        game.bet(Money::chips(2)).unwrap();
+        let mut events = game.events().to_vec();
+        // Dealing a card that isn't next in the shoe
+        events.push(GameEvent::CardDealt {
+            to: Seat::Player(0),
+            card: Card::from_notation("As").unwrap(),
+        });
+        assert_eq!(Game::from_events(&events).err(), Some(Error::Fatal));
+        assert_eq!(Game::from_events(&[]).err(), Some(Error::InvalidAction));
+    }
+
+    #[test]
+    fn failed_event_changes_nothing() {
+        let mut game = stacked("8h 10c 8s 6d 3h", 5);
+        let before = game.snapshot();
+        let bet = GameEvent::BetPlaced {
+            bet: Money::chips(6),
+            commitment: String::new(),
+        };
+        assert_eq!(game.emit(bet), Err(Error::Money(Money::chips(1))));
+        assert_eq!(game.snapshot(), before);
+
+        game.bet(Money::chips(2)).unwrap();
+        // A card for a hand that doesn't exist stays in the shoe
+        let before = game.snapshot();
+        let card = GameEvent::CardDealt {
+            to: Seat::Player(1),
+            card: Card::from_notation("3h").unwrap(),
+        };
+        assert_eq!(game.emit(card), Err(Error::InvalidAction));
+        assert_eq!(game.snapshot(), before);
+
+        // A split that can't move a card doesn't take the bet either
+        game.player.hands[0].cards.clear();
+        let before = game.snapshot();
+        let split = GameEvent::ActionTaken {
+            action: Action::Split,
+        };
+        assert_eq!(game.emit(split), Err(Error::Fatal));
+        assert_eq!(game.snapshot(), before);
+    }
+
+    #[test]
+    fn exhausted_refunds_the_stake() {
+        let mut game = stacked("8h 10c 8s 6d 3h", 20);
+        game.bet(Money::chips(3)).unwrap();
+        assert_eq!(game.player(Action::Split), Err(Error::Fatal));
+        assert_eq!(game.state, State::Error);
+        assert_eq!(game.stake(), Money::ZERO);
+        assert!(game.bets.is_empty());
+        assert_eq!(game.player.chips, Money::chips(20));
+        assert_eq!(game.events().last(), Some(&GameEvent::Exhausted));
+    }
+}
@@ -3,8 +3,15 @@
 //! A custom random number generator can be supplied, for instance, to always
 //! deal the same hands (with a deterministicly seeded PRNG) in the same order
 use super::*;
+use crate::event::{GameEvent, Seat};
+use crate::infinite::InfiniteDeck;
+use crate::journal;
+use crate::limits::Limit;
+use crate::mental::Client;
+use crate::observer::Observer;
+use crate::responsible::Breach;
+use crate::rng::FairRng;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
 
 #[derive(Copy, Clone, PartialEq, Debug, Deserialize, Serialize)]
 pub enum Action {
@@ -16,14 +23,31 @@ pub enum Action {
 }
 
 pub struct Game {
-    rules: Ruleset,
-    deck: Deck,
-    dealer: Hand,
-    player: Player,
-    bet: usize,
-    state: State,
-    last: Last,
-    scores: Vec<Outcome>,
+    pub(crate) rules: Ruleset,
+    pub(crate) shoe: Shoe,
+    pub(crate) dealer: Hand,
+    pub(crate) player: Player,
+    /// Bet on each of the player's hands, including any double
+    pub(crate) bets: Vec<Money>,
+    pub(crate) state: State,
+    pub(crate) last: Last,
+    pub(crate) scores: Vec<Outcome>,
+    /// Sha256 of the shoe at the moment the bet was placed
+    pub(crate) commitment: String,
+    /// Initial bet placed on the round
+    pub(crate) wager: Money,
+    /// Total chips taken from the player, including doubles and splits, and
+    /// not yet returned
+    pub(crate) stake: Money,
+    pub(crate) actions: Vec<Action>,
+    pub(crate) events: Vec<GameEvent>,
+    /// Not part of the game state, so never snapshotted or folded
+    pub(crate) observers: Vec<Box<dyn Observer>>,
+    /// Where events are written before they are applied, see `Journal`
+    pub(crate) journal: Option<journal::Writer>,
+    /// The player's half of a `MentalDeck`. Not part of the game state, so
+    /// never snapshotted, folded or journaled
+    pub(crate) client: Option<Box<dyn Client>>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Deserialize, Serialize)]
@@ -36,10 +60,23 @@ pub enum Last {
 /// Loss = player's loss, etc
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Deserialize, Serialize)]
 pub enum Outcome {
-    Lose(usize),
-    Win(usize),
-    Blackjack(usize),
-    Push(usize),
+    Lose(Money),
+    Win(Money),
+    Blackjack(Money),
+    Push(Money),
+    /// The player gave up the hand, and got back half the bet
+    Surrender(Money),
+}
+
+impl Outcome {
+    /// Amount paid back to the player, including the returned stake
+    pub fn payout(self) -> Money {
+        match self {
+            Outcome::Blackjack(win) | Outcome::Win(win) | Outcome::Push(win) => win,
+            Outcome::Surrender(refund) => refund,
+            Outcome::Lose(_) => Money::ZERO,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Deserialize, Serialize)]
@@ -57,7 +94,9 @@ pub enum State {
 /// (e.g. dealer's upcard). The lack of reference to the parent `Game` allows
 /// multiple `View`s to exist alongside a mutable game object
 pub struct View {
-    pub bet: usize,
+    /// Bet on the hand being played, or on the last hand once the player's
+    /// turn is over
+    pub bet: Money,
     pub rules: Ruleset,
     pub dealer: Hand,
     pub player: Player,
@@ -71,7 +110,28 @@ pub enum Error {
     InvalidAction,
     Fatal,
     DoubleAfterSplit,
-    Money(usize),
+    Money(Money),
+    /// The bet breaks the table's limits
+    Limit(Limit),
+    /// The bet breaks the player's responsible gaming limits
+    PlayerLimit(Breach),
+    /// The event could not be written to the journal, and was not applied
+    Journal,
+    /// A card of a `MentalDeck` didn't decrypt to a card, so either the
+    /// server or the client has cheated. The round is left where it was
+    Integrity,
+}
+
+impl From<Breach> for Error {
+    fn from(breach: Breach) -> Error {
+        Error::PlayerLimit(breach)
+    }
+}
+
+impl From<Limit> for Error {
+    fn from(limit: Limit) -> Error {
+        Error::Limit(limit)
+    }
 }
 
 impl View {
@@ -92,14 +152,18 @@ impl View {
                     } else if self.player.chips < self.bet {
                         Err(Error::Money(self.bet - self.player.chips))
                     } else {
+                        self.rules.limits.check_double(self.bet)?;
                         Ok(idx)
                     }
                 }
                 Action::Split => {
-                    if self.player.can_split(idx) {
-                        Ok(idx)
-                    } else {
+                    if !self.player.can_split(idx) {
                         Err(Error::InvalidAction)
+                    } else if self.player.chips < self.bet {
+                        Err(Error::Money(self.bet - self.player.chips))
+                    } else {
+                        self.rules.limits.check_split(self.bet)?;
+                        Ok(idx)
                     }
                 }
                 Action::Surrender => {
@@ -115,63 +179,86 @@ impl View {
     }
 }
 
+/// Does the dealer draw another card to this hand?
+pub fn dealer_hits(dealer: &Hand, rules: &Ruleset) -> bool {
+    dealer.score() < 17 || (dealer.score() == 17 && dealer.soft() && !rules.stand)
+}
+
 impl Game {
     /// Deal cards to all players
     fn deal(&mut self) -> Result<(), Error> {
         for _ in 0..2 {
-            let c = self.draw()?;
-            self.player.deal(c);
-            let c = self.draw()?;
-            self.dealer.deal(c);
-            self.last = Last::Dealer(c);
+            self.draw(|card| GameEvent::CardDealt {
+                to: Seat::Player(0),
+                card,
+            })?;
+            self.draw(|card| GameEvent::CardDealt {
+                to: Seat::Dealer,
+                card,
+            })?;
         }
         Ok(())
     }
 
-    /// Draw a card from the deck
-    fn draw(&mut self) -> Result<Card, Error> {
-        match self.deck.draw() {
-            Some(card) => Ok(card),
+    /// Draw the next card from the shoe, recording it with `event`
+    fn draw<F: FnOnce(Card) -> GameEvent>(&mut self, event: F) -> Result<Card, Error> {
+        if let Some(c) = self.shoe.unrevealed() {
+            let client = self.client.as_mut().ok_or(Error::InvalidAction)?;
+            let share = client.reveal(c);
+            self.emit(GameEvent::Revealed { share })?;
+        }
+        match self.shoe.peek() {
+            Some(card) => {
+                self.emit(event(card))?;
+                Ok(card)
+            }
             None => {
-                self.player.chips += self.bet;
-                self.state = State::Error;
-                self.bet = 0;
+                self.emit(GameEvent::Exhausted)?;
                 Err(Error::Fatal)
             }
         }
     }
 
-    fn blackjack_amount(&self) -> usize {
-        (self.bet as f64 * 2.5).floor() as usize
+    /// Whether the next card can be drawn. A `MentalDeck` needs the client
+    /// to reveal it
+    fn can_draw(&self) -> bool {
+        self.shoe.unrevealed().is_none() || self.client.is_some()
+    }
+
+    /// Round `amount` according to the rules, given the fraction of a chip
+    /// already owed. Also returns the fraction owed afterwards
+    fn round(&self, amount: Money, carry: Money) -> (Money, Money) {
+        match self.rules.rounding {
+            Rounding::Carry => Rounding::Carry.round(amount + carry),
+            rounding => (rounding.round(amount).0, carry),
+        }
     }
 
     /// Update the current game state - if the player has multiple hands, and they
     /// are not currently playing the final hand, then the state will simply switch
-    fn update_state(&mut self, state: State) {
-        match self.state {
-            State::Player(idx) if idx < self.player.hands.len() - 1 => {
-                self.state = State::Player(idx + 1)
-            }
-            _ => self.state = state,
-        }
+    fn update_state(&mut self, state: State) -> Result<(), Error> {
+        let state = match self.state {
+            State::Player(idx) if idx < self.player.hands.len() - 1 => State::Player(idx + 1),
+            _ => state,
+        };
+        self.emit(GameEvent::StateChanged { state })
     }
 
     /// Check to see if the player has been dealt a Blackjack or has gone bust
-    fn check_end_game(&mut self) {
+    fn check_end_game(&mut self) -> Result<(), Error> {
         if let State::Player(hidx) = self.state {
             if self.player.hands[hidx].blackjack() || self.player.hands[hidx].bust() {
-                self.update_state(State::Dealer);
+                self.update_state(State::Dealer)?;
             }
         }
+        Ok(())
     }
 }
 
 impl Game {
     /// Returns a Sha256 hash of the current deck state
     pub fn sha256(&self) -> String {
-        let mut hasher = Sha256::default();
-        hasher.input(self.deck.notation());
-        format!("{:0x}", hasher.result())
+        self.shoe.sha256()
     }
 
     /// Check to see if an action is valid.
@@ -181,81 +268,105 @@ impl Game {
     /// An `Err` value indicates that the action is invalid, and may give
     /// a cause
     pub fn valid_action(&self, action: Action) -> Result<usize, Error> {
-        match self.state {
-            State::Player(idx) => match action {
-                Action::Hit => Ok(idx),
-                Action::Stand => Ok(idx),
-                Action::Double => {
-                    if !self.rules.double_after_split && self.player.hands.len() > 1 {
-                        Err(Error::DoubleAfterSplit)
-                    } else if self.player.chips < self.bet {
-                        Err(Error::Money(self.bet - self.player.chips))
-                    } else {
-                        Ok(idx)
-                    }
+        let idx = match self.state {
+            State::Player(idx) => idx,
+            _ => return Err(Error::InvalidAction),
+        };
+        let bet = self.bets[idx];
+        match action {
+            Action::Hit => Ok(idx),
+            Action::Stand => Ok(idx),
+            Action::Double => {
+                if !self.rules.double_after_split && self.player.hands.len() > 1 {
+                    Err(Error::DoubleAfterSplit)
+                } else if self.player.chips < bet {
+                    Err(Error::Money(bet - self.player.chips))
+                } else {
+                    self.rules.limits.check_double(bet)?;
+                    Ok(idx)
                 }
-                Action::Split => {
-                    if self.player.can_split(idx) {
-                        Ok(idx)
-                    } else {
-                        Err(Error::InvalidAction)
-                    }
+            }
+            Action::Split => {
+                if !self.player.can_split(idx) {
+                    Err(Error::InvalidAction)
+                } else if self.player.chips < bet {
+                    Err(Error::Money(bet - self.player.chips))
+                } else {
+                    self.rules.limits.check_split(bet)?;
+                    Ok(idx)
                 }
-                Action::Surrender => {
-                    if self.rules.surrender {
-                        Ok(idx)
-                    } else {
-                        Err(Error::InvalidAction)
-                    }
+            }
+            Action::Surrender => {
+                if self.rules.surrender {
+                    Ok(idx)
+                } else {
+                    Err(Error::InvalidAction)
                 }
-            },
-            _ => Err(Error::InvalidAction),
+            }
         }
     }
 
+    /// Bet on the hand being played, or on the last hand once the player's
+    /// turn is over
+    pub(crate) fn current_bet(&self) -> Money {
+        let bet = match self.state {
+            State::Player(idx) => self.bets.get(idx),
+            _ => self.bets.last(),
+        };
+        bet.copied().unwrap_or(Money::ZERO)
+    }
+
     pub fn player(&mut self, action: Action) -> Result<View, Error> {
         let hidx = self.valid_action(action)?;
+        if !self.can_draw() {
+            return Err(Error::InvalidAction);
+        }
+        self.emit(GameEvent::ActionTaken { action })?;
         match action {
             Action::Hit => {
-                let card = self.draw()?;
-                self.last = Last::Player(card);
-                self.player.hands[hidx].deal(card);
+                self.draw(|card| GameEvent::CardDealt {
+                    to: Seat::Player(hidx),
+                    card,
+                })?;
             }
             Action::Stand => {
-                self.update_state(State::Dealer);
+                self.update_state(State::Dealer)?;
             }
             Action::Double => {
-                self.update_state(State::Dealer);
-                self.player.chips -= self.bet;
-                self.bet += self.bet;
-
-                let card = self.draw()?;
-                self.last = Last::Player(card);
-                self.player.hands[hidx].deal(card);
+                self.update_state(State::Dealer)?;
+                self.draw(|card| GameEvent::CardDealt {
+                    to: Seat::Player(hidx),
+                    card,
+                })?;
             }
             Action::Split => {
-                self.player.chips -= self.bet;
-
-                let split = self.player.hands[hidx].cards.pop().ok_or(Error::Fatal)?;
-
-                let card = self.draw()?;
-                self.player.hands[hidx].deal(card);
-
-                let card = self.draw()?;
-                self.last = Last::Player(card);
-                self.player.hands.push(Hand {
-                    cards: vec![split, card],
-                });
+                // The split card has been moved to a new hand at the end
+                let new = self.player.hands.len() - 1;
+                self.draw(|card| GameEvent::CardDealt {
+                    to: Seat::Player(hidx),
+                    card,
+                })?;
+                self.draw(|card| GameEvent::CardDealt {
+                    to: Seat::Player(new),
+                    card,
+                })?;
             }
             Action::Surrender => {
-                self.state = State::Final;
-                for _ in 0..self.player.hands.len() {
-                    self.scores.push(Outcome::Lose(self.bet / 2));
+                let mut carry = self.player.carry;
+                let mut scores = Vec::new();
+                for &bet in &self.bets {
+                    let (refund, owed) = self.round(bet / 2, carry);
+                    carry = owed;
+                    scores.push(Outcome::Surrender(refund));
                 }
+                self.emit(GameEvent::Settled { scores, carry })?;
+                self.emit(GameEvent::StateChanged {
+                    state: State::Final,
+                })?;
             }
         }
 
-        self.check_end_game();
+        self.check_end_game()?;
         Ok(self.view())
     }
 
@@ -265,14 +376,15 @@ impl Game {
                 cards: Vec::default(),
             },
             State::Dealer | State::Final => self.dealer.clone(),
+            // The shoe may have run out before the dealer had any cards
             _ => Hand {
-                cards: self.dealer.cards[1..].to_vec(),
+                cards: self.dealer.cards.get(1..).unwrap_or_default().to_vec(),
             },
         };
 
         View {
             rules: self.rules,
-            bet: self.bet,
+            bet: self.current_bet(),
             dealer,
             player: self.player.clone(),
             state: self.state,
@@ -281,117 +393,351 @@ impl Game {
         }
     }
 
-    /// Initialize a game to the Ready state, and shuffle with the provided RNG
-    pub fn init<R: rand::Rng>(rules: Ruleset, player: Player, rng: &mut R) -> Game {
-        let mut g = Game {
-            rules,
-            deck: Deck::new(6),
-            dealer: Hand::default(),
-            player,
-            bet: 0,
-            state: State::Ready,
-            last: Last::Player(Card {
-                rank: Rank::Three,
-                suit: Suit::Clubs,
-            }),
-            scores: Vec::new(),
+    /// Initialize a game to the Ready state, and shuffle with the provided RNG.
+    /// See the `rng` module for the available backends. `round` is the
+    /// round's position in its session, starting from 0, and is the nonce of
+    /// an infinite deck
+    pub fn init<R: FairRng>(rules: Ruleset, player: Player, rng: &mut R, round: u64) -> Game {
+        let shoe = match rules.decks {
+            Decks::Finite(n) => {
+                let mut deck = Deck::new(n);
+                deck.shuffle(rng);
+                Shoe::Deck(deck)
+            }
+            Decks::Infinite => Shoe::Infinite(InfiniteDeck::generate(rng, round)),
         };
-        g.deck.shuffle(rng);
-        g
+        Game::with_shoe(rules, player, shoe)
     }
 
-    /// Player may shuffle the deck before a bet is placed
-    pub fn player_shuffle<R: rand::Rng>(&mut self, rng: &mut R) {
-        if self.state == State::Ready {
-            self.deck.shuffle(rng);
+    /// Initialize a game to the Ready state with an already prepared shoe,
+    /// such as a `MentalDeck`
+    pub fn with_shoe(rules: Ruleset, player: Player, shoe: Shoe) -> Game {
+        Game::open(rules, player, shoe)
+    }
+
+    /// Player may shuffle the deck before a bet is placed. Fails if the
+    /// shuffle can't be journaled, or the shoe is a `MentalDeck` and no
+    /// client has been set
+    pub fn player_shuffle<R: rand::Rng>(&mut self, rng: &mut R) -> Result<(), Error> {
+        if self.state != State::Ready {
+            return Err(Error::InvalidAction);
         }
+        let mut shoe = self.shoe.clone();
+        let client = self.client.as_mut().map(|c| c.as_mut() as &mut dyn Client);
+        if !shoe.shuffle(rng, client) {
+            return Err(Error::InvalidAction);
+        }
+        self.emit(GameEvent::Shuffled { shoe })
+    }
+
+    /// Attach the player's half of a `MentalDeck`, which shuffles the shoe
+    /// and reveals each card as it is dealt
+    pub fn set_client<C: Client + 'static>(&mut self, client: C) {
+        self.client = Some(Box::new(client));
     }
 
     /// Once the game is in Ready state, the player may place a bet and be
-    /// dealt a hand of cards
-    pub fn bet(&mut self, bet: usize) -> Result<View, Error> {
-        if self.state != State::Ready {
+    /// dealt a hand of cards. A `MentalDeck` must have been shuffled by the
+    /// player first. The bet is held to the table's limits, but not to the
+    /// player's own, which only a `Session` enforces
+    pub fn bet(&mut self, bet: Money) -> Result<View, Error> {
+        if self.state != State::Ready || !self.shoe.ready() || !self.can_draw() {
             return Err(Error::InvalidAction);
         }
-        if bet == 0 {
+        if bet.is_zero() {
             Err(Error::InvalidAction)
+        } else if let Err(limit) = self.rules.limits.check_bet(bet) {
+            Err(Error::Limit(limit))
         } else if self.player.chips < bet {
             Err(Error::Money(bet - self.player.chips))
         } else {
             // Don't let the player set their starting hand!
-            self.player.hands = vec![Hand::default()];
+            let commitment = self.shoe.sha256();
+            self.emit(GameEvent::BetPlaced { bet, commitment })?;
             assert_eq!(self.player.count(), 0);
             self.deal()?;
             assert_eq!(self.player.count(), 2);
-            self.player.chips -= bet;
-            self.bet += bet;
-            self.state = State::Player(0);
+            self.emit(GameEvent::StateChanged {
+                state: State::Player(0),
+            })?;
 
             // Check for initial blackjack
-            self.check_end_game();
+            self.check_end_game()?;
             Ok(self.view())
         }
     }
 
     pub fn dealer(&mut self) -> Result<View, Error> {
-        if self.state != State::Dealer {
+        if self.state != State::Dealer || !self.can_draw() {
             return Err(Error::InvalidAction);
         }
 
         // If there's a hand that isn't busted (i.e. player stood or got blackjack)
         // then we will continue to draw cards to try and beat them
-        for hidx in 0..self.player.hands.len() {
-            if !self.player.hands[hidx].bust()
-                && (self.dealer.score() < 17
-                    || (self.dealer.score() == 17 && self.dealer.soft() && !self.rules.stand))
-            {
-                let card = self.draw()?;
-                self.last = Last::Dealer(card);
-                self.dealer.deal(card);
-                // if !self.dealer.bust() && !self.dealer.blackjack() {
-                //     return Ok(self.view());
-                // }
+        if self.player.hands.iter().any(|hand| !hand.bust()) {
+            while dealer_hits(&self.dealer, &self.rules) {
+                self.draw(|card| GameEvent::DealerDrew { card })?;
             }
         }
 
         // We have now possibly drawn cards for the dealer, so check to see
         // if we have beaten the player
-        for hand in &self.player.hands {
+        let mut scores = Vec::new();
+        let mut carry = self.player.carry;
+        for (hand, &bet) in self.player.hands.iter().zip(&self.bets) {
             if hand.bust() {
-                self.scores.push(Outcome::Lose(self.bet));
+                scores.push(Outcome::Lose(bet));
             } else if hand.blackjack() {
                 if self.dealer.blackjack() {
-                    self.scores.push(Outcome::Push(self.bet));
+                    scores.push(Outcome::Push(bet));
                 } else {
-                    self.scores
-                        .push(Outcome::Blackjack(self.blackjack_amount()));
+                    // Returned stake plus 3:2
+                    let (paid, owed) = self.round(bet.ratio(5, 2), carry);
+                    carry = owed;
+                    scores.push(Outcome::Blackjack(paid));
                 }
             } else if self.dealer.score() > hand.score() && !self.dealer.bust() {
-                self.scores.push(Outcome::Lose(self.bet));
+                scores.push(Outcome::Lose(bet));
             } else if self.dealer.score() == hand.score() {
-                self.scores.push(Outcome::Push(self.bet));
+                scores.push(Outcome::Push(bet));
             } else {
-                self.scores.push(Outcome::Win(self.bet * 2));
+                scores.push(Outcome::Win(bet * 2));
             }
         }
-        self.state = State::Final;
+        self.emit(GameEvent::Settled { scores, carry })?;
+        self.emit(GameEvent::StateChanged {
+            state: State::Final,
+        })?;
         Ok(self.view())
     }
 
+    /// Total amount to be paid back to the player, including returned stakes
+    pub fn winnings(&self) -> Money {
+        self.scores.iter().map(|score| score.payout()).sum()
+    }
+
+    pub fn rules(&self) -> Ruleset {
+        self.rules
+    }
+
+    /// Sha256 of the shoe at the moment the bet was placed
+    pub fn commitment(&self) -> &str {
+        &self.commitment
+    }
+
+    /// Initial bet placed on the round
+    pub fn wager(&self) -> Money {
+        self.wager
+    }
+
+    /// Total chips taken from the player, including doubles and splits. Zero
+    /// once the bets have been refunded because the shoe ran out
+    pub fn stake(&self) -> Money {
+        self.stake
+    }
+
+    /// Register an observer to be called for every event from now on
+    pub fn add_observer<O: Observer + 'static>(&mut self, observer: O) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Every action the player has taken this round, in order
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+
     /// Winnings are not transferred back to the player until finish()
     /// is called. This forces the round to go to completion
     pub fn finish(mut self) -> Result<Player, Error> {
         if self.state != State::Final {
             return Err(Error::InvalidAction);
         }
-        for score in self.scores {
-            match score {
-                Outcome::Blackjack(win) => self.player.chips += win,
-                Outcome::Win(win) => self.player.chips += win,
-                Outcome::Push(win) => self.player.chips += win,
-                _ => {}
-            }
-        }
+        self.player.chips += self.winnings();
         Ok(self.player)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mental::{LocalClient, MentalDeck};
+    use crate::pcg::PCG32;
+
+    fn stacked(rules: Ruleset, cards: &str) -> Game {
+        let shoe = Shoe::Deck(Deck::stacked(cards));
+        Game::with_shoe(rules, Player::new(Money::chips(10)), shoe)
+    }
+
+    #[test]
+    fn dealer_draws_to_seventeen() {
+        // Player 10 9, dealer 2 3 and then four more cards to reach 19
+        let mut game = stacked(Ruleset::default(), "10h 2c 9s 3d 4h 5s 2h 3c Kd");
+        game.bet(Money::chips(2)).unwrap();
+        game.player(Action::Stand).unwrap();
+        let view = game.dealer().unwrap();
+        assert_eq!(view.dealer.cards.len(), 6);
+        assert_eq!(view.dealer.score(), 19);
+        assert_eq!(view.scores, vec![Outcome::Push(Money::chips(2))]);
+    }
+
+    #[test]
+    fn dealer_soft_seventeen() {
+        let cards = "10h Ac 9s 6d 3h Kd";
+        let mut game = stacked(Ruleset::default().stand(true), cards);
+        game.bet(Money::chips(2)).unwrap();
+        game.player(Action::Stand).unwrap();
+        assert_eq!(game.dealer().unwrap().dealer.cards.len(), 2);
+
+        let mut game = stacked(Ruleset::default().stand(false), cards);
+        game.bet(Money::chips(2)).unwrap();
+        game.player(Action::Stand).unwrap();
+        let view = game.dealer().unwrap();
+        assert_eq!(view.dealer.cards.len(), 3);
+        assert_eq!(view.dealer.score(), 20);
+    }
+
+    #[test]
+    fn dealer_stands_on_hard_seventeen_with_an_ace() {
+        // Dealer A 6 is soft 17 and hits, but A 6 K is a hard 17
+        let cards = "10h Ac 9s 6d Kh 5d";
+        let mut game = stacked(Ruleset::default().stand(false), cards);
+        game.bet(Money::chips(2)).unwrap();
+        game.player(Action::Stand).unwrap();
+        let view = game.dealer().unwrap();
+        assert_eq!(view.dealer.cards.len(), 3);
+        assert_eq!(view.dealer.score(), 17);
+        assert!(!view.dealer.soft());
+    }
+
+    #[test]
+    fn dealer_stands_when_player_busts() {
+        let mut game = stacked(Ruleset::default(), "10h 2c 6s 3d Kh 4h");
+        game.bet(Money::chips(2)).unwrap();
+        game.player(Action::Hit).unwrap();
+        let view = game.dealer().unwrap();
+        assert_eq!(view.dealer.cards.len(), 2);
+        assert_eq!(view.scores, vec![Outcome::Lose(Money::chips(2))]);
+    }
+
+    #[test]
+    fn double_after_split_doubles_one_hand() {
+        // Split eights against 16, then double 11 and 10 into two 20s
+        let shoe = Shoe::Deck(Deck::stacked("8h 6c 8s 10d 3h 2s 9c Jd 2h"));
+        let mut game = Game::with_shoe(Ruleset::default(), Player::new(Money::chips(20)), shoe);
+        game.bet(Money::chips(3)).unwrap();
+        game.player(Action::Split).unwrap();
+        assert_eq!(game.view().bet, Money::chips(3));
+        game.player(Action::Double).unwrap();
+        game.player(Action::Double).unwrap();
+        assert_eq!(game.stake(), Money::chips(12));
+        assert_eq!(game.player.chips, Money::chips(8));
+
+        let view = game.dealer().unwrap();
+        assert_eq!(view.dealer.score(), 18);
+        let win = Outcome::Win(Money::chips(12));
+        assert_eq!(view.scores, vec![win, win]);
+    }
+
+    #[test]
+    fn surrender_refunds_rounded_half() {
+        let rules = Ruleset::default().surrender(true);
+        let mut game = stacked(rules, "10h 6c 6s 10d");
+        game.bet(Money::chips(3)).unwrap();
+        let view = game.player(Action::Surrender).unwrap();
+        assert_eq!(view.scores, vec![Outcome::Surrender(Money::chips(1))]);
+        assert_eq!(game.winnings(), Money::chips(1));
+        assert_eq!(game.finish().unwrap().chips, Money::chips(8));
+
+        let rules = rules.rounding(Rounding::HalfChip);
+        let mut game = stacked(rules, "10h 6c 6s 10d");
+        game.bet(Money::chips(3)).unwrap();
+        game.player(Action::Surrender).unwrap();
+        assert_eq!(game.finish().unwrap().chips, Money::minor(850));
+    }
+
+    #[test]
+    fn shoe_runs_out_after_split() {
+        let shoe = Shoe::Deck(Deck::stacked("8h 6c 8s 10d 3h"));
+        let mut game = Game::with_shoe(Ruleset::default(), Player::new(Money::chips(20)), shoe);
+        game.bet(Money::chips(3)).unwrap();
+        assert_eq!(game.player(Action::Split), Err(Error::Fatal));
+        assert_eq!(game.state, State::Error);
+        assert_eq!(game.player.chips, Money::chips(20));
+    }
+
+    #[test]
+    fn view_after_shoe_runs_out_before_dealer_card() {
+        let shoe = Shoe::Deck(Deck::stacked("9h"));
+        let mut game = Game::with_shoe(Ruleset::default(), Player::new(Money::chips(10)), shoe);
+        assert_eq!(game.bet(Money::chips(2)), Err(Error::Fatal));
+        assert_eq!(game.state, State::Error);
+        assert!(game.dealer.cards.is_empty());
+
+        let view = game.view();
+        assert!(view.dealer.cards.is_empty());
+        assert_eq!(view.player.chips, Money::chips(10));
+    }
+
+    #[test]
+    fn bet_needs_player_shuffle_of_mental_deck() {
+        let mut rng = PCG32::new(7, 11);
+        let shoe = Shoe::Mental(MentalDeck::new(1, &mut rng));
+        let mut game = Game::with_shoe(Ruleset::default(), Player::new(Money::chips(10)), shoe);
+
+        // The client's half of the shuffle can't be done without the client
+        assert_eq!(game.player_shuffle(&mut rng), Err(Error::InvalidAction));
+        game.set_client(LocalClient::new(&mut rng));
+
+        assert_eq!(game.bet(Money::chips(2)), Err(Error::InvalidAction));
+        assert_eq!(game.state, State::Ready);
+        assert_eq!(game.player.chips, Money::chips(10));
+        assert_eq!(game.view().dealer.cards.len(), 0);
+
+        game.player_shuffle(&mut rng).unwrap();
+        let view = game.bet(Money::chips(2)).unwrap();
+        assert_eq!(view.dealer.cards.len(), 1);
+        assert_eq!(view.player.hands[0].cards.len(), 2);
+
+        // Every card was revealed by the client just before it was dealt, and
+        // the stream replays without it
+        let revealed = game
+            .events()
+            .iter()
+            .filter(|event| matches!(event, GameEvent::Revealed { .. }))
+            .count();
+        assert_eq!(revealed, 4);
+        let folded = Game::from_events(game.events()).unwrap();
+        assert_eq!(folded.view(), game.view());
+    }
+
+    /// A client that lies about its decryption of each card
+    struct Cheat(LocalClient);
+
+    impl Client for Cheat {
+        fn shuffle(&mut self, cards: &mut [num_bigint::BigUint], rng: &mut dyn rand::RngCore) {
+            self.0.shuffle(cards, rng)
+        }
+
+        fn reveal(&mut self, c: &num_bigint::BigUint) -> num_bigint::BigUint {
+            self.0.reveal(c) + 1u32
+        }
+    }
+
+    #[test]
+    fn mental_card_that_does_not_decode_is_an_integrity_error() {
+        let mut rng = PCG32::new(7, 11);
+        let shoe = Shoe::Mental(MentalDeck::new(1, &mut rng));
+        let mut game = Game::with_shoe(Ruleset::default(), Player::new(Money::chips(10)), shoe);
+        game.set_client(Cheat(LocalClient::new(&mut rng)));
+        game.player_shuffle(&mut rng).unwrap();
+
+        // Not mistaken for an exhausted shoe, so the bet isn't refunded
+        assert_eq!(game.bet(Money::chips(2)), Err(Error::Integrity));
+        assert_ne!(game.state, State::Error);
+        assert_eq!(game.player.chips, Money::chips(8));
+        assert!(!game
+            .events()
+            .iter()
+            .any(|event| matches!(event, GameEvent::Exhausted | GameEvent::Revealed { .. })));
+    }
+}
@@ -0,0 +1,681 @@
+//! Hand histories, a plain text record of finished rounds
+//!
+//! Each round is written as a block of lines, in the spirit of the hand
+//! histories poker sites export, so that players can read and share them:
+//!
+//! ```text
+//! Hand #1
+//! Rules: 6 decks, dealer stands on soft 17, double after split, no surrender, blackjack rounded down
+//! Limits: bets from 1.00, in steps of 1.00, to 500.00
+//! Deck: 9c2f...
+//! Seat 1: 1000.00 in chips, 0.00 carried
+//! Bet: 10.00
+//! Dealt to hand 1: 10h
+//! Dealt to dealer: 7c
+//! Dealt to hand 1: 6d
+//! Dealt to dealer: Ks
+//! Hand 1: hit
+//! Dealt to hand 1: 5c
+//! Hand 1: stand
+//! *** Result ***
+//! Dealer: 7c Ks (17)
+//! Hand 1: 10h 6d 5c (21), win 20.00
+//! Seat 1: 1010.00 in chips, 0.00 carried
+//! ```
+//!
+//! Blocks are separated by blank lines. Once the seeds have been revealed
+//! they can be added on a `Seeds:` line after the limits, and `parse` reads
+//! the blocks back so that `HandHistory::record` can replay the round
+use super::event::{GameEvent, Seat};
+use super::game::Outcome;
+use super::replay::RoundRecord;
+use super::rng::Seed;
+use super::rules::Decks;
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// One line of play, in the order it happened
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Play {
+    /// A card dealt during the deal or the player's turn
+    Dealt(Seat, Card),
+    /// An action taken on the player's hand at this index
+    Action(usize, Action),
+    /// A card drawn by the dealer playing out their hand
+    Drew(Card),
+    /// The shoe ran out of cards, and the bet was returned
+    Exhausted,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct HandHistory {
+    /// Number of the round in the session, starting from 1
+    pub number: usize,
+    pub rules: Ruleset,
+    /// Server's and player's seeds at the start of the round, once revealed
+    pub seeds: Option<(Seed, Seed)>,
+    /// Sha256 of the shoe when the bet was placed
+    pub deck: String,
+    /// Player's chips before the bet
+    pub chips: Money,
+    pub carry: Money,
+    pub bet: Money,
+    pub plays: Vec<Play>,
+    pub outcomes: Vec<Outcome>,
+    /// Player's chips once the winnings have been paid
+    pub chips_after: Money,
+    pub carry_after: Money,
+}
+
+/// The first line of a hand history that could not be read
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    /// Line number, starting from 1
+    pub line: usize,
+    /// What was expected on the line
+    pub expected: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: expected {}", self.line, self.expected)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl HandHistory {
+    /// The history of a finished round, numbered `number` in its session.
+    /// `None` if the game is not in the `Final` or `Error` state, as the
+    /// history shows the dealer's hole card
+    pub fn new(number: usize, game: &Game) -> Option<HandHistory> {
+        if !matches!(game.state, State::Final | State::Error) {
+            return None;
+        }
+        let mut history = HandHistory {
+            number,
+            rules: game.rules(),
+            seeds: None,
+            deck: String::new(),
+            chips: Money::ZERO,
+            carry: Money::ZERO,
+            bet: Money::ZERO,
+            plays: Vec::new(),
+            outcomes: Vec::new(),
+            chips_after: game.player.chips + game.winnings(),
+            carry_after: game.player.carry,
+        };
+        let mut state = State::Ready;
+        for event in game.events() {
+            match event {
+                GameEvent::Created { player, .. } => {
+                    history.chips = player.chips;
+                    history.carry = player.carry;
+                }
+                GameEvent::BetPlaced { bet, commitment } => {
+                    history.bet = *bet;
+                    history.deck = commitment.clone();
+                }
+                GameEvent::CardDealt { to, card } => history.plays.push(Play::Dealt(*to, *card)),
+                GameEvent::ActionTaken { action } => {
+                    if let State::Player(idx) = state {
+                        history.plays.push(Play::Action(idx, *action));
+                    }
+                }
+                GameEvent::DealerDrew { card } => history.plays.push(Play::Drew(*card)),
+                GameEvent::StateChanged { state: next } => state = *next,
+                GameEvent::Settled { scores, .. } => history.outcomes.extend(scores),
+                GameEvent::Exhausted => history.plays.push(Play::Exhausted),
+                GameEvent::Shuffled { .. } | GameEvent::Revealed { .. } => {}
+            }
+        }
+        Some(history)
+    }
+
+    /// Add the seeds the round started from, once they have been revealed
+    pub fn seeds(mut self, server: Seed, client: Seed) -> HandHistory {
+        self.seeds = Some((server, client));
+        self
+    }
+
+    /// Every action the player took, in order
+    pub fn actions(&self) -> Vec<Action> {
+        self.plays
+            .iter()
+            .filter_map(|play| match play {
+                Play::Action(_, action) => Some(*action),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Enough to replay the round with `Game::replay`, if the seeds are known
+    pub fn record(&self) -> Option<RoundRecord> {
+        let (server, client) = self.seeds.clone()?;
+        Some(RoundRecord {
+            rules: self.rules,
+            server,
+            client,
+            chips: self.chips,
+            carry: self.carry,
+            bet: self.bet,
+            actions: self.actions(),
+            round: self.number.saturating_sub(1) as u64,
+        })
+    }
+
+    /// The player's hands and the dealer's hand at the end of the round
+    pub fn hands(&self) -> (Vec<Hand>, Hand) {
+        let mut hands = vec![Hand::default()];
+        let mut dealer = Hand::default();
+        for play in &self.plays {
+            match *play {
+                Play::Dealt(Seat::Dealer, card) | Play::Drew(card) => dealer.deal(card),
+                Play::Dealt(Seat::Player(idx), card) => {
+                    if let Some(hand) = hands.get_mut(idx) {
+                        hand.deal(card);
+                    }
+                }
+                Play::Action(idx, Action::Split) => {
+                    let card = hands.get_mut(idx).and_then(|hand| hand.cards.pop());
+                    hands.push(Hand {
+                        cards: card.into_iter().collect(),
+                    });
+                }
+                Play::Action(..) | Play::Exhausted => {}
+            }
+        }
+        (hands, dealer)
+    }
+}
+
+fn action_name(action: Action) -> &'static str {
+    match action {
+        Action::Hit => "hit",
+        Action::Stand => "stand",
+        Action::Split => "split",
+        Action::Double => "double",
+        Action::Surrender => "surrender",
+    }
+}
+
+fn describe_rules(rules: &Ruleset) -> String {
+    let mut parts = vec![match rules.decks {
+        Decks::Finite(1) => "1 deck".to_string(),
+        Decks::Finite(n) => format!("{} decks", n),
+        Decks::Infinite => "infinite deck".to_string(),
+    }];
+    parts.push(if rules.stand {
+        "dealer stands on soft 17".into()
+    } else {
+        "dealer hits soft 17".into()
+    });
+    parts.push(if rules.double_after_split {
+        "double after split".into()
+    } else {
+        "no double after split".into()
+    });
+    parts.push(if rules.surrender {
+        "surrender".into()
+    } else {
+        "no surrender".into()
+    });
+    parts.push(
+        match rules.rounding {
+            Rounding::Down => "blackjack rounded down",
+            Rounding::HalfChip => "blackjack rounded to half chips",
+            Rounding::Carry => "blackjack fractions carried",
+        }
+        .into(),
+    );
+    if rules.rewind {
+        parts.push("rewind".into());
+    }
+    parts.join(", ")
+}
+
+fn describe_limits(rules: &Ruleset) -> String {
+    let limits = rules.limits;
+    let mut parts = vec![
+        format!("bets from {}", limits.min),
+        format!("in steps of {}", limits.increment),
+    ];
+    if let Some(max) = limits.max {
+        parts.push(format!("to {}", max));
+    }
+    if let Some(max) = limits.double {
+        parts.push(format!("double up to {}", max));
+    }
+    if let Some(max) = limits.split {
+        parts.push(format!("split up to {}", max));
+    }
+    if !limits.side_min.is_zero() {
+        parts.push(format!("side bets from {}", limits.side_min));
+    }
+    if let Some(max) = limits.side_max {
+        parts.push(format!("side bets to {}", max));
+    }
+    parts.join(", ")
+}
+
+/// Is written as a block of lines, each ending in a newline
+impl fmt::Display for HandHistory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Hand #{}", self.number)?;
+        writeln!(f, "Rules: {}", describe_rules(&self.rules))?;
+        writeln!(f, "Limits: {}", describe_limits(&self.rules))?;
+        if let Some((server, client)) = &self.seeds {
+            writeln!(f, "Seeds: server {}, client {}", server, client)?;
+        }
+        writeln!(f, "Deck: {}", self.deck)?;
+        writeln!(f, "Seat 1: {} in chips, {} carried", self.chips, self.carry)?;
+        writeln!(f, "Bet: {}", self.bet)?;
+        for play in &self.plays {
+            match play {
+                Play::Dealt(Seat::Dealer, card) => {
+                    writeln!(f, "Dealt to dealer: {}", card.notation())?
+                }
+                Play::Dealt(Seat::Player(idx), card) => {
+                    writeln!(f, "Dealt to hand {}: {}", idx + 1, card.notation())?
+                }
+                Play::Action(idx, action) => {
+                    writeln!(f, "Hand {}: {}", idx + 1, action_name(*action))?
+                }
+                Play::Drew(card) => writeln!(f, "Dealer draws: {}", card.notation())?,
+                Play::Exhausted => writeln!(f, "Shoe exhausted, bet returned")?,
+            }
+        }
+
+        writeln!(f, "*** Result ***")?;
+        let (hands, dealer) = self.hands();
+        writeln!(f, "Dealer: {} ({})", dealer, dealer.score())?;
+        for (idx, outcome) in self.outcomes.iter().enumerate() {
+            let hand = hands.get(idx).cloned().unwrap_or_default();
+            let (name, amount) = match *outcome {
+                Outcome::Lose(amount) => ("lose", amount),
+                Outcome::Win(amount) => ("win", amount),
+                Outcome::Blackjack(amount) => ("blackjack", amount),
+                Outcome::Push(amount) => ("push", amount),
+                Outcome::Surrender(amount) => ("surrender", amount),
+            };
+            writeln!(
+                f,
+                "Hand {}: {} ({}), {} {}",
+                idx + 1,
+                hand,
+                hand.score(),
+                name,
+                amount
+            )?;
+        }
+        writeln!(
+            f,
+            "Seat 1: {} in chips, {} carried",
+            self.chips_after, self.carry_after
+        )
+    }
+}
+
+/// An amount written by `Money`'s `Display`, such as `12.50`
+fn parse_money(s: &str) -> Option<Money> {
+    let (whole, frac) = s.split_once('.')?;
+    if frac.len() != 2 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let whole: u64 = whole.parse().ok()?;
+    let minor = whole
+        .checked_mul(Money::SCALE)?
+        .checked_add(frac.parse().ok()?)?;
+    Some(Money::minor(minor))
+}
+
+fn parse_rules(s: &str) -> Option<Ruleset> {
+    let mut rules = Ruleset::default();
+    for part in s.split(", ") {
+        match part {
+            "dealer stands on soft 17" => rules.stand = true,
+            "dealer hits soft 17" => rules.stand = false,
+            "double after split" => rules.double_after_split = true,
+            "no double after split" => rules.double_after_split = false,
+            "surrender" => rules.surrender = true,
+            "no surrender" => rules.surrender = false,
+            "blackjack rounded down" => rules.rounding = Rounding::Down,
+            "blackjack rounded to half chips" => rules.rounding = Rounding::HalfChip,
+            "blackjack fractions carried" => rules.rounding = Rounding::Carry,
+            "rewind" => rules.rewind = true,
+            "infinite deck" => rules.decks = Decks::Infinite,
+            "1 deck" => rules.decks = Decks::Finite(1),
+            _ => {
+                let n: usize = part.strip_suffix(" decks")?.parse().ok()?;
+                if n == 0 {
+                    return None;
+                }
+                rules.decks = Decks::Finite(n);
+            }
+        }
+    }
+    Some(rules)
+}
+
+fn parse_limits(s: &str, rules: &mut Ruleset) -> Option<()> {
+    let limits = &mut rules.limits;
+    for part in s.split(", ") {
+        let (name, amount) = part.rsplit_once(' ')?;
+        let amount = parse_money(amount)?;
+        match name {
+            "bets from" => limits.min = amount,
+            "in steps of" if !amount.is_zero() => limits.increment = amount,
+            "to" => limits.max = Some(amount),
+            "double up to" => limits.double = Some(amount),
+            "split up to" => limits.split = Some(amount),
+            "side bets from" => limits.side_min = amount,
+            "side bets to" => limits.side_max = Some(amount),
+            _ => return None,
+        }
+    }
+    Some(())
+}
+
+/// `1000.00 in chips, 0.00 carried`
+fn parse_seat(s: &str) -> Option<(Money, Money)> {
+    let (chips, carry) = s.split_once(", ")?;
+    Some((
+        parse_money(chips.strip_suffix(" in chips")?)?,
+        parse_money(carry.strip_suffix(" carried")?)?,
+    ))
+}
+
+/// A 1-based hand number, as an index
+fn parse_hand(s: &str) -> Option<usize> {
+    s.parse::<usize>().ok()?.checked_sub(1)
+}
+
+fn parse_play(line: &str) -> Option<Play> {
+    if line == "Shoe exhausted, bet returned" {
+        return Some(Play::Exhausted);
+    }
+    if let Some(card) = line.strip_prefix("Dealt to dealer: ") {
+        return Some(Play::Dealt(Seat::Dealer, Card::from_notation(card)?));
+    }
+    if let Some(card) = line.strip_prefix("Dealer draws: ") {
+        return Some(Play::Drew(Card::from_notation(card)?));
+    }
+    if let Some(rest) = line.strip_prefix("Dealt to hand ") {
+        let (idx, card) = rest.split_once(": ")?;
+        return Some(Play::Dealt(
+            Seat::Player(parse_hand(idx)?),
+            Card::from_notation(card)?,
+        ));
+    }
+    let (idx, action) = line.strip_prefix("Hand ")?.split_once(": ")?;
+    let action = match action {
+        "hit" => Action::Hit,
+        "stand" => Action::Stand,
+        "split" => Action::Split,
+        "double" => Action::Double,
+        "surrender" => Action::Surrender,
+        _ => return None,
+    };
+    Some(Play::Action(parse_hand(idx)?, action))
+}
+
+/// `Hand 1: 10h 6d 5c (21), win 20.00`. The cards and score are only for
+/// the reader, and are rebuilt from the plays
+fn parse_outcome(line: &str, idx: usize) -> Option<Outcome> {
+    let (hand, result) = line.strip_prefix("Hand ")?.split_once(": ")?;
+    if parse_hand(hand)? != idx {
+        return None;
+    }
+    let (name, amount) = result.rsplit_once("), ")?.1.split_once(' ')?;
+    let amount = parse_money(amount)?;
+    Some(match name {
+        "lose" => Outcome::Lose(amount),
+        "win" => Outcome::Win(amount),
+        "blackjack" => Outcome::Blackjack(amount),
+        "push" => Outcome::Push(amount),
+        "surrender" => Outcome::Surrender(amount),
+        _ => return None,
+    })
+}
+
+/// The lines of one block, with their line numbers
+struct Block<'a> {
+    lines: std::iter::Peekable<std::vec::IntoIter<(usize, &'a str)>>,
+    end: usize,
+}
+
+impl<'a> Block<'a> {
+    fn peek(&mut self) -> Option<&'a str> {
+        self.lines.peek().map(|(_, line)| *line)
+    }
+
+    /// The next line, which must start with `prefix`, without it
+    fn field(
+        &mut self,
+        prefix: &str,
+        expected: &'static str,
+    ) -> Result<(usize, &'a str), ParseError> {
+        match self.lines.next() {
+            Some((n, line)) => match line.strip_prefix(prefix) {
+                Some(rest) => Ok((n, rest)),
+                None => Err(ParseError { line: n, expected }),
+            },
+            None => Err(ParseError {
+                line: self.end,
+                expected,
+            }),
+        }
+    }
+
+    /// Parse the next line, which must start with `prefix`, with `parse`
+    fn parse<T>(
+        &mut self,
+        prefix: &str,
+        expected: &'static str,
+        parse: impl FnOnce(&'a str) -> Option<T>,
+    ) -> Result<T, ParseError> {
+        let (line, rest) = self.field(prefix, expected)?;
+        parse(rest).ok_or(ParseError { line, expected })
+    }
+}
+
+fn parse_block(mut block: Block) -> Result<HandHistory, ParseError> {
+    let number = block.parse("Hand #", "a hand number", |s| s.parse().ok())?;
+    let mut rules = block.parse("Rules: ", "the table rules", parse_rules)?;
+    block.parse("Limits: ", "the table limits", |s| {
+        parse_limits(s, &mut rules)
+    })?;
+    let seeds = match block.peek() {
+        Some(line) if line.starts_with("Seeds: ") => {
+            Some(block.parse("Seeds: server ", "the seeds", |s| {
+                let (server, client) = s.split_once(", client ")?;
+                Some((server.parse().ok()?, client.parse().ok()?))
+            })?)
+        }
+        _ => None,
+    };
+    let deck = block.parse("Deck: ", "the deck commitment", |s| Some(s.to_string()))?;
+    let (chips, carry) = block.parse("Seat 1: ", "the player's chips", parse_seat)?;
+    let bet = block.parse("Bet: ", "the bet", parse_money)?;
+
+    let mut plays = Vec::new();
+    while block.peek() != Some("*** Result ***") {
+        plays.push(block.parse("", "a card or action", parse_play)?);
+    }
+    block.field("*** Result ***", "the result")?;
+    block.field("Dealer: ", "the dealer's hand")?;
+
+    let mut outcomes = Vec::new();
+    while let Some(line) = block.peek() {
+        if !line.starts_with("Hand ") {
+            break;
+        }
+        let idx = outcomes.len();
+        outcomes.push(block.parse("", "the outcome of a hand", |s| parse_outcome(s, idx))?);
+    }
+    let (chips_after, carry_after) =
+        block.parse("Seat 1: ", "the player's chips after the round", parse_seat)?;
+    if let Some((line, _)) = block.lines.next() {
+        return Err(ParseError {
+            line,
+            expected: "a blank line before the next hand",
+        });
+    }
+
+    Ok(HandHistory {
+        number,
+        rules,
+        seeds,
+        deck,
+        chips,
+        carry,
+        bet,
+        plays,
+        outcomes,
+        chips_after,
+        carry_after,
+    })
+}
+
+/// Read every hand history in `text`, written by `HandHistory`'s `Display`
+/// and separated by blank lines
+pub fn parse(text: &str) -> Result<Vec<HandHistory>, ParseError> {
+    let mut histories = Vec::new();
+    let mut lines = Vec::new();
+    let numbered = text.lines().map(str::trim_end).enumerate();
+    for (n, line) in numbered.chain(Some((text.lines().count(), ""))) {
+        if !line.is_empty() {
+            lines.push((n + 1, line));
+        } else if !lines.is_empty() {
+            let block = Block {
+                lines: std::mem::take(&mut lines).into_iter().peekable(),
+                end: n + 1,
+            };
+            histories.push(parse_block(block)?);
+        }
+    }
+    Ok(histories)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Limits;
+    use crate::rng::Backend;
+
+    fn stacked(rules: Ruleset, cards: &str) -> Game {
+        let shoe = Shoe::Deck(Deck::stacked(cards));
+        Game::with_shoe(rules, Player::new(Money::chips(20)), shoe)
+    }
+
+    /// Rounds with a split and doubles, a surrender, and a shoe that ran
+    /// out, under limits and rules that all show in the history
+    fn histories() -> Vec<HandHistory> {
+        let limits = Limits::new(Money::chips(1), Money::chips(500))
+            .increment(Money::minor(50))
+            .double(Money::chips(100))
+            .split(Money::chips(200))
+            .side(Money::chips(1), Money::chips(25));
+        let rules = Ruleset::default().decks(6).limits(limits);
+
+        let mut split = stacked(rules, "8h 6c 8s 10d 3h 2s 9c Jd 2h");
+        split.bet(Money::chips(3)).unwrap();
+        split.player(Action::Split).unwrap();
+        split.player(Action::Double).unwrap();
+        split.player(Action::Double).unwrap();
+        split.dealer().unwrap();
+
+        let rules = rules.surrender(true).rounding(Rounding::HalfChip);
+        let mut surrender = stacked(rules, "10h 6c 6s 10d");
+        surrender.bet(Money::chips(3)).unwrap();
+        surrender.player(Action::Surrender).unwrap();
+
+        let mut exhausted = stacked(Ruleset::default().infinite(), "9h");
+        assert_eq!(exhausted.bet(Money::chips(2)), Err(game::Error::Fatal));
+
+        vec![
+            HandHistory::new(1, &split).unwrap(),
+            HandHistory::new(2, &surrender).unwrap(),
+            HandHistory::new(3, &exhausted).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn parses_what_it_writes() {
+        let histories = histories();
+        assert_eq!(
+            histories[1].outcomes,
+            vec![Outcome::Surrender(Money::minor(150))]
+        );
+        assert_eq!(histories[2].plays.last(), Some(&Play::Exhausted));
+        for history in &histories {
+            assert_eq!(parse(&history.to_string()), Ok(vec![history.clone()]));
+        }
+
+        let text = histories.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse(&text.join("\n")), Ok(histories));
+    }
+
+    #[test]
+    fn record_replays_the_round() {
+        let server = Seed::new(Backend::ChaCha20, &[8; 32]).unwrap();
+        let client = Seed::new(Backend::ChaCha20, &[9; 32]).unwrap();
+        let record = RoundRecord {
+            rules: Ruleset::default().decks(2),
+            server: server.clone(),
+            client: client.clone(),
+            chips: Money::chips(100),
+            carry: Money::ZERO,
+            bet: Money::chips(5),
+            actions: vec![Action::Stand],
+            round: 4,
+        };
+        let game = Game::replay(&record).unwrap().game;
+        let history = HandHistory::new(5, &game).unwrap().seeds(server, client);
+
+        let parsed = parse(&history.to_string()).unwrap().remove(0);
+        assert_eq!(parsed, history);
+        assert_eq!(parsed.record(), Some(record));
+        assert_eq!(HandHistory::new(5, &game).unwrap().record(), None);
+    }
+
+    #[test]
+    fn reports_the_first_bad_line() {
+        let text = histories()[0].to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        let error = |line, expected| Err(ParseError { line, expected });
+        let edit = |n: usize, with: &str| {
+            let mut lines = lines.clone();
+            lines[n] = with;
+            parse(&lines.join("\n"))
+        };
+
+        assert_eq!(edit(0, "Hand one"), error(1, "a hand number"));
+        assert_eq!(
+            edit(1, "Rules: 6 decks, no peeking"),
+            error(2, "the table rules")
+        );
+        assert_eq!(
+            edit(2, "Limits: bets from 1.5"),
+            error(3, "the table limits")
+        );
+        assert_eq!(edit(5, "Bet: 3"), error(6, "the bet"));
+        assert_eq!(edit(8, "Hand 1: fold"), error(9, "a card or action"));
+        assert_eq!(edit(8, "Dealt to hand 1: 1x"), error(9, "a card or action"));
+        let last = lines.len() - 1;
+        assert_eq!(
+            edit(last - 1, "Hand 3: 8s 2s 10d (20), win 6.00"),
+            error(last, "the outcome of a hand")
+        );
+
+        // A block cut short, or running into the next without a blank line
+        let cut = lines[..6].join("\n");
+        assert_eq!(parse(&cut), error(7, "a card or action"));
+        let joined = format!("{}{}", text, text);
+        assert_eq!(
+            parse(&joined),
+            error(lines.len() + 1, "a blank line before the next hand")
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! The `infinite` module deals from an infinite deck, where every card is
+//! derived independently from `HMAC-SHA256(server_seed, client_seed:nonce:cursor)`
+//!
+//! There is no deck order to commit to: the server publishes the Sha256 of
+//! its seed, and once the seed is revealed a player can check any single
+//! card with `card`, without replaying the rest of the round
+use super::card::Card;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Derive the card at position `cursor` of round `nonce`.
+///
+/// The first 8 bytes of the HMAC are read as a big-endian integer and
+/// reduced modulo 52, which has a bias of less than 2^-58
+pub fn card(server_seed: &[u8], client_seed: &str, nonce: u64, cursor: u64) -> Card {
+    let mut mac = Hmac::<Sha256>::new_varkey(server_seed).expect("HMAC accepts any key length");
+    mac.input(format!("{}:{}:{}", client_seed, nonce, cursor).as_bytes());
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&mac.result().code()[..8]);
+    Card::from_code((u64::from_be_bytes(buf) % 52) as u8).expect("code is less than 52")
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct InfiniteDeck {
+    server_seed: [u8; 32],
+    client_seed: String,
+    nonce: u64,
+    cursor: u64,
+}
+
+impl InfiniteDeck {
+    pub fn new(server_seed: [u8; 32], client_seed: String, nonce: u64) -> InfiniteDeck {
+        InfiniteDeck {
+            server_seed,
+            client_seed,
+            nonce,
+            cursor: 0,
+        }
+    }
+
+    /// Draw a fresh server seed from `rng` for the round numbered `nonce`,
+    /// with an empty client seed. The nonce keeps rounds apart even if the
+    /// same server seed is drawn again
+    pub fn generate<R: Rng>(rng: &mut R, nonce: u64) -> InfiniteDeck {
+        let mut server_seed = [0u8; 32];
+        rng.fill(&mut server_seed);
+        InfiniteDeck::new(server_seed, String::new(), nonce)
+    }
+
+    pub fn client_seed(&self) -> &str {
+        &self.client_seed
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Number of cards dealt so far
+    pub fn cursor(&self) -> u64 {
+        self.cursor
+    }
+
+    /// Replace the client seed with 16 random bytes from the player's RNG.
+    /// Only has an effect before the first card is dealt
+    pub fn shuffle<R: Rng>(&mut self, rng: &mut R) {
+        if self.cursor == 0 {
+            let mut buf = [0u8; 16];
+            rng.fill(&mut buf);
+            self.client_seed = buf.iter().map(|b| format!("{:02x}", b)).collect();
+        }
+    }
+
+    /// Card at position `cursor` of this round, whether or not it has been
+    /// dealt yet
+    pub fn card_at(&self, cursor: u64) -> Card {
+        card(&self.server_seed, &self.client_seed, self.nonce, cursor)
+    }
+
+    pub fn draw(&mut self) -> Option<Card> {
+        let c = self.card_at(self.cursor);
+        self.cursor += 1;
+        Some(c)
+    }
+
+    /// Sha256 of the server seed, published before play begins
+    pub fn sha256(&self) -> String {
+        let mut hasher = Sha256::default();
+        hasher.input(self.server_seed);
+        format!("{:0x}", hasher.result())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcg::PCG32;
+
+    fn cards(deck: &mut InfiniteDeck, n: usize) -> Vec<Card> {
+        (0..n).map(|_| deck.draw().unwrap()).collect()
+    }
+
+    /// Cards 0 to 5 for server seed `[7; 32]`, client seed `client` and nonce
+    /// 3, computed separately with Python's `hmac` module
+    const CARDS: [&str; 6] = ["9h", "Jc", "Kc", "9c", "Js", "6d"];
+
+    #[test]
+    fn card_derivation_is_reproducible() {
+        let seed = [7u8; 32];
+        let dealt = (0..6)
+            .map(|cursor| card(&seed, "client", 3, cursor).notation())
+            .collect::<Vec<_>>();
+        assert_eq!(dealt, CARDS);
+
+        let mut deck = InfiniteDeck::new(seed, "client".into(), 3);
+        assert_eq!(deck.card_at(4).notation(), CARDS[4]);
+        let drawn = cards(&mut deck, 6);
+        assert_eq!(
+            drawn.iter().map(|c| c.notation()).collect::<Vec<_>>(),
+            CARDS
+        );
+        assert_eq!(deck.cursor(), 6);
+    }
+
+    #[test]
+    fn rounds_deal_different_cards() {
+        // The same server seed in two rounds still deals different cards
+        let seed = [7u8; 32];
+        let mut first = InfiniteDeck::new(seed, "client".into(), 0);
+        let mut second = InfiniteDeck::new(seed, "client".into(), 1);
+        assert_ne!(cards(&mut first, 10), cards(&mut second, 10));
+
+        let mut rng = PCG32::new(1, 2);
+        let mut first = InfiniteDeck::generate(&mut rng.clone(), 0);
+        let mut again = InfiniteDeck::generate(&mut rng.clone(), 0);
+        let mut second = InfiniteDeck::generate(&mut rng, 1);
+        assert_eq!(second.nonce(), 1);
+        assert_eq!(first.sha256(), second.sha256());
+        let dealt = cards(&mut first, 10);
+        assert_eq!(dealt, cards(&mut again, 10));
+        assert_ne!(dealt, cards(&mut second, 10));
+    }
+}
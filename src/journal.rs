@@ -0,0 +1,436 @@
+//! Write-ahead journal of rounds in progress
+//!
+//! A player's chips are taken when the bet is placed and only given back by
+//! `Game::finish`, so a server that dies in between would lose them. Once a
+//! game has been started in a `Journal`, every event is appended to the
+//! round's own file and synced to disk before it is applied. When the server
+//! starts again, `Journal::recover` rebuilds each round that was never
+//! closed from its events. Rounds the dealer had settled are paid out, and
+//! the rest are resumed or refunded, as the `Policy` says.
+//!
+//! The journal only covers the game. Once the player's chips have been
+//! stored, by `Wallet::settle` or `Wallet::refund` for instance, the round
+//! should be closed with `Journal::close`. A crash in between recovers the
+//! round a second time. Its player then names the wallet `Lock` that was
+//! already released, so the wallet refuses it rather than paying out twice
+use super::event::GameEvent;
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// A pending round. Ids of closed rounds may be used again once the
+/// journal is reopened
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct RoundId(pub u64);
+
+impl fmt::Display for RoundId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// What to do with a round that was interrupted before it was settled
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Policy {
+    /// Rebuild the round so that it can be played on
+    Resume,
+    /// Give the player back the chips they had before the bet
+    Refund,
+}
+
+/// A round brought back by `Journal::recover`
+pub enum Recovered {
+    /// The round as it was left, still journaled
+    Resumed(RoundId, Box<Game>),
+    /// The round hadn't been settled, and the player has their stake back.
+    /// The game is returned as it was left, for `Wallet::refund`
+    Refunded(RoundId, Player, Box<Game>),
+    /// The round had been settled, and the player has been paid. The game
+    /// is returned unfinished, for `Wallet::settle`
+    Settled(RoundId, Player, Box<Game>),
+    /// The round's file could not be read or replayed. It is left in the
+    /// journal for someone to look at
+    Corrupt(RoundId, io::Error),
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Make a file created or removed in `dir` durable
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Appends the events of one round to its file
+pub(crate) struct Writer {
+    file: File,
+    path: PathBuf,
+    len: u64,
+}
+
+impl Writer {
+    fn open(path: &Path) -> io::Result<Writer> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Writer {
+            file,
+            path: path.to_path_buf(),
+            len,
+        })
+    }
+
+    /// Append events and sync them to disk. Returns the length of the file
+    /// before them
+    fn write_all(&mut self, events: &[GameEvent]) -> io::Result<u64> {
+        let mut buf = Vec::new();
+        for event in events {
+            serde_json::to_writer(&mut buf, event)?;
+            buf.push(b'\n');
+        }
+        self.file.write_all(&buf)?;
+        self.file.sync_data()?;
+        let mark = self.len;
+        self.len += buf.len() as u64;
+        Ok(mark)
+    }
+
+    pub(crate) fn write(&mut self, event: &GameEvent) -> io::Result<u64> {
+        self.write_all(std::slice::from_ref(event))
+    }
+
+    /// Drop everything written after `mark`
+    pub(crate) fn truncate(&mut self, mark: u64) -> io::Result<()> {
+        self.file.set_len(mark)?;
+        self.file.sync_data()?;
+        self.len = mark;
+        Ok(())
+    }
+
+    /// Replace the round's events, after a rewind
+    pub(crate) fn rewrite(&mut self, events: &[GameEvent]) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let _ = fs::remove_file(&tmp);
+        let mut writer = Writer::open(&tmp)?;
+        writer.write_all(events)?;
+        fs::rename(&tmp, &self.path)?;
+        if let Some(dir) = self.path.parent() {
+            sync_dir(dir)?;
+        }
+        writer.path = self.path.clone();
+        *self = writer;
+        Ok(())
+    }
+}
+
+/// Read the events synced to a round's file, with the length they take up.
+/// A last line without a newline was cut off by a crash, so its event was
+/// never applied and is ignored
+fn read(path: &Path) -> io::Result<(Vec<GameEvent>, u64)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut events = Vec::new();
+    let mut len = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let n = reader.read_until(b'\n', &mut line)?;
+        if n == 0 || line.last() != Some(&b'\n') {
+            return Ok((events, len));
+        }
+        let event = serde_json::from_slice(&line)
+            .map_err(|e| invalid(format!("{}: event {}: {}", path.display(), events.len(), e)))?;
+        events.push(event);
+        len += n as u64;
+    }
+}
+
+/// A directory holding one file for each round that hasn't been closed
+pub struct Journal {
+    dir: PathBuf,
+    next: u64,
+}
+
+impl Journal {
+    /// Open the journal in `dir`, creating it if needed
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Journal> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut journal = Journal { dir, next: 0 };
+        journal.next = journal.pending()?.last().map_or(0, |id| id.0 + 1);
+        Ok(journal)
+    }
+
+    fn path(&self, id: RoundId) -> PathBuf {
+        self.dir.join(format!("{}.wal", id.0))
+    }
+
+    /// Rounds that have been started and not closed, in order
+    pub fn pending(&self) -> io::Result<Vec<RoundId>> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let id = name.to_str().and_then(|name| name.strip_suffix(".wal"));
+            if let Some(id) = id.and_then(|id| id.parse().ok()) {
+                ids.push(RoundId(id));
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Journal `game` from now on. The events so far are written at once,
+    /// so this should be called before the bet is placed
+    pub fn begin(&mut self, game: &mut Game) -> io::Result<RoundId> {
+        if game.journal.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "game is already journaled",
+            ));
+        }
+        let id = RoundId(self.next);
+        let mut writer = Writer::open(&self.path(id))?;
+        writer.truncate(0)?;
+        writer.write_all(game.events())?;
+        sync_dir(&self.dir)?;
+        self.next += 1;
+        game.journal = Some(writer);
+        Ok(id)
+    }
+
+    /// Forget a round, once the player's chips have been stored
+    pub fn close(&mut self, id: RoundId) -> io::Result<()> {
+        fs::remove_file(self.path(id))?;
+        sync_dir(&self.dir)
+    }
+
+    /// Every event of a round that reached the disk
+    pub fn events(&self, id: RoundId) -> io::Result<Vec<GameEvent>> {
+        Ok(read(&self.path(id))?.0)
+    }
+
+    /// Rebuild every pending round. Settled rounds are finished, and the
+    /// rest resumed or refunded under `policy`. A round that can't be
+    /// rebuilt is reported as `Corrupt`, and the others are still
+    /// recovered. Each round stays pending until it is closed, as the
+    /// player still has to be stored
+    pub fn recover(&mut self, policy: Policy) -> io::Result<Vec<Recovered>> {
+        let mut recovered = Vec::new();
+        for id in self.pending()? {
+            match self.recover_round(id, policy) {
+                Ok(Some(round)) => recovered.push(round),
+                Ok(None) => {}
+                Err(e) => recovered.push(Recovered::Corrupt(id, e)),
+            }
+        }
+        Ok(recovered)
+    }
+
+    /// Rebuild one pending round, or close it if it never started
+    fn recover_round(&mut self, id: RoundId, policy: Policy) -> io::Result<Option<Recovered>> {
+        let path = self.path(id);
+        let (events, len) = read(&path)?;
+        // The crash came before `begin` returned, so there is no round
+        let player = match events.first() {
+            Some(GameEvent::Created { player, .. }) => player.clone(),
+            Some(_) => return Err(invalid(format!("round {} has no start", id))),
+            None => {
+                self.close(id)?;
+                return Ok(None);
+            }
+        };
+        let replay = || {
+            Game::from_events(&events)
+                .map_err(|e| invalid(format!("round {} does not replay: {:?}", id, e)))
+        };
+        let mut game = replay()?;
+
+        Ok(Some(match (game.state, policy) {
+            (State::Final, _) => {
+                let player = replay()?.finish().expect("game is in the final state");
+                Recovered::Settled(id, player, Box::new(game))
+            }
+            // The shoe ran out, and the bet has already been returned
+            (State::Error, _) => Recovered::Settled(id, game.player.clone(), Box::new(game)),
+            (_, Policy::Refund) => Recovered::Refunded(id, player, Box::new(game)),
+            (_, Policy::Resume) => {
+                let mut writer = Writer::open(&path)?;
+                writer.truncate(len)?;
+                game.journal = Some(writer);
+                Recovered::Resumed(id, Box::new(game))
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty journal in its own directory
+    fn journal(name: &str) -> (Journal, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("journal-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        (Journal::open(&dir).unwrap(), dir)
+    }
+
+    /// Player 10 6 against a 6 and a 10, with a 3 and a King to come
+    fn stacked(rules: Ruleset) -> Game {
+        let shoe = Shoe::Deck(Deck::stacked("10h 6c 6s 10d 3h Kc"));
+        Game::with_shoe(rules, Player::new(Money::chips(10)), shoe)
+    }
+
+    #[test]
+    fn resumed_round_plays_on() {
+        let (mut journal, dir) = journal("resume");
+        let mut game = stacked(Ruleset::default());
+        let id = journal.begin(&mut game).unwrap();
+        game.bet(Money::chips(2)).unwrap();
+        game.player(Action::Hit).unwrap();
+        let events = game.events().to_vec();
+        drop(game);
+
+        let mut game = match journal.recover(Policy::Resume).unwrap().remove(0) {
+            Recovered::Resumed(resumed, game) => {
+                assert_eq!(resumed, id);
+                game
+            }
+            _ => panic!("round was not resumed"),
+        };
+        assert_eq!(game.events(), &events[..]);
+        game.player(Action::Stand).unwrap();
+        let view = game.dealer().unwrap();
+        assert_eq!(view.state, State::Final);
+        assert_eq!(journal.events(id).unwrap(), game.events());
+        assert_eq!(game.finish().unwrap().chips, Money::chips(12));
+
+        journal.close(id).unwrap();
+        assert_eq!(journal.pending().unwrap(), vec![]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn settled_round_is_paid_out() {
+        let (mut journal, dir) = journal("settled");
+        let mut game = stacked(Ruleset::default());
+        journal.begin(&mut game).unwrap();
+        game.bet(Money::chips(2)).unwrap();
+        game.player(Action::Hit).unwrap();
+        game.player(Action::Stand).unwrap();
+        game.dealer().unwrap();
+        drop(game);
+
+        // Under either policy, as the dealer had already settled the round
+        for &policy in &[Policy::Resume, Policy::Refund] {
+            match journal.recover(policy).unwrap().remove(0) {
+                Recovered::Settled(_, player, game) => {
+                    assert_eq!(player.chips, Money::chips(12));
+                    assert_eq!(game.view().state, State::Final);
+                }
+                _ => panic!("round was not settled under {:?}", policy),
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn round_where_the_shoe_ran_out_is_settled() {
+        let (mut journal, dir) = journal("error");
+        let shoe = Shoe::Deck(Deck::stacked("9h"));
+        let mut game = Game::with_shoe(Ruleset::default(), Player::new(Money::chips(10)), shoe);
+        journal.begin(&mut game).unwrap();
+        assert_eq!(game.bet(Money::chips(2)), Err(game::Error::Fatal));
+        drop(game);
+
+        match journal.recover(Policy::Resume).unwrap().remove(0) {
+            Recovered::Settled(_, player, game) => {
+                assert_eq!(player.chips, Money::chips(10));
+                assert_eq!(game.view().state, State::Error);
+            }
+            _ => panic!("round was not settled"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cut_off_last_line_is_ignored() {
+        let (mut journal, dir) = journal("cut");
+        let mut game = stacked(Ruleset::default());
+        let id = journal.begin(&mut game).unwrap();
+        game.bet(Money::chips(2)).unwrap();
+        let events = game.events().to_vec();
+        drop(game);
+
+        // A crash partway through writing the next event
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(journal.path(id))
+            .unwrap();
+        file.write_all(b"{\"ActionTaken\":{\"act").unwrap();
+        drop(file);
+        assert_eq!(journal.events(id).unwrap(), events);
+
+        let mut game = match journal.recover(Policy::Resume).unwrap().remove(0) {
+            Recovered::Resumed(_, game) => game,
+            _ => panic!("round was not resumed"),
+        };
+        assert_eq!(game.view().state, State::Player(0));
+        // The partial line is dropped before anything else is written
+        game.player(Action::Hit).unwrap();
+        assert_eq!(journal.events(id).unwrap(), game.events());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rewind_rewrites_the_round() {
+        let (mut journal, dir) = journal("rewind");
+        let mut game = stacked(Ruleset::default().rewind(true));
+        let id = journal.begin(&mut game).unwrap();
+        game.bet(Money::chips(2)).unwrap();
+        let checkpoint = game.checkpoint().unwrap();
+        game.player(Action::Stand).unwrap();
+        game.dealer().unwrap();
+
+        game.rewind(&checkpoint).unwrap();
+        let events = game.events().to_vec();
+        assert_eq!(journal.events(id).unwrap(), events);
+        assert!(!journal.path(id).with_extension("tmp").exists());
+        drop(game);
+
+        // Recovery finds the round at the checkpoint, not settled
+        match journal.recover(Policy::Refund).unwrap().remove(0) {
+            Recovered::Refunded(_, player, game) => {
+                assert_eq!(player.chips, Money::chips(10));
+                assert_eq!(game.events(), &events[..]);
+            }
+            _ => panic!("round was not refunded"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recovery_goes_past_a_corrupt_round() {
+        let (mut journal, dir) = journal("corrupt");
+        for _ in 0..2 {
+            let shoe = Shoe::Deck(Deck::stacked("10h 6c 9s 10d"));
+            let mut game = Game::with_shoe(Ruleset::default(), Player::new(Money::chips(10)), shoe);
+            journal.begin(&mut game).unwrap();
+            game.bet(Money::chips(2)).unwrap();
+        }
+        fs::write(journal.path(RoundId(0)), "not an event\n").unwrap();
+
+        let recovered = journal.recover(Policy::Refund).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        match &recovered[..] {
+            [Recovered::Corrupt(RoundId(0), _), Recovered::Refunded(RoundId(1), player, _)] => {
+                assert_eq!(player.chips, Money::chips(10))
+            }
+            _ => panic!("recovered {} rounds", recovered.len()),
+        }
+    }
+}
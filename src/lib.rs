@@ -1,14 +1,40 @@
 //! Provably fair blackjack, implemented in Rust
+pub mod audit;
+pub mod binlog;
 pub mod card;
+pub mod chain;
 pub mod deck;
+pub mod delta;
+pub mod event;
 pub mod game;
+pub mod history;
+pub mod infinite;
+pub mod journal;
+pub mod limits;
+pub mod mental;
+pub mod money;
+pub mod observer;
 pub mod player;
+pub mod receipt;
+pub mod replay;
+pub mod responsible;
+pub mod rewind;
 pub mod rules;
+pub mod session;
+pub mod snapshot;
+pub mod verify;
+pub mod visibility;
+pub mod wallet;
+pub mod wire;
 
+pub mod battery;
 pub mod pcg;
+pub mod rng;
+pub mod stats;
 
 pub use card::*;
-pub use deck::Deck;
+pub use deck::{Deck, Shoe};
 pub use game::{Action, Game, Outcome, State, View};
+pub use money::{Money, Rounding};
 pub use player::*;
-pub use rules::Ruleset;
+pub use rules::{Decks, Ruleset};
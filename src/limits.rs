@@ -0,0 +1,231 @@
+//! Table limits
+//!
+//! A table only accepts bets between its minimum and maximum, in multiples of
+//! its increment, and may cap the extra bet put up to double or split. Side
+//! bets have their own range. Any of these can be left open
+use super::money::Money;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Limits {
+    pub min: Money,
+    pub max: Option<Money>,
+    /// Every bet must be a multiple of the increment
+    pub increment: Money,
+    /// Largest extra bet put up to double
+    pub double: Option<Money>,
+    /// Largest extra bet put up to split
+    pub split: Option<Money>,
+    pub side_min: Money,
+    pub side_max: Option<Money>,
+}
+
+/// The limit a bet broke, with the amount it was held to
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Deserialize, Serialize)]
+pub enum Limit {
+    Minimum(Money),
+    Maximum(Money),
+    /// The bet isn't a multiple of the increment
+    Increment(Money),
+    Double(Money),
+    Split(Money),
+    SideMinimum(Money),
+    SideMaximum(Money),
+}
+
+impl Default for Limits {
+    /// Any bet of at least one minor unit
+    fn default() -> Limits {
+        Limits {
+            min: Money::ZERO,
+            max: None,
+            increment: Money::minor(1),
+            double: None,
+            split: None,
+            side_min: Money::ZERO,
+            side_max: None,
+        }
+    }
+}
+
+impl Limits {
+    /// A table taking bets from `min` to `max`, in whole chips
+    pub fn new(min: Money, max: Money) -> Limits {
+        assert!(min <= max);
+        Limits {
+            min,
+            max: Some(max),
+            increment: Money::chips(1),
+            ..Limits::default()
+        }
+    }
+
+    pub fn increment(mut self, increment: Money) -> Limits {
+        assert!(!increment.is_zero());
+        self.increment = increment;
+        self
+    }
+
+    pub fn double(mut self, max: Money) -> Limits {
+        self.double = Some(max);
+        self
+    }
+
+    pub fn split(mut self, max: Money) -> Limits {
+        self.split = Some(max);
+        self
+    }
+
+    pub fn side(mut self, min: Money, max: Money) -> Limits {
+        assert!(min <= max);
+        self.side_min = min;
+        self.side_max = Some(max);
+        self
+    }
+
+    /// Check the initial bet on a round
+    pub fn check_bet(&self, bet: Money) -> Result<(), Limit> {
+        match self.max {
+            _ if bet < self.min => Err(Limit::Minimum(self.min)),
+            Some(max) if bet > max => Err(Limit::Maximum(max)),
+            _ => self.check_increment(bet),
+        }
+    }
+
+    /// Check the extra bet put up to double
+    pub fn check_double(&self, extra: Money) -> Result<(), Limit> {
+        match self.double {
+            Some(max) if extra > max => Err(Limit::Double(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the extra bet put up to split
+    pub fn check_split(&self, extra: Money) -> Result<(), Limit> {
+        match self.split {
+            Some(max) if extra > max => Err(Limit::Split(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check a side bet. The engine deals no side bets itself, but a table
+    /// offering them should hold them to these limits
+    pub fn check_side(&self, bet: Money) -> Result<(), Limit> {
+        match self.side_max {
+            _ if bet < self.side_min => Err(Limit::SideMinimum(self.side_min)),
+            Some(max) if bet > max => Err(Limit::SideMaximum(max)),
+            _ => self.check_increment(bet),
+        }
+    }
+
+    fn check_increment(&self, bet: Money) -> Result<(), Limit> {
+        if bet.as_minor() % self.increment.as_minor() == 0 {
+            Ok(())
+        } else {
+            Err(Limit::Increment(self.increment))
+        }
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Minimum(m) => write!(f, "bet is below the table minimum of {}", m),
+            Limit::Maximum(m) => write!(f, "bet is above the table maximum of {}", m),
+            Limit::Increment(m) => write!(f, "bet is not a multiple of {}", m),
+            Limit::Double(m) => write!(f, "doubling would bet more than {}", m),
+            Limit::Split(m) => write!(f, "splitting would bet more than {}", m),
+            Limit::SideMinimum(m) => write!(f, "side bet is below the minimum of {}", m),
+            Limit::SideMaximum(m) => write!(f, "side bet is above the maximum of {}", m),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Error;
+    use crate::*;
+
+    fn table() -> Limits {
+        Limits::new(Money::chips(5), Money::chips(100))
+            .double(Money::chips(10))
+            .split(Money::chips(20))
+    }
+
+    /// A game dealing a pair of eights, to double or split
+    fn eights(limits: Limits) -> Game {
+        let shoe = Shoe::Deck(Deck::stacked("8h 10c 8s 7d 2h 3c"));
+        let rules = Ruleset::default().limits(limits);
+        Game::with_shoe(rules, Player::new(Money::chips(1000)), shoe)
+    }
+
+    #[test]
+    fn bets_are_held_to_the_table() {
+        let limit = |bet| eights(table()).bet(bet).map(|_| ());
+        assert_eq!(
+            limit(Money::chips(4)),
+            Err(Error::Limit(Limit::Minimum(Money::chips(5))))
+        );
+        assert_eq!(
+            limit(Money::chips(101)),
+            Err(Error::Limit(Limit::Maximum(Money::chips(100))))
+        );
+        assert_eq!(
+            limit(Money::minor(550)),
+            Err(Error::Limit(Limit::Increment(Money::chips(1))))
+        );
+        assert_eq!(limit(Money::chips(5)), Ok(()));
+        assert_eq!(limit(Money::chips(100)), Ok(()));
+
+        let halves = table().increment(Money::minor(50));
+        assert_eq!(halves.check_bet(Money::minor(550)), Ok(()));
+        assert_eq!(
+            halves.check_bet(Money::minor(525)),
+            Err(Limit::Increment(Money::minor(50)))
+        );
+    }
+
+    #[test]
+    fn doubles_and_splits_are_held_to_the_table() {
+        let mut game = eights(table());
+        game.bet(Money::chips(15)).unwrap();
+        assert_eq!(
+            game.player(Action::Double),
+            Err(Error::Limit(Limit::Double(Money::chips(10))))
+        );
+        // Nothing was taken for the rejected double
+        assert_eq!(game.player.chips, Money::chips(985));
+        game.player(Action::Split).unwrap();
+
+        let mut game = eights(table());
+        game.bet(Money::chips(25)).unwrap();
+        assert_eq!(
+            game.player(Action::Split),
+            Err(Error::Limit(Limit::Split(Money::chips(20))))
+        );
+        assert_eq!(game.player.chips, Money::chips(975));
+    }
+
+    #[test]
+    fn side_bets_and_open_limits() {
+        let limits = table().side(Money::chips(1), Money::chips(5));
+        assert_eq!(
+            limits.check_side(Money::ZERO),
+            Err(Limit::SideMinimum(Money::chips(1)))
+        );
+        assert_eq!(
+            limits.check_side(Money::chips(6)),
+            Err(Limit::SideMaximum(Money::chips(5)))
+        );
+        assert_eq!(limits.check_side(Money::chips(5)), Ok(()));
+
+        let open = Limits::default();
+        assert_eq!(open.check_bet(Money::minor(1)), Ok(()));
+        assert_eq!(open.check_bet(Money::chips(1_000_000)), Ok(()));
+        assert_eq!(open.check_double(Money::chips(1_000_000)), Ok(()));
+        assert_eq!(open.check_split(Money::chips(1_000_000)), Ok(()));
+    }
+}
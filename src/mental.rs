@@ -0,0 +1,301 @@
+//! The `mental` module implements a "mental poker" shuffle, so that neither
+//! the server nor the player knows the order of the shoe
+//!
+//! Cards are encoded as quadratic residues modulo a 2048-bit safe prime, and
+//! encrypted with SRA commutative encryption, `c = m^e mod p`. The server
+//! shuffles and encrypts the shoe, and then the client shuffles and encrypts
+//! the server's output. Because encryption commutes, a card is recovered by
+//! removing both layers of encryption in either order, and so a card is only
+//! ever revealed when both parties agree to decrypt it.
+//!
+//! Encoding every card as a square keeps all ciphertexts inside the subgroup
+//! of quadratic residues, which closes SRA's well known leak of one bit per
+//! card through the Legendre symbol.
+//!
+//! The server only ever holds its own key. The client's half of the protocol
+//! is behind the `Client` trait, attached to a game with `Game::set_client`,
+//! and is asked to remove its layer from one card at a time as each card is
+//! dealt. `LocalClient` stands in for the same computation performed on the
+//! player's machine
+use super::card::Card;
+use super::deck::{shuffle, Deck};
+use num_bigint::{BigUint, RandBigInt};
+use num_traits::Num;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// RFC 3526 2048-bit MODP group prime, `p = 2q + 1` with `q` also prime
+const MODP_2048: &str = "\
+FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E3404DD\
+EF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF0598DA48361C55D39A69163FA8FD24CF5F\
+83655D23DCA3AD961C62F356208552BB9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF6955817183995497CEA956AE515D2261898FA0510\
+15728E5A8AACAA68FFFFFFFFFFFFFFFF";
+
+fn prime() -> BigUint {
+    BigUint::from_str_radix(MODP_2048, 16).expect("valid prime")
+}
+
+/// Order of the subgroup of quadratic residues, `(p - 1) / 2`
+fn order() -> BigUint {
+    prime() >> 1
+}
+
+/// Encode a card as a quadratic residue: `(code + 2)^2 mod p`
+fn encode(card: Card) -> BigUint {
+    let m = BigUint::from(card.code() as u32 + 2);
+    &m * &m
+}
+
+fn decode(m: &BigUint) -> Option<Card> {
+    (0..52)
+        .filter_map(Card::from_code)
+        .find(|&card| encode(card) == *m)
+}
+
+/// One party's SRA key pair. Both exponents are inverses modulo the order of
+/// the quadratic residue subgroup, so `decrypt(encrypt(m)) == m`
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Key {
+    e: BigUint,
+    d: BigUint,
+}
+
+impl Key {
+    pub fn generate<R: Rng>(rng: &mut R) -> Key {
+        let q = order();
+        let e = rng.gen_biguint_range(&BigUint::from(3u32), &q);
+        // q is prime, so e^-1 = e^(q-2) mod q
+        let d = e.modpow(&(&q - 2u32), &q);
+        Key { e, d }
+    }
+
+    pub fn encrypt(&self, m: &BigUint) -> BigUint {
+        m.modpow(&self.e, &prime())
+    }
+
+    pub fn decrypt(&self, c: &BigUint) -> BigUint {
+        c.modpow(&self.d, &prime())
+    }
+}
+
+/// The player's half of the protocol. The server never holds the client's
+/// key: it hands over the shoe to be shuffled, and then one ciphertext at a
+/// time as each card is dealt
+pub trait Client {
+    /// Second pass of the shuffle: permute and encrypt the server's shoe
+    fn shuffle(&mut self, cards: &mut [BigUint], rng: &mut dyn RngCore);
+
+    /// Remove the client's layer of encryption from the card being dealt
+    fn reveal(&mut self, c: &BigUint) -> BigUint;
+}
+
+/// In-process stand-in for the player's half of the protocol
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct LocalClient {
+    key: Key,
+}
+
+impl LocalClient {
+    pub fn new<R: Rng>(rng: &mut R) -> LocalClient {
+        LocalClient {
+            key: Key::generate(rng),
+        }
+    }
+}
+
+impl Client for LocalClient {
+    fn shuffle(&mut self, cards: &mut [BigUint], mut rng: &mut dyn RngCore) {
+        shuffle(cards, &mut rng);
+        for c in cards.iter_mut() {
+            *c = self.key.encrypt(c);
+        }
+    }
+
+    fn reveal(&mut self, c: &BigUint) -> BigUint {
+        self.key.decrypt(c)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Phase {
+    /// Shuffled and encrypted by the server only
+    Server,
+    /// Shuffled and encrypted by both parties, ready to deal
+    Shuffled,
+}
+
+/// Why `MentalDeck::reveal` refused the client's decryption of a card
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RevealError {
+    /// There is no card waiting to be revealed: the client hasn't shuffled,
+    /// the shoe is empty, or the next card is already revealed
+    NotReady,
+    /// The card doesn't decode, meaning one of the parties has cheated
+    Integrity,
+}
+
+/// A shoe that has been shuffled and encrypted by both parties. Only the
+/// server's key is kept, so the cards can't be read until the client
+/// reveals them one at a time
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct MentalDeck {
+    server: Key,
+    cards: Vec<BigUint>,
+    phase: Phase,
+    /// The client's decryption of the next card, once it has been revealed
+    share: Option<BigUint>,
+}
+
+impl MentalDeck {
+    /// First pass of the shuffle: the server encodes a fresh shoe of `decks`
+    /// decks, then shuffles and encrypts it
+    pub fn new<R: Rng>(decks: usize, rng: &mut R) -> MentalDeck {
+        let server = Key::generate(rng);
+        let mut cards = Deck::new(decks)
+            .cards()
+            .iter()
+            .map(|&card| encode(card))
+            .collect::<Vec<_>>();
+        shuffle(&mut cards, rng);
+        for c in cards.iter_mut() {
+            *c = server.encrypt(c);
+        }
+        MentalDeck {
+            server,
+            cards,
+            phase: Phase::Server,
+            share: None,
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Second pass of the shuffle, performed by the client. Does nothing if
+    /// the client has already shuffled
+    pub fn client_shuffle<R: Rng>(&mut self, client: &mut dyn Client, rng: &mut R) {
+        if self.phase == Phase::Server {
+            client.shuffle(&mut self.cards, rng);
+            self.phase = Phase::Shuffled;
+        }
+    }
+
+    /// The next card, still encrypted, if the client must reveal it before
+    /// it can be dealt
+    pub fn unrevealed(&self) -> Option<&BigUint> {
+        match (self.phase, &self.share) {
+            (Phase::Shuffled, None) => self.cards.last(),
+            _ => None,
+        }
+    }
+
+    /// Accept the client's decryption of the next card, which is kept so
+    /// that anyone holding the server's key can check the card dealt
+    pub fn reveal(&mut self, share: BigUint) -> Result<Card, RevealError> {
+        if self.unrevealed().is_none() {
+            return Err(RevealError::NotReady);
+        }
+        let card = decode(&self.server.decrypt(&share)).ok_or(RevealError::Integrity)?;
+        self.share = Some(share);
+        Ok(card)
+    }
+
+    /// Deal the next card, once it has been revealed. Returns `None` if the
+    /// shoe is empty or the card hasn't been revealed yet
+    pub fn draw(&mut self) -> Option<Card> {
+        let card = self.peek()?;
+        self.cards.pop();
+        self.share = None;
+        Some(card)
+    }
+
+    /// The next card without removing it from the shoe, once it has been
+    /// revealed
+    pub fn peek(&self) -> Option<Card> {
+        self.share
+            .as_ref()
+            .and_then(|share| decode(&self.server.decrypt(share)))
+    }
+
+    pub fn count(&self) -> usize {
+        self.cards.len()
+    }
+
+    /// Sha256 of the encrypted shoe, which either party may publish as a
+    /// commitment without revealing any cards
+    pub fn sha256(&self) -> String {
+        let mut hasher = Sha256::default();
+        for c in &self.cards {
+            // Pad every ciphertext to the width of the prime
+            let bytes = c.to_bytes_be();
+            hasher.input(&[0u8; 256][bytes.len()..]);
+            hasher.input(bytes);
+        }
+        format!("{:0x}", hasher.result())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcg::PCG32;
+
+    fn shuffled(rng: &mut PCG32) -> (MentalDeck, LocalClient) {
+        let mut client = LocalClient::new(rng);
+        let mut deck = MentalDeck::new(1, rng);
+        deck.client_shuffle(&mut client, rng);
+        (deck, client)
+    }
+
+    #[test]
+    fn shuffle_and_reveal_round_trip() {
+        let mut rng = PCG32::new(5, 9);
+        let (mut deck, mut client) = shuffled(&mut rng);
+        assert_eq!(deck.phase(), Phase::Shuffled);
+
+        let mut dealt = Vec::new();
+        while let Some(c) = deck.unrevealed().cloned() {
+            // Nothing can be dealt before the client reveals it
+            assert_eq!(deck.peek(), None);
+            let card = deck.reveal(client.reveal(&c)).unwrap();
+            assert_eq!(deck.reveal(client.reveal(&c)), Err(RevealError::NotReady));
+            assert_eq!(deck.draw(), Some(card));
+            dealt.push(card);
+        }
+        assert_eq!(deck.count(), 0);
+        assert_eq!(deck.draw(), None);
+
+        let mut sorted = Deck::new(1).cards().to_vec();
+        assert_ne!(dealt, sorted);
+        dealt.sort_by_key(|card| card.code());
+        sorted.sort_by_key(|card| card.code());
+        assert_eq!(dealt, sorted);
+    }
+
+    #[test]
+    fn reveal_needs_both_shuffles() {
+        let mut rng = PCG32::new(5, 9);
+        let mut deck = MentalDeck::new(1, &mut rng);
+        assert_eq!(deck.unrevealed(), None);
+        let share = deck.cards[0].clone();
+        assert_eq!(deck.reveal(share), Err(RevealError::NotReady));
+    }
+
+    #[test]
+    fn tampered_card_is_rejected() {
+        let mut rng = PCG32::new(5, 9);
+        let (mut deck, mut client) = shuffled(&mut rng);
+        let top = deck.cards.last_mut().unwrap();
+        *top = (&*top * 2u32) % prime();
+
+        let c = deck.unrevealed().cloned().unwrap();
+        assert_eq!(deck.reveal(client.reveal(&c)), Err(RevealError::Integrity));
+        assert_eq!(deck.peek(), None);
+        assert_eq!(deck.draw(), None);
+        assert_eq!(deck.count(), 52);
+    }
+}
@@ -0,0 +1,246 @@
+//! Fixed-point amounts of money
+//!
+//! `Money` counts minor units, `Money::SCALE` to the chip, so that payouts
+//! like 3:2 on an odd bet don't have to go through floating point. Every
+//! operation is checked: the `checked_*` methods return `None` on overflow,
+//! and the operators panic rather than wrap.
+//!
+//! A payout that doesn't come to a whole chip is settled according to the
+//! table's `Rounding` policy
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, Sub, SubAssign};
+
+/// An amount of money, in minor units. Serialized as the number of minor units
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize,
+)]
+#[serde(transparent)]
+pub struct Money(u64);
+
+impl Money {
+    /// Minor units in one chip
+    pub const SCALE: u64 = 100;
+
+    pub const ZERO: Money = Money(0);
+
+    /// A whole number of chips. Panics if it can't be represented
+    pub fn chips(chips: u64) -> Money {
+        Money(chips.checked_mul(Money::SCALE).expect("money overflow"))
+    }
+
+    pub const fn minor(units: u64) -> Money {
+        Money(units)
+    }
+
+    /// The amount in minor units
+    pub fn as_minor(self) -> u64 {
+        self.0
+    }
+
+    /// Number of whole chips, ignoring any fraction
+    pub fn whole(self) -> u64 {
+        self.0 / Money::SCALE
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, rhs: Money) -> Option<Money> {
+        self.0.checked_add(rhs.0).map(Money)
+    }
+
+    pub fn checked_sub(self, rhs: Money) -> Option<Money> {
+        self.0.checked_sub(rhs.0).map(Money)
+    }
+
+    pub fn checked_mul(self, rhs: u64) -> Option<Money> {
+        self.0.checked_mul(rhs).map(Money)
+    }
+
+    pub fn saturating_sub(self, rhs: Money) -> Money {
+        Money(self.0.saturating_sub(rhs.0))
+    }
+
+    /// `self * num / den`, rounded down to a minor unit. Panics if `den` is
+    /// zero or the result can't be represented
+    pub fn ratio(self, num: u64, den: u64) -> Money {
+        let units = self.0 as u128 * num as u128 / den as u128;
+        Money(u64::try_from(units).expect("money overflow"))
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+    fn add(self, rhs: Money) -> Money {
+        self.checked_add(rhs).expect("money overflow")
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, rhs: Money) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+    fn sub(self, rhs: Money) -> Money {
+        self.checked_sub(rhs).expect("money underflow")
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, rhs: Money) {
+        *self = *self - rhs;
+    }
+}
+
+impl Mul<u64> for Money {
+    type Output = Money;
+    fn mul(self, rhs: u64) -> Money {
+        self.checked_mul(rhs).expect("money overflow")
+    }
+}
+
+/// Rounds down to a minor unit
+impl Div<u64> for Money {
+    type Output = Money;
+    fn div(self, rhs: u64) -> Money {
+        Money(self.0 / rhs)
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
+
+/// Written as chips with two decimal places, e.g. `12.50`
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(&format!("{}.{:02}", self.whole(), self.0 % Money::SCALE))
+    }
+}
+
+/// How to pay out an amount that isn't a whole number of chips, such as 3:2
+/// on an odd bet
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Deserialize, Serialize)]
+pub enum Rounding {
+    /// Pay whole chips only, and the house keeps the fraction
+    #[default]
+    Down,
+    /// Pay down to half a chip, and the house keeps anything smaller
+    HalfChip,
+    /// Pay whole chips, and owe the player the fraction. It is kept in
+    /// `Player::carry` and added to their next uneven payout
+    Carry,
+}
+
+impl Rounding {
+    /// Split `amount` into what is paid now and the remainder. Under `Carry`
+    /// the remainder is owed to the player, otherwise it is kept by the house
+    pub fn round(self, amount: Money) -> (Money, Money) {
+        let unit = match self {
+            Rounding::Down | Rounding::Carry => Money::SCALE,
+            Rounding::HalfChip => Money::SCALE / 2,
+        };
+        let paid = Money(amount.0 / unit * unit);
+        (paid, amount - paid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn rounds_to_the_policy() {
+        let amount = Money::minor(775);
+        assert_eq!(
+            Rounding::Down.round(amount),
+            (Money::chips(7), Money::minor(75))
+        );
+        assert_eq!(
+            Rounding::Carry.round(amount),
+            (Money::chips(7), Money::minor(75))
+        );
+        assert_eq!(
+            Rounding::HalfChip.round(amount),
+            (Money::minor(750), Money::minor(25))
+        );
+        assert_eq!(
+            Rounding::HalfChip.round(Money::minor(749)),
+            (Money::chips(7), Money::minor(49))
+        );
+        assert_eq!(
+            Rounding::HalfChip.round(Money::chips(3)),
+            (Money::chips(3), Money::ZERO)
+        );
+    }
+
+    #[test]
+    fn three_to_two_on_odd_bets() {
+        assert_eq!(Money::chips(3).ratio(3, 2), Money::minor(450));
+        assert_eq!(Money::chips(5).ratio(5, 2), Money::minor(1250));
+        // A fraction of a minor unit is dropped
+        assert_eq!(Money::minor(1).ratio(3, 2), Money::minor(1));
+        assert_eq!(Money::minor(3).ratio(3, 2), Money::minor(4));
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        assert_eq!(
+            Money::chips(5).checked_sub(Money::chips(2)),
+            Some(Money::chips(3))
+        );
+        assert_eq!(Money::chips(2).checked_sub(Money::chips(5)), None);
+        assert_eq!(Money::chips(2).saturating_sub(Money::chips(5)), Money::ZERO);
+        assert_eq!(Money::minor(u64::MAX).checked_add(Money::minor(1)), None);
+        assert_eq!(Money::minor(u64::MAX).checked_mul(2), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "money underflow")]
+    fn subtraction_below_zero_panics() {
+        let _ = Money::chips(2) - Money::chips(5);
+    }
+
+    /// Play a blackjack on a bet of 3 chips
+    fn blackjack(rules: Ruleset, player: Player) -> Player {
+        let shoe = Shoe::Deck(Deck::stacked("Ah 9c Kh Kd"));
+        let mut game = Game::with_shoe(rules, player, shoe);
+        let view = game.bet(Money::chips(3)).unwrap();
+        assert_eq!(view.state, State::Dealer);
+        game.dealer().unwrap();
+        game.finish().unwrap()
+    }
+
+    #[test]
+    fn carry_accumulates_across_rounds() {
+        let rules = Ruleset::default().rounding(Rounding::Carry);
+        let player = blackjack(rules, Player::new(Money::chips(10)));
+        assert_eq!(player.chips, Money::chips(14));
+        assert_eq!(player.carry, Money::minor(50));
+
+        // The half chip owed makes the next blackjack come to whole chips
+        let player = blackjack(rules, player);
+        assert_eq!(player.chips, Money::chips(19));
+        assert_eq!(player.carry, Money::ZERO);
+
+        // Other policies never owe anything
+        let rules = rules.rounding(Rounding::Down);
+        let player = blackjack(rules, blackjack(rules, Player::new(Money::chips(10))));
+        assert_eq!(player.chips, Money::chips(18));
+        assert_eq!(player.carry, Money::ZERO);
+        let rules = rules.rounding(Rounding::HalfChip);
+        let player = blackjack(rules, Player::new(Money::chips(10)));
+        assert_eq!(player.chips, Money::minor(1450));
+        assert_eq!(player.carry, Money::ZERO);
+    }
+}
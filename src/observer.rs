@@ -0,0 +1,148 @@
+//! Hooks for reacting to a game as it is played
+//!
+//! An `Observer` registered with `Game::add_observer` is called for every
+//! `GameEvent` the engine emits, so that sound, animation, logging or
+//! metrics can follow the game without wrapping each call or diffing views.
+//! Every callback has an empty default, so an observer only implements the
+//! ones it needs
+use super::event::{GameEvent, Seat};
+use super::game::Outcome;
+use super::*;
+
+#[allow(unused_variables)]
+pub trait Observer {
+    /// Called for every event, before the more specific callbacks below
+    fn on_event(&mut self, event: &GameEvent) {}
+
+    /// The shoe was shuffled, when the game was created or by the player
+    fn on_shuffle(&mut self) {}
+
+    fn on_bet(&mut self, bet: Money) {}
+
+    /// A card was dealt to the dealer, or to the player's hand at `Seat::Player(idx)`
+    fn on_card_dealt(&mut self, to: Seat, card: Card) {}
+
+    /// The player took `action` on hand `hand`
+    fn on_action(&mut self, hand: usize, action: Action) {}
+
+    /// The dealer drew a card while playing out their hand
+    fn on_dealer_draw(&mut self, card: Card) {}
+
+    fn on_state(&mut self, state: State) {}
+
+    /// Outcomes of each of the player's hands, in order
+    fn on_settle(&mut self, scores: &[Outcome]) {}
+
+    /// The shoe ran out, and the bet was returned
+    fn on_exhausted(&mut self) {}
+}
+
+/// Dispatch an event that has just been applied to a game now in `state`
+pub(crate) fn notify(observer: &mut dyn Observer, event: &GameEvent, state: State) {
+    observer.on_event(event);
+    match event {
+        GameEvent::Created { .. } | GameEvent::Revealed { .. } => {}
+        GameEvent::Shuffled { .. } => observer.on_shuffle(),
+        GameEvent::BetPlaced { bet, .. } => observer.on_bet(*bet),
+        GameEvent::CardDealt { to, card } => observer.on_card_dealt(*to, *card),
+        GameEvent::ActionTaken { action } => {
+            // Actions don't change the state, so it still points at the hand
+            if let State::Player(idx) = state {
+                observer.on_action(idx, *action);
+            }
+        }
+        GameEvent::DealerDrew { card } => observer.on_dealer_draw(*card),
+        GameEvent::StateChanged { state } => observer.on_state(*state),
+        GameEvent::Settled { scores, .. } => observer.on_settle(scores),
+        GameEvent::Exhausted => observer.on_exhausted(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Records every callback, shared with the test
+    #[derive(Clone, Default)]
+    struct Recorder {
+        events: Rc<RefCell<Vec<GameEvent>>>,
+        calls: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Observer for Recorder {
+        fn on_event(&mut self, event: &GameEvent) {
+            self.events.borrow_mut().push(event.clone());
+        }
+
+        fn on_bet(&mut self, bet: Money) {
+            self.calls.borrow_mut().push(format!("bet {}", bet));
+        }
+
+        fn on_card_dealt(&mut self, to: Seat, card: Card) {
+            self.calls
+                .borrow_mut()
+                .push(format!("{:?} {}", to, card.notation()));
+        }
+
+        fn on_action(&mut self, hand: usize, action: Action) {
+            self.calls
+                .borrow_mut()
+                .push(format!("{:?} on {}", action, hand));
+        }
+
+        fn on_dealer_draw(&mut self, card: Card) {
+            self.calls
+                .borrow_mut()
+                .push(format!("dealer draws {}", card.notation()));
+        }
+
+        fn on_state(&mut self, state: State) {
+            self.calls.borrow_mut().push(format!("{:?}", state));
+        }
+
+        fn on_settle(&mut self, scores: &[Outcome]) {
+            self.calls.borrow_mut().push(format!("{:?}", scores));
+        }
+    }
+
+    #[test]
+    fn observer_sees_events_in_emit_order() {
+        let shoe = Shoe::Deck(Deck::stacked("8h 10c 8s 6d 3h 2c Kh 9d"));
+        let mut game = Game::with_shoe(Ruleset::default(), Player::new(Money::chips(20)), shoe);
+        let recorder = Recorder::default();
+        game.add_observer(recorder.clone());
+        // Only events from registration on are seen
+        let from = game.events().len();
+
+        game.bet(Money::chips(2)).unwrap();
+        game.player(Action::Split).unwrap();
+        game.player(Action::Double).unwrap();
+        game.player(Action::Stand).unwrap();
+        game.dealer().unwrap();
+
+        assert_eq!(*recorder.events.borrow(), &game.events()[from..]);
+        let calls = recorder.calls.borrow();
+        assert_eq!(
+            calls[..7],
+            [
+                "bet 2.00",
+                "Player(0) 8h",
+                "Dealer 10c",
+                "Player(0) 8s",
+                "Dealer 6d",
+                "Player(0)",
+                "Split on 0",
+            ]
+        );
+        let settled = format!(
+            "{:?}",
+            [Outcome::Win(Money::chips(8)), Outcome::Win(Money::chips(4))]
+        );
+        assert_eq!(
+            calls[calls.len() - 3..],
+            ["dealer draws 9d".to_string(), settled, "Final".to_string()]
+        );
+    }
+}
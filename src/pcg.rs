@@ -1,8 +1,23 @@
 //! PCG psuedorandom number generator
 //! Adapted from http://www.pcg-random.org/
+//!
+//! The underlying LCG, stream selection, `advance` and the XSH-RR output
+//! permutation follow the reference implementation exactly, so `next_u32`
+//! matches `pcg32_random_r` for the same state and stream. `new` and
+//! `from_seed` set the state directly, without the extra steps taken by the
+//! reference `pcg32_srandom_r`
 
-use rand_core::{impls, Error, RngCore};
+use rand_core::{impls, Error, RngCore, SeedableRng};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+/// Streams are 63 bits: the increment is `(seq << 1) | 1`, so the top bit of
+/// a sequence number never reaches the generator
+const STREAM_MASK: u64 = u64::MAX >> 1;
 
 #[derive(Copy, Clone, PartialEq, PartialOrd)]
 pub struct PCG32 {
@@ -10,20 +25,54 @@ pub struct PCG32 {
     inc: u64,
 }
 
+/// A `PCG32` state and stream. Displayed, parsed and serialized as 32 hex
+/// digits: the state followed by the stream. The top bit of the stream is
+/// always cleared, so two seeds that select the same generator compare, print
+/// and hash the same
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
 pub struct PCG32Seed(u64, u64);
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseSeedError;
+
 impl PCG32Seed {
     pub fn new(state: u64, seq: u64) -> PCG32Seed {
-        PCG32Seed(state, seq)
+        PCG32Seed(state, seq & STREAM_MASK)
     }
 
-    pub fn sha256(&self) -> String {
+    pub fn state(&self) -> u64 {
+        self.0
+    }
+
+    pub fn stream(&self) -> u64 {
+        self.1
+    }
+
+    /// The same state on a different stream
+    pub fn with_stream(self, seq: u64) -> PCG32Seed {
+        PCG32Seed::new(self.0, seq)
+    }
+
+    /// Little-endian state followed by little-endian sequence, the same
+    /// layout used by `SeedableRng::from_seed`
+    pub fn to_bytes(&self) -> [u8; 16] {
         let mut buf: [u8; 16] = [0u8; 16];
         buf[0..8].copy_from_slice(&self.0.to_le_bytes());
         buf[8..].copy_from_slice(&self.1.to_le_bytes());
+        buf
+    }
+
+    pub fn from_bytes(buf: [u8; 16]) -> PCG32Seed {
+        let mut state = [0u8; 8];
+        let mut seq = [0u8; 8];
+        state.copy_from_slice(&buf[0..8]);
+        seq.copy_from_slice(&buf[8..]);
+        PCG32Seed::new(u64::from_le_bytes(state), u64::from_le_bytes(seq))
+    }
+
+    pub fn sha256(&self) -> String {
         let mut hasher = Sha256::default();
-        hasher.input(buf);
+        hasher.input(self.to_bytes());
         format!("{:0x}", hasher.result())
     }
 }
@@ -46,21 +95,109 @@ impl PCG32 {
     pub fn to_seed(&self) -> PCG32Seed {
         PCG32Seed(self.state, self.inc >> 1)
     }
+
+    /// Stream currently selected by this generator
+    pub fn stream(&self) -> u64 {
+        self.inc >> 1
+    }
+
+    /// Switch to a different stream, keeping the current state. Generators
+    /// on different streams produce distinct sequences from the same state
+    pub fn set_stream(&mut self, seq: u64) {
+        self.inc = (seq << 1) | 1;
+    }
+
+    /// Jump forward `delta` steps in log(delta) time, as if `next_u32` had
+    /// been called `delta` times. Wrapping arithmetic means that jumping by
+    /// `delta.wrapping_neg()` steps backwards.
+    ///
+    /// See Brown, "Random Number Generation with Arbitrary Stride" (1994)
+    pub fn advance(&mut self, mut delta: u64) {
+        let mut cur_mult = MULTIPLIER;
+        let mut cur_plus = self.inc;
+        let mut acc_mult = 1u64;
+        let mut acc_plus = 0u64;
+        while delta > 0 {
+            if delta & 1 == 1 {
+                acc_mult = acc_mult.wrapping_mul(cur_mult);
+                acc_plus = acc_plus.wrapping_mul(cur_mult).wrapping_add(cur_plus);
+            }
+            cur_plus = cur_mult.wrapping_add(1).wrapping_mul(cur_plus);
+            cur_mult = cur_mult.wrapping_mul(cur_mult);
+            delta >>= 1;
+        }
+        self.state = acc_mult.wrapping_mul(self.state).wrapping_add(acc_plus);
+    }
+
+    /// Split one seed into non-overlapping blocks of `stride` outputs each,
+    /// returning a generator positioned at the start of block `idx`
+    pub fn block(seed: PCG32Seed, stride: u64, idx: u64) -> PCG32 {
+        let mut rng = PCG32::from_seed(seed);
+        rng.advance(stride.wrapping_mul(idx));
+        rng
+    }
+}
+
+impl fmt::Display for PCG32Seed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}{:016x}", self.0, self.1)
+    }
+}
+
+impl fmt::Display for ParseSeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "seed must be 32 hex digits")
+    }
+}
+
+impl std::error::Error for ParseSeedError {}
+
+impl FromStr for PCG32Seed {
+    type Err = ParseSeedError;
+
+    fn from_str(s: &str) -> Result<PCG32Seed, ParseSeedError> {
+        if s.len() != 32 || !s.is_char_boundary(16) {
+            return Err(ParseSeedError);
+        }
+        let state = u64::from_str_radix(&s[..16], 16).map_err(|_| ParseSeedError)?;
+        let seq = u64::from_str_radix(&s[16..], 16).map_err(|_| ParseSeedError)?;
+        Ok(PCG32Seed::new(state, seq))
+    }
+}
+
+impl Serialize for PCG32Seed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PCG32Seed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+impl SeedableRng for PCG32 {
+    type Seed = [u8; 16];
+
+    fn from_seed(seed: Self::Seed) -> PCG32 {
+        PCG32::from_seed(PCG32Seed::from_bytes(seed))
+    }
 }
 
 impl RngCore for PCG32 {
+    /// XSH-RR output permutation of the pre-step state
     fn next_u32(&mut self) -> u32 {
-        self.next_u64() as u32
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
     }
 
+    /// Two consecutive `next_u32` outputs, low word first
     fn next_u64(&mut self) -> u64 {
-        self.state = self
-            .state
-            .wrapping_mul(6364136223846793005)
-            .wrapping_add(self.inc);
-        let xor = ((self.state >> 18) ^ self.state) >> 27;
-        let rot = (self.state >> 59) as i64;
-        (xor >> rot) | (xor << ((-rot) & 31))
+        impls::next_u64_via_u32(self)
     }
 
     fn fill_bytes(&mut self, dest: &mut [u8]) {
@@ -68,6 +205,107 @@ impl RngCore for PCG32 {
     }
 
     fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
-        Ok(self.fill_bytes(dest))
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `pcg32_srandom_r(initstate, initseq)` from the reference
+    fn srandom(initstate: u64, initseq: u64) -> PCG32 {
+        let mut rng = PCG32::new(0, initseq);
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(initstate);
+        rng.next_u32();
+        rng
+    }
+
+    #[test]
+    fn reference_vectors() {
+        // pcg32-demo output for `pcg32_srandom_r(&rng, 42u, 54u)`
+        let mut rng = srandom(42, 54);
+        let expected = [
+            0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e,
+        ];
+        for &n in &expected {
+            assert_eq!(rng.next_u32(), n);
+        }
+    }
+
+    #[test]
+    fn next_u64_joins_two_outputs() {
+        let mut rng = srandom(42, 54);
+        assert_eq!(rng.next_u64(), 0x7b47f409_a15c02b7);
+        assert_eq!(rng.next_u64(), 0x83d2f293_ba1d3330);
+    }
+
+    #[test]
+    fn advance_matches_stepping() {
+        let seed = PCG32Seed::new(0x853c49e6748fea9b, 0xda3e39cb94b95bdb);
+        for &n in &[0u64, 1, 2, 7, 64, 1000, 12345] {
+            let mut stepped = PCG32::from_seed(seed);
+            for _ in 0..n {
+                stepped.next_u32();
+            }
+            let mut jumped = PCG32::from_seed(seed);
+            jumped.advance(n);
+            assert!(stepped == jumped, "advance({})", n);
+            assert_eq!(stepped.next_u32(), jumped.next_u32());
+        }
+    }
+
+    #[test]
+    fn advance_backwards() {
+        let mut rng = srandom(42, 54);
+        let start = rng;
+        rng.advance(100);
+        rng.advance(100u64.wrapping_neg());
+        assert!(rng == start);
+    }
+
+    #[test]
+    fn seed_round_trip() {
+        let seed = PCG32Seed::new(0x0123456789abcdef, 0x7edcba9876543210);
+        let text = seed.to_string();
+        assert_eq!(text, "0123456789abcdef7edcba9876543210");
+        assert_eq!(text.parse::<PCG32Seed>(), Ok(seed));
+
+        let json = serde_json::to_string(&seed).unwrap();
+        assert_eq!(json, format!("\"{}\"", text));
+        assert_eq!(serde_json::from_str::<PCG32Seed>(&json).unwrap(), seed);
+
+        assert_eq!(PCG32Seed::from_bytes(seed.to_bytes()), seed);
+        assert_eq!(PCG32::from_seed(seed).to_seed(), seed);
+    }
+
+    #[test]
+    fn seed_stream_top_bit_is_cleared() {
+        let seed = PCG32Seed::new(0x0123456789abcdef, 0xfedcba9876543210);
+        let low = PCG32Seed::new(0x0123456789abcdef, 0x7edcba9876543210);
+        assert_eq!(seed, low);
+        assert_eq!(seed.stream(), 0x7edcba9876543210);
+        assert_eq!(seed.sha256(), low.sha256());
+        assert_eq!(PCG32::from_seed(seed).to_seed(), seed);
+
+        let mut bytes = low.to_bytes();
+        bytes[15] |= 0x80;
+        assert_eq!(PCG32Seed::from_bytes(bytes), low);
+        let text = "0123456789abcdeffedcba9876543210";
+        assert_eq!(text.parse::<PCG32Seed>(), Ok(low));
+        assert_eq!(low.with_stream(u64::MAX).stream(), u64::MAX >> 1);
+    }
+
+    #[test]
+    fn seed_parse_errors() {
+        assert_eq!("".parse::<PCG32Seed>(), Err(ParseSeedError));
+        assert_eq!("0123".parse::<PCG32Seed>(), Err(ParseSeedError));
+        let bad = "0123456789abcdeffedcba987654321g";
+        assert_eq!(bad.parse::<PCG32Seed>(), Err(ParseSeedError));
+        let wide = "0123456789abcdeé0123456789abcde";
+        assert_eq!(wide.parse::<PCG32Seed>(), Err(ParseSeedError));
+        assert!(serde_json::from_str::<PCG32Seed>("\"xyz\"").is_err());
     }
 }
@@ -1,3 +1,4 @@
+use super::wallet::AccountId;
 use super::*;
 use std::fmt;
 
@@ -7,7 +8,16 @@ use serde::{Deserialize, Serialize};
 pub struct Player {
     // pub active: usize,
     pub hands: Vec<Hand>,
-    pub chips: usize,
+    pub chips: Money,
+    /// Fraction of a chip owed to the player under `Rounding::Carry`
+    #[serde(default)]
+    pub carry: Money,
+    /// Wallet account the chips were taken from, if any
+    #[serde(default)]
+    pub account: Option<AccountId>,
+    /// Index of the wallet transaction that locked the chips
+    #[serde(default)]
+    pub lock: Option<u64>,
 }
 
 impl std::ops::Deref for Player {
@@ -24,11 +34,14 @@ impl std::ops::DerefMut for Player {
 }
 
 impl Player {
-    pub fn new(chips: usize) -> Player {
+    pub fn new(chips: Money) -> Player {
         Player {
             // active: 0,
             hands: vec![Hand::default()],
             chips,
+            carry: Money::ZERO,
+            account: None,
+            lock: None,
         }
     }
 
@@ -55,7 +68,7 @@ pub struct Hand {
 
 impl Hand {
     fn initial_hand(&self) -> Option<u8> {
-        Some(*self.cards.get(0)? + *self.cards.get(1)?)
+        Some(*self.cards.first()? + *self.cards.get(1)?)
     }
 
     pub fn score(&self) -> u8 {
@@ -69,8 +82,11 @@ impl Hand {
         score
     }
 
+    /// Is an ace still counted as 11?
     pub fn soft(&self) -> bool {
-        self.ace_count() >= 1
+        let total: u8 = self.cards.iter().map(|c| c.value()).sum();
+        // Each ace counted as 1 instead takes 10 off the score
+        usize::from((total - self.score()) / 10) < self.ace_count()
     }
 
     pub fn ace_count(&self) -> usize {
@@ -0,0 +1,261 @@
+//! Signed round receipts
+//!
+//! Once a round reaches the `Final` state, the server signs a `RoundReceipt`
+//! recording everything it agreed to: the rules, the seed commitments, the
+//! deck hash, every action and the final table. A player can archive the
+//! receipt and later prove, with nothing but the server's public key, what
+//! was dealt and what they were owed
+use super::game::Error;
+use super::*;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Money moved by the round
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Settlement {
+    /// Total chips taken from the player, including doubles and splits
+    pub stake: Money,
+    /// Total paid back to the player, including returned stakes
+    pub paid: Money,
+    /// Player's chips once the winnings have been paid
+    pub chips: Money,
+}
+
+impl Settlement {
+    /// Money moved by a game in the `Final` state
+    pub fn new(game: &Game) -> Settlement {
+        let paid = game.winnings();
+        Settlement {
+            stake: game.stake(),
+            paid,
+            chips: game.view().player.chips + paid,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RoundReceipt {
+    pub rules: Ruleset,
+    /// Sha256 commitment to the server's seed, published before play
+    pub server: String,
+    /// Sha256 commitment to the client's seed
+    pub client: String,
+    /// Sha256 of the shoe when the bet was placed
+    pub deck: String,
+    pub bet: Money,
+    pub actions: Vec<Action>,
+    /// Final view of the table, with the dealer's hole card revealed
+    pub view: View,
+    pub settlement: Settlement,
+    /// Hex encoded ed25519 signature over every other field
+    pub signature: String,
+}
+
+/// Fields covered by the signature, borrowed from a `RoundReceipt`
+#[derive(Serialize)]
+struct Body<'a> {
+    rules: &'a Ruleset,
+    server: &'a str,
+    client: &'a str,
+    deck: &'a str,
+    bet: Money,
+    actions: &'a [Action],
+    view: &'a View,
+    settlement: &'a Settlement,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReceiptError {
+    /// The signature is malformed, or was not made by the given key
+    Signature,
+    /// The settlement does not match the outcomes in the final view
+    Settlement,
+}
+
+impl fmt::Display for ReceiptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReceiptError::Signature => write!(f, "invalid signature"),
+            ReceiptError::Settlement => write!(f, "settlement does not match outcomes"),
+        }
+    }
+}
+
+impl std::error::Error for ReceiptError {}
+
+impl RoundReceipt {
+    /// Sign a receipt for a game in the `Final` state. `server` and `client`
+    /// are the seed commitments that were published before play began
+    pub fn new(
+        game: &Game,
+        server: String,
+        client: String,
+        key: &Keypair,
+    ) -> Result<RoundReceipt, Error> {
+        let view = game.view();
+        if view.state != State::Final {
+            return Err(Error::InvalidAction);
+        }
+        let mut receipt = RoundReceipt {
+            rules: game.rules(),
+            server,
+            client,
+            deck: game.commitment().into(),
+            bet: game.wager(),
+            actions: game.actions().to_vec(),
+            settlement: Settlement::new(game),
+            view,
+            signature: String::new(),
+        };
+        receipt.signature = hex::encode(key.sign(&receipt.body()).to_bytes().as_ref());
+        Ok(receipt)
+    }
+
+    /// Canonical bytes covered by the signature
+    fn body(&self) -> Vec<u8> {
+        serde_json::to_vec(&Body {
+            rules: &self.rules,
+            server: &self.server,
+            client: &self.client,
+            deck: &self.deck,
+            bet: self.bet,
+            actions: &self.actions,
+            view: &self.view,
+            settlement: &self.settlement,
+        })
+        .expect("receipt body is always serializable")
+    }
+}
+
+/// Verify that a receipt was signed by `key`, and that its settlement agrees
+/// with the outcomes it records
+pub fn verify(receipt: &RoundReceipt, key: &PublicKey) -> Result<(), ReceiptError> {
+    let bytes = hex::decode(&receipt.signature).map_err(|_| ReceiptError::Signature)?;
+    let signature = Signature::try_from(&bytes[..]).map_err(|_| ReceiptError::Signature)?;
+    key.verify(&receipt.body(), &signature)
+        .map_err(|_| ReceiptError::Signature)?;
+
+    let paid: Money = receipt.view.scores.iter().map(|s| s.payout()).sum();
+    let s = receipt.settlement;
+    if s.paid != paid || s.chips != receipt.view.player.chips + paid {
+        return Err(ReceiptError::Settlement);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::commit;
+    use ed25519_dalek::SecretKey;
+
+    fn keypair(byte: u8) -> Keypair {
+        let secret = SecretKey::from_bytes(&[byte; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    /// A finished round: the player stands on 19 against the dealer's 17
+    fn finished(cards: &str) -> Game {
+        let shoe = Shoe::Deck(Deck::stacked(cards));
+        let mut game = Game::with_shoe(Ruleset::default(), Player::new(Money::chips(10)), shoe);
+        game.bet(Money::chips(2)).unwrap();
+        game.player(Action::Stand).unwrap();
+        game.dealer().unwrap();
+        game
+    }
+
+    fn receipt(game: &Game, key: &Keypair) -> RoundReceipt {
+        RoundReceipt::new(game, commit(b"server"), commit(b"client"), key).unwrap()
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let key = keypair(1);
+        let game = finished("10h 2c 9s 7d 8c");
+        let receipt = receipt(&game, &key);
+        assert_eq!(verify(&receipt, &key.public), Ok(()));
+        assert_eq!(receipt.actions, vec![Action::Stand]);
+        assert_eq!(
+            receipt.settlement,
+            Settlement {
+                stake: Money::chips(2),
+                paid: Money::chips(4),
+                chips: Money::chips(12),
+            }
+        );
+
+        // Only the server's key verifies it
+        assert_eq!(
+            verify(&receipt, &keypair(2).public),
+            Err(ReceiptError::Signature)
+        );
+        // and only once the round is over
+        let shoe = Shoe::Deck(Deck::stacked("10h 2c 9s 7d 8c"));
+        let mut game = Game::with_shoe(Ruleset::default(), Player::new(Money::chips(10)), shoe);
+        game.bet(Money::chips(2)).unwrap();
+        assert_eq!(
+            RoundReceipt::new(&game, String::new(), String::new(), &key),
+            Err(Error::InvalidAction)
+        );
+    }
+
+    #[test]
+    fn tampering_breaks_the_signature() {
+        let key = keypair(1);
+        let receipt = receipt(&finished("10h 2c 9s 7d 8c"), &key);
+
+        let mut bet = receipt.clone();
+        bet.bet = Money::chips(3);
+        let mut actions = receipt.clone();
+        actions.actions.push(Action::Hit);
+        let mut view = receipt.clone();
+        view.view.player.chips = Money::chips(100);
+        let mut signature = receipt.clone();
+        let mut bytes = hex::decode(&receipt.signature).unwrap();
+        bytes[0] ^= 1;
+        signature.signature = hex::encode(bytes);
+        let mut garbage = receipt.clone();
+        garbage.signature = "xyz".into();
+        for tampered in &[bet, actions, view, signature, garbage] {
+            assert_eq!(verify(tampered, &key.public), Err(ReceiptError::Signature));
+        }
+
+        // A settlement that disagrees with the outcomes is caught even when
+        // the server signed it
+        let mut wrong = receipt;
+        wrong.settlement.paid = Money::chips(5);
+        wrong.signature = hex::encode(key.sign(&wrong.body()).to_bytes().as_ref());
+        assert_eq!(verify(&wrong, &key.public), Err(ReceiptError::Settlement));
+    }
+
+    #[test]
+    fn bound_to_its_commitments_and_round() {
+        let key = keypair(1);
+        let first = finished("10h 2c 9s 7d 8c");
+        let second = finished("Kh 3c 9s 7d 7c");
+        let one = receipt(&first, &key);
+        let two = receipt(&second, &key);
+        assert_eq!(one.server, commit(b"server"));
+        assert_eq!(one.client, commit(b"client"));
+        assert_eq!(one.deck, first.commitment());
+        assert_eq!(two.deck, second.commitment());
+        assert_ne!(one.deck, two.deck);
+
+        // Claiming other seeds, or another round's deck, isn't signed
+        let mut server = one.clone();
+        server.server = commit(b"other");
+        let mut client = one.clone();
+        client.client = commit(b"other");
+        let mut deck = one.clone();
+        deck.deck = two.deck.clone();
+        let mut table = one;
+        table.view = two.view;
+        table.settlement = two.settlement;
+        for claimed in &[server, client, deck, table] {
+            assert_eq!(verify(claimed, &key.public), Err(ReceiptError::Signature));
+        }
+    }
+}
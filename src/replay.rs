@@ -0,0 +1,156 @@
+//! Deterministic replay of a single round
+//!
+//! A round is fully determined by the two generators, the rules, the
+//! player's chips, the bet and the actions taken. Each generator is recorded
+//! as a `Seed`, with its backend and how far it had advanced. A `RoundRecord` holds exactly that,
+//! so that any round, including one that hit a bug, can be reproduced from
+//! a single line of JSON
+use super::game::Error;
+use super::rng::{Seed, SeededRng};
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RoundRecord {
+    pub rules: Ruleset,
+    /// Server's RNG at the start of the round, used by `Game::init`
+    pub server: Seed,
+    /// Player's RNG at the start of the round, used by `Game::player_shuffle`
+    pub client: Seed,
+    /// Player's chips before the bet
+    pub chips: Money,
+    /// Fraction of a chip owed to the player before the bet, under
+    /// `Rounding::Carry`
+    #[serde(default)]
+    pub carry: Money,
+    pub bet: Money,
+    pub actions: Vec<Action>,
+    /// Position of the round in its session, starting from 0, which is the
+    /// nonce of an infinite deck
+    #[serde(default)]
+    pub round: u64,
+}
+
+/// A successfully replayed round
+pub struct Replay {
+    /// The view after the bet, after each action, and after the dealer
+    /// played, if they did
+    pub views: Vec<View>,
+    /// The game in its final state
+    pub game: Game,
+    /// Server's RNG after the round, where the next round starts
+    pub server: SeededRng,
+    /// Player's RNG after the round
+    pub client: SeededRng,
+}
+
+/// Where a replay stopped following the record
+#[derive(Clone, Debug, PartialEq)]
+pub enum Divergence {
+    /// The engine rejected the player's shuffle or the bet
+    Bet(Error),
+    /// Recorded action `index` was illegal in `view`
+    Action {
+        index: usize,
+        action: Action,
+        error: Error,
+        view: Box<View>,
+    },
+    /// The recorded actions ended while it was still the player's turn
+    Incomplete { view: Box<View> },
+    /// The engine failed while the dealer played
+    Dealer(Error),
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Divergence::Bet(error) => write!(f, "bet was rejected ({:?})", error),
+            Divergence::Action {
+                index,
+                action,
+                error,
+                view,
+            } => write!(
+                f,
+                "action {} ({:?}) was rejected ({:?}) in state {:?} with hands {}",
+                index,
+                action,
+                error,
+                view.state,
+                view.player
+                    .hands
+                    .iter()
+                    .map(|h| format!("[{}]", h))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Divergence::Incomplete { view } => write!(
+                f,
+                "actions end during the player's turn, in state {:?}",
+                view.state
+            ),
+            Divergence::Dealer(error) => write!(f, "dealer failed ({:?})", error),
+        }
+    }
+}
+
+impl Game {
+    /// Reproduce a recorded round exactly, returning every intermediate view
+    pub fn replay(record: &RoundRecord) -> Result<Replay, Divergence> {
+        Game::replay_with(record, record.server.rng(), record.client.rng())
+    }
+
+    /// Replay a round from generators already at the recorded seeds, which
+    /// saves stepping them forward from the start of the session
+    pub(crate) fn replay_with(
+        record: &RoundRecord,
+        mut server: SeededRng,
+        mut client: SeededRng,
+    ) -> Result<Replay, Divergence> {
+        let player = Player {
+            carry: record.carry,
+            ..Player::new(record.chips)
+        };
+        let mut game = Game::init(record.rules, player, &mut server, record.round);
+        game.player_shuffle(&mut client).map_err(Divergence::Bet)?;
+
+        let mut views = vec![ran_out(game.bet(record.bet), &game).map_err(Divergence::Bet)?];
+        for (index, &action) in record.actions.iter().enumerate() {
+            let view = ran_out(game.player(action), &game).map_err(|error| Divergence::Action {
+                index,
+                action,
+                error,
+                view: Box::new(game.view()),
+            })?;
+            views.push(view);
+        }
+
+        match game.view().state {
+            State::Player(_) => {
+                return Err(Divergence::Incomplete {
+                    view: Box::new(game.view()),
+                })
+            }
+            State::Dealer => views.push(ran_out(game.dealer(), &game).map_err(Divergence::Dealer)?),
+            _ => {}
+        }
+
+        Ok(Replay {
+            views,
+            game,
+            server,
+            client,
+        })
+    }
+}
+
+/// A round where the shoe ran out ends there, in the `Error` state with the
+/// bet refunded, and replays like any other
+fn ran_out(result: Result<View, Error>, game: &Game) -> Result<View, Error> {
+    match result {
+        Err(Error::Fatal) if game.state == State::Error => Ok(game.view()),
+        result => result,
+    }
+}
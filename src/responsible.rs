@@ -0,0 +1,403 @@
+//! Responsible gaming limits on a player's session
+//!
+//! `PlayerLimits` caps how much a player may lose and wager, and for how
+//! long they may play. A `Guard` tracks the chips moved during a session and
+//! refuses any bet that would break a limit. Once a limit has been reached,
+//! the player can be made to cool off for a while, after which the limits
+//! start over. Warnings are raised as a limit gets close, so that a front end
+//! can tell the player before bets are refused.
+//!
+//! The limits are only enforced by a `Session`, which checks the bet, every
+//! double and every split with its `Guard`. A `Game` played directly, with
+//! `Game::bet` and `Game::player`, is not held to them.
+//!
+//! Time comes from a `Clock`, so that tests and simulations can run without
+//! waiting
+use super::session::Movement;
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+/// The system's wall clock
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct PlayerLimits {
+    /// Largest net loss over the session
+    pub loss: Option<Money>,
+    /// Largest total wagered over the session, including doubles and splits
+    pub wager: Option<Money>,
+    /// Longest the session may last
+    pub duration: Option<Duration>,
+    /// How long bets are refused once a limit has been reached
+    pub cool_off: Option<Duration>,
+    /// Percentage of a limit at which to start warning the player
+    pub warn: u8,
+}
+
+impl Default for PlayerLimits {
+    fn default() -> PlayerLimits {
+        PlayerLimits {
+            loss: None,
+            wager: None,
+            duration: None,
+            cool_off: None,
+            warn: 80,
+        }
+    }
+}
+
+impl PlayerLimits {
+    pub fn loss(mut self, loss: Money) -> PlayerLimits {
+        self.loss = Some(loss);
+        self
+    }
+
+    pub fn wager(mut self, wager: Money) -> PlayerLimits {
+        self.wager = Some(wager);
+        self
+    }
+
+    pub fn duration(mut self, duration: Duration) -> PlayerLimits {
+        self.duration = Some(duration);
+        self
+    }
+
+    pub fn cool_off(mut self, cool_off: Duration) -> PlayerLimits {
+        self.cool_off = Some(cool_off);
+        self
+    }
+
+    pub fn warn(mut self, percent: u8) -> PlayerLimits {
+        assert!(percent <= 100);
+        self.warn = percent;
+        self
+    }
+}
+
+/// Why a bet was refused, with the limit that would have been broken
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Deserialize, Serialize)]
+pub enum Breach {
+    Loss(Money),
+    Wager(Money),
+    Duration(Duration),
+    /// The player is cooling off for this much longer
+    CoolOff(Duration),
+}
+
+impl fmt::Display for Breach {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breach::Loss(limit) => write!(f, "bet could exceed the loss limit of {}", limit),
+            Breach::Wager(limit) => write!(f, "bet would exceed the wager limit of {}", limit),
+            Breach::Duration(limit) => {
+                write!(f, "session has lasted over {} seconds", limit.as_secs())
+            }
+            Breach::CoolOff(left) => write!(f, "cooling off for {} more seconds", left.as_secs()),
+        }
+    }
+}
+
+/// A limit that is getting close
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Warning {
+    Loss { lost: Money, limit: Money },
+    Wager { wagered: Money, limit: Money },
+    Duration { elapsed: Duration, limit: Duration },
+}
+
+/// Enforces a player's limits over a session
+pub struct Guard {
+    limits: PlayerLimits,
+    clock: Box<dyn Clock>,
+    started: SystemTime,
+    wagered: Money,
+    paid: Money,
+    /// Bets are refused until then
+    cool_off: Option<SystemTime>,
+}
+
+impl Guard {
+    /// Start tracking a session from now
+    pub fn new<C: Clock + 'static>(limits: PlayerLimits, clock: C) -> Guard {
+        Guard {
+            limits,
+            started: clock.now(),
+            clock: Box::new(clock),
+            wagered: Money::ZERO,
+            paid: Money::ZERO,
+            cool_off: None,
+        }
+    }
+
+    pub fn limits(&self) -> PlayerLimits {
+        self.limits
+    }
+
+    /// Count chips moved during the session
+    pub fn record(&mut self, movement: Movement) {
+        match movement {
+            Movement::Wager(amount) => self.wagered += amount,
+            Movement::Payout(amount) => self.paid += amount,
+        }
+    }
+
+    /// Net loss over the session so far
+    pub fn lost(&self) -> Money {
+        self.wagered.saturating_sub(self.paid)
+    }
+
+    pub fn wagered(&self) -> Money {
+        self.wagered
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.clock
+            .now()
+            .duration_since(self.started)
+            .unwrap_or_default()
+    }
+
+    /// Check a bet about to be placed. Reaching a limit starts the cool-off
+    /// period, if there is one, and once it has passed the session starts over
+    pub fn check(&mut self, bet: Money) -> Result<(), Breach> {
+        let now = self.clock.now();
+        if let Some(until) = self.cool_off {
+            match until.duration_since(now) {
+                Ok(left) if !left.is_zero() => return Err(Breach::CoolOff(left)),
+                _ => {
+                    self.started = now;
+                    self.wagered = Money::ZERO;
+                    self.paid = Money::ZERO;
+                    self.cool_off = None;
+                }
+            }
+        }
+
+        let limits = self.limits;
+        let elapsed = self.elapsed();
+        let reached = limits.loss.is_some_and(|limit| self.lost() >= limit)
+            || limits.wager.is_some_and(|limit| self.wagered >= limit)
+            || limits.duration.is_some_and(|limit| elapsed >= limit);
+        if reached {
+            self.cool_off = limits.cool_off.map(|cool_off| now + cool_off);
+        }
+
+        if let Some(limit) = limits.duration {
+            if elapsed >= limit {
+                return Err(Breach::Duration(limit));
+            }
+        }
+        if let Some(limit) = limits.loss {
+            if self.lost() + bet > limit {
+                return Err(Breach::Loss(limit));
+            }
+        }
+        if let Some(limit) = limits.wager {
+            if self.wagered + bet > limit {
+                return Err(Breach::Wager(limit));
+            }
+        }
+        Ok(())
+    }
+
+    /// Limits that have passed the warning threshold
+    pub fn warnings(&self) -> Vec<Warning> {
+        let near = |used: u128, limit: u128| used * 100 >= limit * self.limits.warn as u128;
+        let mut warnings = Vec::new();
+        if let Some(limit) = self.limits.loss {
+            let lost = self.lost();
+            if near(lost.as_minor() as u128, limit.as_minor() as u128) {
+                warnings.push(Warning::Loss { lost, limit });
+            }
+        }
+        if let Some(limit) = self.limits.wager {
+            if near(self.wagered.as_minor() as u128, limit.as_minor() as u128) {
+                warnings.push(Warning::Wager {
+                    wagered: self.wagered,
+                    limit,
+                });
+            }
+        }
+        if let Some(limit) = self.limits.duration {
+            let elapsed = self.elapsed();
+            if near(elapsed.as_millis(), limit.as_millis()) {
+                warnings.push(Warning::Duration { elapsed, limit });
+            }
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Error;
+    use crate::rng::{Backend, Seed};
+    use crate::session::Session;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// A clock that only moves when told to
+    #[derive(Clone)]
+    struct FakeClock(Rc<Cell<SystemTime>>);
+
+    impl FakeClock {
+        fn new() -> FakeClock {
+            FakeClock(Rc::new(Cell::new(SystemTime::UNIX_EPOCH)))
+        }
+
+        fn advance(&self, by: Duration) {
+            self.0.set(self.0.get() + by);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> SystemTime {
+            self.0.get()
+        }
+    }
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn bets_are_held_to_each_limit() {
+        let limits = PlayerLimits::default()
+            .loss(Money::chips(10))
+            .wager(Money::chips(20))
+            .duration(MINUTE * 60);
+        let clock = FakeClock::new();
+        let mut guard = Guard::new(limits, clock.clone());
+
+        guard.record(Movement::Wager(Money::chips(15)));
+        guard.record(Movement::Payout(Money::chips(7)));
+        assert_eq!(guard.lost(), Money::chips(8));
+        assert_eq!(guard.check(Money::chips(2)), Ok(()));
+        assert_eq!(
+            guard.check(Money::chips(3)),
+            Err(Breach::Loss(Money::chips(10)))
+        );
+
+        guard.record(Movement::Payout(Money::chips(8)));
+        assert_eq!(guard.check(Money::chips(5)), Ok(()));
+        assert_eq!(
+            guard.check(Money::chips(6)),
+            Err(Breach::Wager(Money::chips(20)))
+        );
+
+        clock.advance(MINUTE * 60);
+        assert_eq!(
+            guard.check(Money::chips(1)),
+            Err(Breach::Duration(MINUTE * 60))
+        );
+    }
+
+    #[test]
+    fn warns_past_the_threshold() {
+        let limits = PlayerLimits::default()
+            .loss(Money::chips(10))
+            .wager(Money::chips(100))
+            .duration(MINUTE * 10)
+            .warn(80);
+        let clock = FakeClock::new();
+        let mut guard = Guard::new(limits, clock.clone());
+
+        guard.record(Movement::Wager(Money::chips(7)));
+        clock.advance(MINUTE * 7);
+        assert_eq!(guard.warnings(), vec![]);
+
+        guard.record(Movement::Wager(Money::chips(1)));
+        clock.advance(MINUTE);
+        assert_eq!(
+            guard.warnings(),
+            vec![
+                Warning::Loss {
+                    lost: Money::chips(8),
+                    limit: Money::chips(10),
+                },
+                Warning::Duration {
+                    elapsed: MINUTE * 8,
+                    limit: MINUTE * 10,
+                },
+            ]
+        );
+
+        guard.record(Movement::Payout(Money::chips(8)));
+        guard.record(Movement::Wager(Money::chips(72)));
+        assert!(guard.warnings().contains(&Warning::Wager {
+            wagered: Money::chips(80),
+            limit: Money::chips(100),
+        }));
+    }
+
+    #[test]
+    fn cool_off_refuses_bets_then_starts_over() {
+        let limits = PlayerLimits::default()
+            .loss(Money::chips(10))
+            .cool_off(MINUTE * 30);
+        let clock = FakeClock::new();
+        let mut guard = Guard::new(limits, clock.clone());
+
+        guard.record(Movement::Wager(Money::chips(10)));
+        assert_eq!(
+            guard.check(Money::chips(1)),
+            Err(Breach::Loss(Money::chips(10)))
+        );
+        clock.advance(MINUTE * 10);
+        assert_eq!(
+            guard.check(Money::chips(1)),
+            Err(Breach::CoolOff(MINUTE * 20))
+        );
+
+        clock.advance(MINUTE * 20);
+        assert_eq!(guard.check(Money::chips(1)), Ok(()));
+        assert_eq!(guard.lost(), Money::ZERO);
+        assert_eq!(guard.elapsed(), Duration::ZERO);
+    }
+
+    #[test]
+    fn doubles_and_splits_count_against_the_limits() {
+        // The wager limit covers the bet but nothing more, so any double or
+        // split is refused, in whichever round one first comes up
+        let mut refused = Vec::new();
+        for b in 0..=255 {
+            let server = Seed::new(Backend::ChaCha20, &[b; 32]).unwrap();
+            let client = Seed::new(Backend::ChaCha20, &[!b; 32]).unwrap();
+            let player = Player::new(Money::chips(100));
+            let rules = Ruleset::default();
+            let limits = PlayerLimits::default().wager(Money::chips(5));
+            let mut session =
+                Session::new(rules, player, server.rng(), client.rng(), server.commit())
+                    .limits(limits, FakeClock::new());
+            let view = session.start(Money::chips(5)).unwrap();
+
+            for &action in &[Action::Double, Action::Split] {
+                if view.valid_action(action).is_ok() {
+                    assert_eq!(
+                        session.player(action),
+                        Err(Error::PlayerLimit(Breach::Wager(Money::chips(5))))
+                    );
+                    assert_eq!(session.chips(), Money::chips(95));
+                    assert!(session.reconciles());
+                    refused.push(action);
+                }
+            }
+            if refused.contains(&Action::Double) && refused.contains(&Action::Split) {
+                return;
+            }
+        }
+        panic!("no round to double and split");
+    }
+}
@@ -0,0 +1,146 @@
+//! Checkpoints and rewinding, for training and analysis
+//!
+//! A `Checkpoint` marks a decision point in a game's event stream. Since a
+//! game is rebuilt exactly by folding its events, going back to a checkpoint
+//! restores the shoe position, hands and bets as they were, and a branch
+//! from it can be played out without touching the main line.
+//!
+//! Seeing how a round would have played out reveals the upcoming cards, so
+//! all of this is refused unless `Ruleset::rewind` is set
+use super::event::GameEvent;
+use super::game::Error;
+use super::*;
+use sha2::{Digest, Sha256};
+
+/// A decision point that a game can be rewound to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    /// Number of events up to the checkpoint
+    len: usize,
+    /// Sha256 of the events up to the checkpoint, to catch checkpoints
+    /// taken on a different game
+    digest: String,
+}
+
+/// Sha256 of a run of events, in their JSON encoding
+fn digest(events: &[GameEvent]) -> String {
+    let mut hasher = Sha256::default();
+    hasher.input(serde_json::to_vec(events).expect("events are always serializable"));
+    format!("{:0x}", hasher.result())
+}
+
+impl Game {
+    /// Mark the current position. Only possible before the bet or while the
+    /// player is deciding on an action
+    pub fn checkpoint(&self) -> Result<Checkpoint, Error> {
+        if !self.rules.rewind {
+            return Err(Error::InvalidAction);
+        }
+        match self.state {
+            State::Ready | State::Player(_) => Ok(Checkpoint {
+                len: self.events.len(),
+                digest: digest(&self.events),
+            }),
+            _ => Err(Error::InvalidAction),
+        }
+    }
+
+    /// A separate game starting from `checkpoint`, for playing out a
+    /// what-if without disturbing this one
+    pub fn branch(&self, checkpoint: &Checkpoint) -> Result<Game, Error> {
+        if !self.rules.rewind {
+            return Err(Error::InvalidAction);
+        }
+        match self.events.get(..checkpoint.len) {
+            Some(events) if digest(events) == checkpoint.digest => Game::from_events(events),
+            _ => Err(Error::InvalidAction),
+        }
+    }
+
+    /// Go back to `checkpoint`, discarding everything that happened since.
+    /// Observers and the client stay registered, but aren't told about the
+    /// rewind. A journaled game's journal is rewritten to match
+    pub fn rewind(&mut self, checkpoint: &Checkpoint) -> Result<View, Error> {
+        let mut game = self.branch(checkpoint)?;
+        if let Some(journal) = &mut self.journal {
+            journal.rewrite(&game.events).map_err(|_| Error::Journal)?;
+        }
+        game.observers = std::mem::take(&mut self.observers);
+        game.journal = self.journal.take();
+        game.client = self.client.take();
+        *self = game;
+        Ok(self.view())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::Journal;
+
+    fn stacked(rules: Ruleset, cards: &str) -> Game {
+        let shoe = Shoe::Deck(Deck::stacked(cards));
+        Game::with_shoe(rules, Player::new(Money::chips(10)), shoe)
+    }
+
+    #[test]
+    fn rewind_restores_the_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("rewind-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut journal = Journal::open(&dir).unwrap();
+
+        // Player 10 6 against a 10, then a 5 and a King to come
+        let mut game = stacked(Ruleset::default().rewind(true), "10h 7c 6s 10d 5h Kc 9d");
+        let id = journal.begin(&mut game).unwrap();
+        game.bet(Money::chips(2)).unwrap();
+        let checkpoint = game.checkpoint().unwrap();
+        let before = game.snapshot();
+
+        // Hitting twice busts
+        game.player(Action::Hit).unwrap();
+        let bust = game.player(Action::Hit).unwrap();
+        assert!(bust.player.hands[0].bust());
+        game.dealer().unwrap();
+        assert_eq!(journal.events(id).unwrap(), game.events());
+
+        // Back at the checkpoint, the same cards are still to come, and the
+        // journal only has the events up to it
+        let view = game.rewind(&checkpoint).unwrap();
+        assert_eq!(game.snapshot(), before);
+        assert_eq!(view, game.view());
+        assert_eq!(view.state, State::Player(0));
+        assert_eq!(journal.events(id).unwrap(), game.events());
+
+        // and play continues on the new line, still journaled
+        game.player(Action::Hit).unwrap();
+        let view = game.player(Action::Stand).unwrap();
+        assert_eq!(view.state, State::Dealer);
+        assert_eq!(view.player.hands[0].score(), 21);
+        assert_eq!(journal.events(id).unwrap(), game.events());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn branch_leaves_the_main_line() {
+        let mut game = stacked(Ruleset::default().rewind(true), "10h 7c 6s 10d 5h Kc 9d");
+        game.bet(Money::chips(2)).unwrap();
+        let checkpoint = game.checkpoint().unwrap();
+        let before = game.snapshot();
+
+        let mut branch = game.branch(&checkpoint).unwrap();
+        branch.player(Action::Stand).unwrap();
+        assert_eq!(game.snapshot(), before);
+
+        // A checkpoint from another game doesn't apply, even at the same
+        // point of the same kind of round
+        let mut other = stacked(Ruleset::default().rewind(true), "10h 7c 6s 10d 5h Kc 8d");
+        other.bet(Money::chips(2)).unwrap();
+        assert_eq!(other.rewind(&checkpoint).err(), Some(Error::InvalidAction));
+
+        // Nor is any of it allowed unless the rules say so
+        let mut game = stacked(Ruleset::default(), "10h 7c 6s 10d 5h Kc 9d");
+        game.bet(Money::chips(2)).unwrap();
+        assert_eq!(game.checkpoint().err(), Some(Error::InvalidAction));
+        assert_eq!(game.branch(&checkpoint).err(), Some(Error::InvalidAction));
+    }
+}
@@ -0,0 +1,479 @@
+//! Pluggable random number generators for shuffling the deck
+//!
+//! `PCG32` is fast and easily reproduced, which makes it a good fit for
+//! simulations, but its internal state can be recovered from a handful of
+//! outputs. A server dealing for real money should shuffle with a
+//! cryptographically secure generator such as `ChaCha20`, so that the
+//! upcoming cards cannot be predicted from the ones already dealt.
+//!
+//! A `Seed` names a backend, its raw seed bytes and how many outputs have
+//! been drawn since, so that any generator can be written down mid-session
+//! and rebuilt exactly. A `SeededRng` keeps count of its outputs for that
+use super::pcg::{PCG32Seed, PCG32};
+use rand_core::{Error, RngCore, SeedableRng};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+pub use rand_chacha::ChaCha20Rng;
+pub use rand_pcg::Pcg64;
+pub use rand_xoshiro::Xoshiro256PlusPlus;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Backend {
+    Pcg32,
+    Pcg64,
+    Xoshiro256PlusPlus,
+    ChaCha20,
+}
+
+/// A generator that may be used by `Game::init` to shuffle the deck
+pub trait FairRng: RngCore {
+    fn backend(&self) -> Backend;
+}
+
+impl FairRng for PCG32 {
+    fn backend(&self) -> Backend {
+        Backend::Pcg32
+    }
+}
+
+impl FairRng for Pcg64 {
+    fn backend(&self) -> Backend {
+        Backend::Pcg64
+    }
+}
+
+impl FairRng for Xoshiro256PlusPlus {
+    fn backend(&self) -> Backend {
+        Backend::Xoshiro256PlusPlus
+    }
+}
+
+impl FairRng for ChaCha20Rng {
+    fn backend(&self) -> Backend {
+        Backend::ChaCha20
+    }
+}
+
+impl Backend {
+    /// Is the generator's output unpredictable without knowledge of the seed?
+    pub fn secure(self) -> bool {
+        matches!(self, Backend::ChaCha20)
+    }
+
+    /// Length in bytes of the seed expected by `seeded`
+    pub fn seed_len(self) -> usize {
+        match self {
+            Backend::Pcg32 => 16,
+            Backend::Pcg64 | Backend::Xoshiro256PlusPlus | Backend::ChaCha20 => 32,
+        }
+    }
+
+    /// Build a generator from raw seed bytes, returning `None` if the seed
+    /// is the wrong length for this backend
+    pub fn seeded(self, seed: &[u8]) -> Option<AnyRng> {
+        if seed.len() != self.seed_len() {
+            return None;
+        }
+        Some(match self {
+            Backend::Pcg32 => AnyRng::Pcg32(<PCG32 as SeedableRng>::from_seed(copy_seed(seed))),
+            Backend::Pcg64 => AnyRng::Pcg64(Pcg64::from_seed(copy_seed(seed))),
+            Backend::Xoshiro256PlusPlus => {
+                AnyRng::Xoshiro256PlusPlus(Xoshiro256PlusPlus::from_seed(copy_seed(seed)))
+            }
+            Backend::ChaCha20 => {
+                AnyRng::ChaCha20(Box::new(ChaCha20Rng::from_seed(copy_seed(seed))))
+            }
+        })
+    }
+
+    /// Bytes in one output counted by `Seed::offset`. Generators built on
+    /// 32-bit words spend two of them on `next_u64`, while 64-bit generators
+    /// spend a whole step on `next_u32`
+    fn output_len(self) -> usize {
+        match self {
+            Backend::Pcg32 | Backend::ChaCha20 => 4,
+            Backend::Pcg64 | Backend::Xoshiro256PlusPlus => 8,
+        }
+    }
+
+    /// Draw a fresh seed from the operating system. The seed should be kept
+    /// secret until the session is over, and only its `commit`ment published
+    pub fn generate_seed(self) -> Vec<u8> {
+        let mut seed = vec![0u8; self.seed_len()];
+        rand::rngs::OsRng.fill_bytes(&mut seed);
+        seed
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Backend::Pcg32 => write!(f, "pcg32"),
+            Backend::Pcg64 => write!(f, "pcg64"),
+            Backend::Xoshiro256PlusPlus => write!(f, "xoshiro256++"),
+            Backend::ChaCha20 => write!(f, "chacha20"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseBackendError;
+
+impl fmt::Display for ParseBackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "backend must be one of pcg32, pcg64, xoshiro256++, chacha20"
+        )
+    }
+}
+
+impl std::error::Error for ParseBackendError {}
+
+impl FromStr for Backend {
+    type Err = ParseBackendError;
+
+    fn from_str(s: &str) -> Result<Backend, ParseBackendError> {
+        match s {
+            "pcg32" => Ok(Backend::Pcg32),
+            "pcg64" => Ok(Backend::Pcg64),
+            "xoshiro256++" => Ok(Backend::Xoshiro256PlusPlus),
+            "chacha20" => Ok(Backend::ChaCha20),
+            _ => Err(ParseBackendError),
+        }
+    }
+}
+
+fn copy_seed<S: Default + AsMut<[u8]>>(bytes: &[u8]) -> S {
+    let mut seed = S::default();
+    seed.as_mut().copy_from_slice(bytes);
+    seed
+}
+
+/// Sha256 commitment to a seed, published before play begins. For `PCG32`
+/// this matches `PCG32Seed::sha256`
+pub fn commit(seed: &[u8]) -> String {
+    let mut hasher = Sha256::default();
+    hasher.input(seed);
+    format!("{:0x}", hasher.result())
+}
+
+/// A generator's backend, the seed it was built from, and the number of
+/// outputs drawn from it since. Written as `backend:hex` followed by
+/// `@offset` when the offset isn't zero. A plain `PCG32Seed` of 32 hex
+/// digits is also read, as a `pcg32` seed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Seed {
+    backend: Backend,
+    bytes: Vec<u8>,
+    offset: u64,
+}
+
+impl Seed {
+    /// A seed for `backend`, or `None` if the bytes are the wrong length
+    pub fn new(backend: Backend, bytes: &[u8]) -> Option<Seed> {
+        if bytes.len() != backend.seed_len() {
+            return None;
+        }
+        Some(Seed {
+            backend,
+            bytes: bytes.to_vec(),
+            offset: 0,
+        })
+    }
+
+    /// A fresh seed from the operating system
+    pub fn generate(backend: Backend) -> Seed {
+        Seed {
+            backend,
+            bytes: backend.generate_seed(),
+            offset: 0,
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Raw seed bytes, as taken by `Backend::seeded`
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Outputs drawn since the generator was seeded: 32-bit words for
+    /// `Pcg32` and `ChaCha20`, 64-bit words for the others
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Sha256 commitment to the seed bytes, see `commit`
+    pub fn commit(&self) -> String {
+        commit(&self.bytes)
+    }
+
+    /// Rebuild the generator at its offset. `Pcg32` and `ChaCha20` jump
+    /// there directly, the others are stepped forward
+    pub fn rng(&self) -> SeededRng {
+        let mut rng = self
+            .backend
+            .seeded(&self.bytes)
+            .expect("seed length was checked");
+        match &mut rng {
+            AnyRng::Pcg32(r) => r.advance(self.offset),
+            AnyRng::ChaCha20(r) => r.set_word_pos(self.offset.into()),
+            rng => {
+                for _ in 0..self.offset {
+                    rng.next_u64();
+                }
+            }
+        }
+        SeededRng {
+            seed: self.clone(),
+            rng,
+        }
+    }
+}
+
+impl From<PCG32Seed> for Seed {
+    fn from(seed: PCG32Seed) -> Seed {
+        Seed {
+            backend: Backend::Pcg32,
+            bytes: seed.to_bytes().to_vec(),
+            offset: 0,
+        }
+    }
+}
+
+impl fmt::Display for Seed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.backend, hex::encode(&self.bytes))?;
+        if self.offset != 0 {
+            write!(f, "@{}", self.offset)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseSeedError;
+
+impl fmt::Display for ParseSeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "seed must be BACKEND:HEX[@OFFSET], or 32 hex digits for pcg32"
+        )
+    }
+}
+
+impl std::error::Error for ParseSeedError {}
+
+impl FromStr for Seed {
+    type Err = ParseSeedError;
+
+    fn from_str(s: &str) -> Result<Seed, ParseSeedError> {
+        let (backend, rest) = match s.split_once(':') {
+            Some(parts) => parts,
+            None => {
+                return s
+                    .parse::<PCG32Seed>()
+                    .map(Seed::from)
+                    .map_err(|_| ParseSeedError)
+            }
+        };
+        let (bytes, offset) = match rest.split_once('@') {
+            Some((bytes, offset)) => (bytes, offset.parse().map_err(|_| ParseSeedError)?),
+            None => (rest, 0),
+        };
+        let backend = backend.parse().map_err(|_| ParseSeedError)?;
+        let bytes = hex::decode(bytes).map_err(|_| ParseSeedError)?;
+        let seed = Seed::new(backend, &bytes).ok_or(ParseSeedError)?;
+        Ok(Seed { offset, ..seed })
+    }
+}
+
+impl Serialize for Seed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Seed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// A generator that counts its outputs, so that where it stands can be
+/// written down as a `Seed` at any time
+#[derive(Clone)]
+pub struct SeededRng {
+    seed: Seed,
+    rng: AnyRng,
+}
+
+impl SeededRng {
+    /// The generator's seed, at its current offset
+    pub fn seed(&self) -> Seed {
+        self.seed.clone()
+    }
+
+    fn advance(&mut self, bytes: usize) {
+        let len = self.seed.backend.output_len();
+        self.seed.offset += bytes.div_ceil(len) as u64;
+    }
+}
+
+impl FairRng for SeededRng {
+    fn backend(&self) -> Backend {
+        self.seed.backend
+    }
+}
+
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        self.advance(4);
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.advance(8);
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.advance(dest.len());
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.advance(dest.len());
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+/// Any of the supported backends, chosen at runtime
+#[derive(Clone)]
+pub enum AnyRng {
+    Pcg32(PCG32),
+    Pcg64(Pcg64),
+    Xoshiro256PlusPlus(Xoshiro256PlusPlus),
+    ChaCha20(Box<ChaCha20Rng>),
+}
+
+impl FairRng for AnyRng {
+    fn backend(&self) -> Backend {
+        match self {
+            AnyRng::Pcg32(r) => r.backend(),
+            AnyRng::Pcg64(r) => r.backend(),
+            AnyRng::Xoshiro256PlusPlus(r) => r.backend(),
+            AnyRng::ChaCha20(r) => r.backend(),
+        }
+    }
+}
+
+impl RngCore for AnyRng {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            AnyRng::Pcg32(r) => r.next_u32(),
+            AnyRng::Pcg64(r) => r.next_u32(),
+            AnyRng::Xoshiro256PlusPlus(r) => r.next_u32(),
+            AnyRng::ChaCha20(r) => r.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            AnyRng::Pcg32(r) => r.next_u64(),
+            AnyRng::Pcg64(r) => r.next_u64(),
+            AnyRng::Xoshiro256PlusPlus(r) => r.next_u64(),
+            AnyRng::ChaCha20(r) => r.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            AnyRng::Pcg32(r) => r.fill_bytes(dest),
+            AnyRng::Pcg64(r) => r.fill_bytes(dest),
+            AnyRng::Xoshiro256PlusPlus(r) => r.fill_bytes(dest),
+            AnyRng::ChaCha20(r) => r.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        match self {
+            AnyRng::Pcg32(r) => r.try_fill_bytes(dest),
+            AnyRng::Pcg64(r) => r.try_fill_bytes(dest),
+            AnyRng::Xoshiro256PlusPlus(r) => r.try_fill_bytes(dest),
+            AnyRng::ChaCha20(r) => r.try_fill_bytes(dest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKENDS: [Backend; 4] = [
+        Backend::Pcg32,
+        Backend::Pcg64,
+        Backend::Xoshiro256PlusPlus,
+        Backend::ChaCha20,
+    ];
+
+    fn seed(backend: Backend) -> Seed {
+        let bytes = (0..backend.seed_len() as u8).collect::<Vec<_>>();
+        Seed::new(backend, &bytes).unwrap()
+    }
+
+    #[test]
+    fn seed_text_round_trip() {
+        for &backend in &BACKENDS {
+            let mut rng = seed(backend).rng();
+            rng.next_u64();
+            let seed = rng.seed();
+            assert!(seed.offset() > 0);
+            assert_eq!(seed.to_string().parse::<Seed>(), Ok(seed.clone()));
+            let json = serde_json::to_string(&seed).unwrap();
+            assert_eq!(serde_json::from_str::<Seed>(&json).unwrap(), seed);
+        }
+
+        // A bare `PCG32Seed` is read as the same pcg32 seed
+        let pcg = PCG32Seed::new(44, 54);
+        let seed = pcg.to_string().parse::<Seed>().unwrap();
+        assert_eq!(seed, Seed::from(pcg));
+        assert_eq!(seed.commit(), pcg.sha256());
+
+        assert!("pcg32:00".parse::<Seed>().is_err());
+        assert!("md5:00".parse::<Seed>().is_err());
+        assert!("chacha20:zz".parse::<Seed>().is_err());
+    }
+
+    #[test]
+    fn seed_resumes_where_rng_stands() {
+        for &backend in &BACKENDS {
+            let mut rng = seed(backend).rng();
+            for i in 0..50 {
+                match i % 4 {
+                    0 => {
+                        rng.next_u32();
+                    }
+                    1 => {
+                        rng.next_u64();
+                    }
+                    2 => rng.fill_bytes(&mut [0u8; 13]),
+                    _ => rng.fill_bytes(&mut [0u8; 3]),
+                }
+            }
+            let mut resumed = rng.seed().rng();
+            for _ in 0..10 {
+                assert_eq!(resumed.next_u32(), rng.next_u32(), "{}", backend);
+                assert_eq!(resumed.next_u64(), rng.next_u64(), "{}", backend);
+            }
+        }
+    }
+}
@@ -1,27 +1,93 @@
-use serde::{Deserialize, Serialize};
+use super::limits::Limits;
+use super::money::Rounding;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Number of decks in the shoe. Serialized as the count, or as `"infinite"`
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum Decks {
+    Finite(usize),
+    /// Every card is drawn independently, see the `infinite` module
+    Infinite,
+}
+
+impl Decks {
+    pub fn count(self) -> Option<usize> {
+        match self {
+            Decks::Finite(n) => Some(n),
+            Decks::Infinite => None,
+        }
+    }
+}
+
+impl Serialize for Decks {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Decks::Finite(n) => serializer.serialize_u64(*n as u64),
+            Decks::Infinite => serializer.serialize_str("infinite"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Decks {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Count(usize),
+            Name(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Count(n) if n > 0 => Ok(Decks::Finite(n)),
+            Repr::Name(ref s) if s == "infinite" => Ok(Decks::Infinite),
+            _ => Err(de::Error::custom(
+                "expected a positive number of decks or \"infinite\"",
+            )),
+        }
+    }
+}
 
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Deserialize, Serialize)]
 pub struct Ruleset {
-    pub decks: usize,
+    pub decks: Decks,
     pub stand: bool,
     pub double_after_split: bool,
     pub surrender: bool,
+    /// Allow checkpoints and rewinding for training and analysis. Must be
+    /// off for real money, where it would let the player see future cards
+    #[serde(default)]
+    pub rewind: bool,
+    /// How to pay a blackjack that doesn't come to a whole chip
+    #[serde(default)]
+    pub rounding: Rounding,
+    #[serde(default)]
+    pub limits: Limits,
 }
 
-impl Ruleset {
-    pub fn default() -> Ruleset {
+impl Default for Ruleset {
+    fn default() -> Ruleset {
         Ruleset {
-            decks: 1,
+            decks: Decks::Finite(1),
             stand: true,
             double_after_split: true,
             surrender: false,
+            rewind: false,
+            rounding: Rounding::Down,
+            limits: Limits::default(),
         }
     }
+}
 
+impl Ruleset {
     /// Set the number of decks to be used in the game
     pub fn decks(mut self, decks: usize) -> Ruleset {
         assert!(decks > 0);
-        self.decks = decks;
+        self.decks = Decks::Finite(decks);
+        self
+    }
+
+    /// Deal from an infinite deck, where each card is derived from a hash
+    pub fn infinite(mut self) -> Ruleset {
+        self.decks = Decks::Infinite;
         self
     }
 
@@ -40,4 +106,21 @@ impl Ruleset {
         self.surrender = surrender;
         self
     }
+
+    /// Enable `Game::checkpoint` and `Game::rewind`
+    pub fn rewind(mut self, rewind: bool) -> Ruleset {
+        self.rewind = rewind;
+        self
+    }
+
+    pub fn rounding(mut self, rounding: Rounding) -> Ruleset {
+        self.rounding = rounding;
+        self
+    }
+
+    /// Set the table's betting limits
+    pub fn limits(mut self, limits: Limits) -> Ruleset {
+        self.limits = limits;
+        self
+    }
 }
@@ -0,0 +1,374 @@
+//! The `session` module runs consecutive rounds for one player
+//!
+//! A `Session` owns the player between rounds, both RNGs, and the record of
+//! every round needed to verify the session once the seeds are revealed. It
+//! also keeps a ledger of every chip taken from and paid to the player,
+//! entered as the round is played, with the running balance after each entry.
+//! The ledger follows the stake and winnings the game reports rather than the
+//! player's chips, so `reconciles` checks that the two agree.
+//!
+//! A session can also hold the player to responsible gaming limits, see the
+//! `responsible` module
+use super::game::Error;
+use super::receipt::Settlement;
+use super::responsible::{Clock, Guard, PlayerLimits, Warning};
+use super::rng::FairRng;
+use super::verify::{Log, Round};
+use super::*;
+use serde::{Deserialize, Serialize};
+
+/// A movement of chips
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Movement {
+    /// Taken from the player: the initial bet, a double or a split
+    Wager(Money),
+    /// Paid to the player, including returned stakes and refunds
+    Payout(Money),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct LedgerEntry {
+    /// Round the movement belongs to, starting from 0
+    pub round: usize,
+    pub movement: Movement,
+    /// Player's chips after the movement
+    pub balance: Money,
+}
+
+pub struct Session<R: FairRng> {
+    rules: Ruleset,
+    /// `None` while a round is in progress, when the game holds the player
+    player: Option<Player>,
+    game: Option<Game>,
+    server: R,
+    client: R,
+    log: Log,
+    ledger: Vec<LedgerEntry>,
+    /// Stake of the current round already entered in the ledger
+    staked: Money,
+    /// Rounds finished so far, including any that ended in an error
+    round: usize,
+    guard: Option<Guard>,
+}
+
+impl<R: FairRng + Clone> Session<R> {
+    /// Start a session. `commitment` is the Sha256 of the server's seed,
+    /// published before the first round
+    pub fn new(
+        rules: Ruleset,
+        player: Player,
+        server: R,
+        client: R,
+        commitment: String,
+    ) -> Session<R> {
+        let log = Log {
+            rules,
+            server: commitment,
+            chips: player.chips,
+            rounds: Vec::new(),
+        };
+        Session {
+            rules,
+            player: Some(player),
+            game: None,
+            server,
+            client,
+            log,
+            ledger: Vec::new(),
+            staked: Money::ZERO,
+            round: 0,
+            guard: None,
+        }
+    }
+
+    /// Hold the player to responsible gaming limits from now on, timed by
+    /// `clock`
+    pub fn limits<C: Clock + 'static>(mut self, limits: PlayerLimits, clock: C) -> Session<R> {
+        self.guard = Some(Guard::new(limits, clock));
+        self
+    }
+
+    /// The player's responsible gaming limits that are getting close
+    pub fn warnings(&self) -> Vec<Warning> {
+        self.guard.as_ref().map_or(Vec::new(), Guard::warnings)
+    }
+
+    /// The round in progress, if any
+    pub fn game(&self) -> Option<&Game> {
+        self.game.as_ref()
+    }
+
+    /// Player's chips, including during a round
+    pub fn chips(&self) -> Money {
+        match (&self.player, &self.game) {
+            (Some(player), _) => player.chips,
+            (None, Some(game)) => game.player.chips,
+            (None, None) => unreachable!("the player is always held by the session or the game"),
+        }
+    }
+
+    /// Fraction of a chip owed to the player, under `Rounding::Carry`
+    pub fn carry(&self) -> Money {
+        match (&self.player, &self.game) {
+            (Some(player), _) => player.carry,
+            (None, Some(game)) => game.player.carry,
+            (None, None) => unreachable!("the player is always held by the session or the game"),
+        }
+    }
+
+    /// The server's and player's RNGs, where the next round will start. The
+    /// server's must not be shown to the player before its seed is revealed
+    pub fn rngs(&self) -> (&R, &R) {
+        (&self.server, &self.client)
+    }
+
+    /// Every finished round, for verifying the session
+    pub fn log(&self) -> &Log {
+        &self.log
+    }
+
+    pub fn ledger(&self) -> &[LedgerEntry] {
+        &self.ledger
+    }
+
+    /// Balance according to the ledger
+    pub fn balance(&self) -> Money {
+        self.ledger
+            .last()
+            .map_or(self.log.chips, |entry| entry.balance)
+    }
+
+    /// Does the ledger agree with the player's chips?
+    pub fn reconciles(&self) -> bool {
+        self.balance() == self.chips()
+    }
+
+    /// Deal a new round with the given bet. Fails if a round is already in
+    /// progress, or if the bet is rejected, in which case the player keeps
+    /// their chips and the RNGs are left untouched
+    pub fn start(&mut self, bet: Money) -> Result<View, Error> {
+        let chips = self.player.as_ref().ok_or(Error::InvalidAction)?.chips;
+        if bet.is_zero() {
+            return Err(Error::InvalidAction);
+        }
+        self.rules.limits.check_bet(bet)?;
+        if chips < bet {
+            return Err(Error::Money(bet - chips));
+        }
+        if let Some(guard) = &mut self.guard {
+            guard.check(bet)?;
+        }
+
+        // Dealing moves both RNGs on, so keep them to put back if it fails
+        let (server, client) = (self.server.clone(), self.client.clone());
+        let player = self.player.take().expect("checked above");
+        let mut game = Game::init(
+            self.rules,
+            player.clone(),
+            &mut self.server,
+            self.round as u64,
+        );
+        let result = game
+            .player_shuffle(&mut self.client)
+            .and_then(|()| game.bet(bet));
+        if result.is_err() {
+            self.player = Some(player);
+            self.server = server;
+            self.client = client;
+            return result;
+        }
+
+        self.staked = Money::ZERO;
+        self.game = Some(game);
+        self.record();
+        result
+    }
+
+    /// Take an action on the player's current hand. Doubling and splitting
+    /// are bets, and held to the player's limits
+    pub fn player(&mut self, action: Action) -> Result<View, Error> {
+        let game = self.game.as_mut().ok_or(Error::InvalidAction)?;
+        if let (Action::Double, Some(guard)) | (Action::Split, Some(guard)) =
+            (action, &mut self.guard)
+        {
+            let idx = game.valid_action(action)?;
+            guard.check(game.bets[idx])?;
+        }
+        let result = game.player(action);
+        self.record();
+        result
+    }
+
+    /// Play out the dealer's hand
+    pub fn dealer(&mut self) -> Result<View, Error> {
+        let result = self.game.as_mut().ok_or(Error::InvalidAction)?.dealer();
+        self.record();
+        result
+    }
+
+    /// Pay out a round in the `Final` state, or close one that ended in an
+    /// `Error` with the bet refunded, and take back the player
+    pub fn finish(&mut self) -> Result<Settlement, Error> {
+        let state = self.state().ok_or(Error::InvalidAction)?;
+        if state != State::Final && state != State::Error {
+            return Err(Error::InvalidAction);
+        }
+        let game = self.game.take().expect("state is only known for a game");
+        let round = self.round;
+        self.round += 1;
+
+        // A round that ended in an error is logged too, as its shuffles
+        // moved the RNGs on for the rounds after it
+        let settlement = if state == State::Error {
+            Settlement {
+                stake: game.stake(),
+                paid: Money::ZERO,
+                chips: game.player.chips,
+            }
+        } else {
+            Settlement::new(&game)
+        };
+        if !settlement.paid.is_zero() {
+            self.push(round, Movement::Payout(settlement.paid));
+        }
+        self.log.rounds.push(Round {
+            deck: game.commitment().into(),
+            bet: game.wager(),
+            actions: game.actions().to_vec(),
+            view: game.view(),
+            chips: settlement.chips,
+        });
+        self.player = Some(if state == State::Error {
+            game.player
+        } else {
+            game.finish()?
+        });
+        Ok(settlement)
+    }
+
+    fn state(&self) -> Option<State> {
+        self.game.as_ref().map(|game| game.view().state)
+    }
+
+    fn push(&mut self, round: usize, movement: Movement) {
+        let balance = match movement {
+            Movement::Wager(amount) => self.balance() - amount,
+            Movement::Payout(amount) => self.balance() + amount,
+        };
+        self.ledger.push(LedgerEntry {
+            round,
+            movement,
+            balance,
+        });
+        if let Some(guard) = &mut self.guard {
+            guard.record(movement);
+        }
+    }
+
+    /// Enter the chips moved by any new events of the current game
+    fn record(&mut self) {
+        let round = self.round;
+        let game = match &self.game {
+            Some(game) => game,
+            None => return,
+        };
+        // Bets, doubles and splits add to the stake, and it only goes down
+        // when they are all refunded because the shoe ran out
+        let stake = game.stake();
+        let movement = if stake > self.staked {
+            Movement::Wager(stake - self.staked)
+        } else if stake < self.staked {
+            Movement::Payout(self.staked - stake)
+        } else {
+            return;
+        };
+        self.staked = stake;
+        self.push(round, movement);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::{Backend, Seed, SeededRng};
+    use crate::verify::verify;
+
+    fn seeds() -> (Seed, Seed) {
+        (
+            Seed::new(Backend::ChaCha20, &[1; 32]).unwrap(),
+            Seed::new(Backend::ChaCha20, &[2; 32]).unwrap(),
+        )
+    }
+
+    fn session(rules: Ruleset) -> Session<SeededRng> {
+        let (server, client) = seeds();
+        let player = Player::new(Money::chips(1000));
+        Session::new(rules, player, server.rng(), client.rng(), server.commit())
+    }
+
+    #[test]
+    fn ledger_reconciles_every_round() {
+        let mut session = session(Ruleset::default().decks(2).surrender(true));
+        let (mut outcomes, mut actions) = (Vec::new(), Vec::new());
+        for _ in 0..300 {
+            let mut view = session.start(Money::chips(5)).unwrap();
+            assert!(session.reconciles());
+            while let State::Player(idx) = view.state {
+                let score = view.player.hands[idx].score();
+                let action = if view.valid_action(Action::Split).is_ok() {
+                    Action::Split
+                } else if (score == 10 || score == 11) && view.valid_action(Action::Double).is_ok()
+                {
+                    Action::Double
+                } else if score == 16 && view.valid_action(Action::Surrender).is_ok() {
+                    Action::Surrender
+                } else if score < 17 {
+                    Action::Hit
+                } else {
+                    Action::Stand
+                };
+                actions.push(action);
+                view = session.player(action).unwrap();
+                assert!(session.reconciles());
+            }
+            if view.state == State::Dealer {
+                view = session.dealer().unwrap();
+            }
+            outcomes.extend(view.scores);
+            session.finish().unwrap();
+            assert!(session.reconciles());
+        }
+
+        assert!(actions.contains(&Action::Split));
+        assert!(actions.contains(&Action::Double));
+        let seen = |kind: fn(&Outcome) -> bool| outcomes.iter().any(kind);
+        assert!(seen(|o| matches!(o, Outcome::Win(_))));
+        assert!(seen(|o| matches!(o, Outcome::Lose(_))));
+        assert!(seen(|o| matches!(o, Outcome::Push(_))));
+        assert!(seen(|o| matches!(o, Outcome::Surrender(_))));
+        assert!(seen(|o| matches!(o, Outcome::Blackjack(_))));
+
+        let (server, client) = seeds();
+        assert_eq!(verify(session.log(), &server, &client), Ok(300));
+    }
+
+    #[test]
+    fn failed_start_changes_nothing() {
+        // With no cards to deal the bet is taken, and then refunded when the
+        // shoe runs out
+        let rules = Ruleset {
+            decks: Decks::Finite(0),
+            ..Ruleset::default()
+        };
+        let mut session = session(rules);
+        let before = session.rngs().0.seed();
+        assert_eq!(session.start(Money::chips(5)), Err(Error::Fatal));
+
+        assert!(session.game().is_none());
+        assert_eq!(session.chips(), Money::chips(1000));
+        assert_eq!(session.rngs().0.seed(), before);
+        assert!(session.ledger().is_empty());
+        assert!(session.log().rounds.is_empty());
+        assert_eq!(session.start(Money::chips(5)), Err(Error::Fatal));
+    }
+}
@@ -0,0 +1,291 @@
+//! Versioned snapshots of the full game state, so that a round in progress
+//! can be suspended and resumed, possibly on another server
+//!
+//! Unlike a `View`, a `Snapshot` holds everything the server knows: the
+//! order of the shoe, the dealer's hole card and any shuffle secrets. It must
+//! never be shown to the player. Since a snapshot may have been stored or
+//! moved, `Game::restore` checks that it is internally consistent before
+//! play continues
+use super::event::GameEvent;
+use super::game::{Last, Outcome};
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Version of the snapshot format written by `Game::snapshot`. Version 2
+/// counts money in minor units rather than whole chips, and version 3 holds
+/// a bet for each hand
+pub const VERSION: u32 = 3;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Snapshot {
+    version: u32,
+    rules: Ruleset,
+    shoe: Shoe,
+    dealer: Hand,
+    player: Player,
+    bets: Vec<Money>,
+    state: State,
+    last: Last,
+    scores: Vec<Outcome>,
+    commitment: String,
+    wager: Money,
+    stake: Money,
+    actions: Vec<Action>,
+    events: Vec<GameEvent>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SnapshotError {
+    /// The snapshot was written by an unsupported version of the format
+    Version(u32),
+    /// The shoe doesn't match the number of decks in the rules
+    Shoe,
+    /// The dealt and remaining cards don't add up to the full shoe
+    Cards,
+    /// The hands, scores or last card are impossible in the current state
+    State,
+    /// The bets and stake don't agree
+    Money,
+    /// Folding the recorded events doesn't give the rest of the snapshot
+    Events,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Version(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::Shoe => write!(f, "shoe does not match the rules"),
+            SnapshotError::Cards => write!(f, "cards do not add up to the full shoe"),
+            SnapshotError::State => write!(f, "hands are inconsistent with the game state"),
+            SnapshotError::Money => write!(f, "bets are inconsistent with the stake"),
+            SnapshotError::Events => write!(f, "event history does not match the game"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl Snapshot {
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Every card dealt so far in the round
+    fn dealt(&self) -> Vec<Card> {
+        if self.state == State::Ready {
+            return Vec::new();
+        }
+        self.dealer
+            .cards
+            .iter()
+            .chain(self.player.hands.iter().flat_map(|h| h.cards.iter()))
+            .copied()
+            .collect()
+    }
+
+    fn check_shoe(&self) -> Result<(), SnapshotError> {
+        let dealt = self.dealt();
+        match (self.rules.decks, &self.shoe) {
+            (Decks::Finite(n), Shoe::Deck(deck)) => {
+                let mut counts = [0usize; 52];
+                for card in deck.cards().iter().chain(&dealt) {
+                    counts[card.code() as usize] += 1;
+                }
+                if counts.iter().any(|&c| c != n) {
+                    return Err(SnapshotError::Cards);
+                }
+            }
+            (Decks::Finite(n), Shoe::Mental(deck)) => {
+                // The remaining cards are encrypted, so only count them
+                if deck.count() + dealt.len() != 52 * n {
+                    return Err(SnapshotError::Cards);
+                }
+            }
+            (Decks::Infinite, Shoe::Infinite(deck)) => {
+                // Every dealt card can be derived again from the seeds, but
+                // not the order they went to each hand in
+                let mut expected = (0..deck.cursor())
+                    .map(|i| deck.card_at(i).code())
+                    .collect::<Vec<_>>();
+                let mut found = dealt.iter().map(|c| c.code()).collect::<Vec<_>>();
+                expected.sort_unstable();
+                found.sort_unstable();
+                if expected != found {
+                    return Err(SnapshotError::Cards);
+                }
+            }
+            _ => return Err(SnapshotError::Shoe),
+        }
+        Ok(())
+    }
+
+    fn check_state(&self) -> Result<(), SnapshotError> {
+        let hands = &self.player.hands;
+        let ok = match self.state {
+            State::Ready => {
+                return if self.dealer.cards.is_empty()
+                    && self.scores.is_empty()
+                    && self.actions.is_empty()
+                    && self.bets.is_empty()
+                {
+                    Ok(())
+                } else {
+                    Err(SnapshotError::State)
+                };
+            }
+            // The bet has been refunded, and the round can't continue
+            State::Error => return Ok(()),
+            State::Player(idx) => idx < hands.len() && self.scores.is_empty(),
+            State::Dealer => self.scores.is_empty(),
+            State::Final => self.scores.len() == hands.len(),
+        };
+        let last = match self.last {
+            Last::Dealer(card) => self.dealer.cards.contains(&card),
+            Last::Player(card) => hands.iter().any(|h| h.cards.contains(&card)),
+        };
+        if !ok
+            || !last
+            || self.dealer.cards.len() < 2
+            || hands.is_empty()
+            || hands.iter().any(|h| h.cards.len() < 2)
+        {
+            return Err(SnapshotError::State);
+        }
+        if self.wager.is_zero()
+            || self.bets.len() != hands.len()
+            || self.bets.iter().any(|&bet| bet < self.wager)
+            || self.bets.iter().copied().sum::<Money>() != self.stake
+        {
+            return Err(SnapshotError::Money);
+        }
+        Ok(())
+    }
+}
+
+impl Game {
+    /// Capture the full state of the game, including the hidden cards
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: VERSION,
+            rules: self.rules,
+            shoe: self.shoe.clone(),
+            dealer: self.dealer.clone(),
+            player: self.player.clone(),
+            bets: self.bets.clone(),
+            state: self.state,
+            last: self.last,
+            scores: self.scores.clone(),
+            commitment: self.commitment.clone(),
+            wager: self.wager,
+            stake: self.stake,
+            actions: self.actions.clone(),
+            events: self.events.clone(),
+        }
+    }
+
+    /// Resume a game from a snapshot, once it has been checked for
+    /// consistency
+    pub fn restore(snapshot: Snapshot) -> Result<Game, SnapshotError> {
+        if snapshot.version != VERSION {
+            return Err(SnapshotError::Version(snapshot.version));
+        }
+        snapshot.check_state()?;
+        snapshot.check_shoe()?;
+        match Game::from_events(&snapshot.events) {
+            Ok(game) if game.snapshot() == snapshot => {}
+            _ => return Err(SnapshotError::Events),
+        }
+        Ok(Game {
+            rules: snapshot.rules,
+            shoe: snapshot.shoe,
+            dealer: snapshot.dealer,
+            player: snapshot.player,
+            bets: snapshot.bets,
+            state: snapshot.state,
+            last: snapshot.last,
+            scores: snapshot.scores,
+            commitment: snapshot.commitment,
+            wager: snapshot.wager,
+            stake: snapshot.stake,
+            actions: snapshot.actions,
+            events: snapshot.events,
+            observers: Vec::new(),
+            journal: None,
+            client: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcg::PCG32;
+
+    /// Hit to 17, then stand and let the dealer play
+    fn play_out(game: &mut Game) -> View {
+        let mut view = game.view();
+        while let State::Player(idx) = view.state {
+            let action = if view.player.hands[idx].score() < 17 {
+                Action::Hit
+            } else {
+                Action::Stand
+            };
+            view = game.player(action).unwrap();
+        }
+        if view.state == State::Dealer {
+            view = game.dealer().unwrap();
+        }
+        view
+    }
+
+    #[test]
+    fn restore_resumes_play() {
+        let mut rng = PCG32::new(3, 5);
+        let player = Player::new(Money::chips(100));
+        let mut game = Game::init(Ruleset::default().decks(2), player, &mut rng, 0);
+        game.player_shuffle(&mut rng).unwrap();
+        game.bet(Money::chips(5)).unwrap();
+        assert_eq!(game.state, State::Player(0));
+
+        // Through JSON, as a suspended round would be stored
+        let snapshot = game.snapshot();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let mut restored = Game::restore(serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(restored.view(), game.view());
+        assert_eq!(restored.snapshot(), snapshot);
+
+        let view = play_out(&mut game);
+        assert_eq!(view.state, State::Final);
+        assert_eq!(play_out(&mut restored), view);
+        assert_eq!(restored.events(), game.events());
+        assert_eq!(restored.finish().unwrap(), game.finish().unwrap());
+    }
+
+    #[test]
+    fn restore_rejects_inconsistent_snapshots() {
+        let mut rng = PCG32::new(3, 5);
+        let player = Player::new(Money::chips(100));
+        let mut game = Game::init(Ruleset::default(), player, &mut rng, 0);
+        game.bet(Money::chips(5)).unwrap();
+        let snapshot = game.snapshot();
+
+        let mut version = snapshot.clone();
+        version.version = 2;
+        let mut stake = snapshot.clone();
+        stake.stake += Money::chips(1);
+        let mut decks = snapshot.clone();
+        decks.rules.decks = Decks::Finite(2);
+        let mut chips = snapshot;
+        chips.player.chips += Money::chips(1);
+        let cases = [
+            (version, SnapshotError::Version(2)),
+            (stake, SnapshotError::Money),
+            (decks, SnapshotError::Cards),
+            (chips, SnapshotError::Events),
+        ];
+        for (snapshot, error) in cases.iter().cloned() {
+            assert_eq!(Game::restore(snapshot).err(), Some(error));
+        }
+    }
+}
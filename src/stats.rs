@@ -0,0 +1,162 @@
+//! Statistical helpers shared by the card and RNG audits
+
+/// Result of a single statistical test
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestResult {
+    pub name: &'static str,
+    /// Number of observations the test was run on
+    pub n: u64,
+    pub statistic: f64,
+    /// Degrees of freedom of a chi-squared statistic, or 0 for a standard
+    /// normal statistic
+    pub df: usize,
+    /// Probability of a statistic at least this extreme from a fair source
+    pub p: f64,
+}
+
+/// Natural log of the gamma function, Lanczos approximation
+pub fn ln_gamma(x: f64) -> f64 {
+    const G: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let mut y = x;
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut ser = 1.000000000190015;
+    for g in &G {
+        y += 1.0;
+        ser += g / y;
+    }
+    -tmp + (2.5066282746310005 * ser / x).ln()
+}
+
+/// Regularized upper incomplete gamma function Q(a, x)
+pub fn gamma_q(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    if x < a + 1.0 {
+        // Series representation of P(a, x)
+        let mut sum = 1.0 / a;
+        let mut del = sum;
+        let mut ap = a;
+        for _ in 0..1000 {
+            ap += 1.0;
+            del *= x / ap;
+            sum += del;
+            if del.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        1.0 - sum * (-x + a * x.ln() - ln_gamma(a)).exp()
+    } else {
+        // Continued fraction representation of Q(a, x), modified Lentz
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let del = d * c;
+            h *= del;
+            if (del - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        (-x + a * x.ln() - ln_gamma(a)).exp() * h
+    }
+}
+
+/// Probability that a chi-squared variable with `df` degrees of freedom
+/// exceeds `x`
+pub fn chi_square_p(x: f64, df: usize) -> f64 {
+    if df == 0 {
+        return 1.0;
+    }
+    gamma_q(df as f64 / 2.0, x / 2.0)
+}
+
+/// Complementary error function
+pub fn erfc(x: f64) -> f64 {
+    if x < 0.0 {
+        2.0 - gamma_q(0.5, x * x)
+    } else {
+        gamma_q(0.5, x * x)
+    }
+}
+
+/// Two-sided p-value of a standard normal statistic
+pub fn normal_p(z: f64) -> f64 {
+    erfc(z.abs() / std::f64::consts::SQRT_2)
+}
+
+/// Pearson's chi-squared statistic for observed counts against expected
+/// probabilities, skipping categories with no expected mass. Returns the
+/// statistic and the degrees of freedom
+pub fn chi_square(observed: &[u64], expected: &[f64]) -> (f64, usize) {
+    let n: u64 = observed.iter().sum();
+    let mut stat = 0.0;
+    let mut categories = 0usize;
+    for (&o, &p) in observed.iter().zip(expected) {
+        let e = n as f64 * p;
+        if e > 0.0 {
+            stat += (o as f64 - e).powi(2) / e;
+            categories += 1;
+        }
+    }
+    (stat, categories.saturating_sub(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn chi_square_of_known_counts() {
+        // Expected 20 in each category, so (10^2 + 0 + 10^2) / 20
+        let (stat, df) = chi_square(&[10, 20, 30], &[1.0 / 3.0; 3]);
+        assert!(close(stat, 10.0), "{}", stat);
+        assert_eq!(df, 2);
+        // With two degrees of freedom the tail is exactly e^(-x/2)
+        assert!(close(chi_square_p(stat, df), (-5.0f64).exp()));
+
+        // Categories with no expected mass don't count towards the degrees
+        // of freedom
+        let (stat, df) = chi_square(&[5, 5, 0], &[0.5, 0.5, 0.0]);
+        assert!(close(stat, 0.0));
+        assert_eq!(df, 1);
+        assert!(close(chi_square_p(stat, df), 1.0));
+        assert!(close(chi_square_p(3.0, 0), 1.0));
+    }
+
+    #[test]
+    fn known_values() {
+        assert!(close(ln_gamma(5.0), 24f64.ln()));
+        assert!(close(ln_gamma(0.5), std::f64::consts::PI.sqrt().ln()));
+        // 3.841 is the 5% critical value with one degree of freedom
+        assert!((chi_square_p(3.841459, 1) - 0.05).abs() < 1e-6);
+        assert!((normal_p(1.959964) - 0.05).abs() < 1e-6);
+        assert!(close(normal_p(0.0), 1.0));
+        assert!(close(erfc(-1.0), 2.0 - erfc(1.0)));
+    }
+}
@@ -0,0 +1,424 @@
+//! The `verify` module replays recorded rounds against revealed seeds
+//!
+//! Before play begins the server publishes the Sha256 of its seed, and before
+//! each bet it publishes the Sha256 of the shuffled deck. Once the session is
+//! over and the seeds are revealed, every round in a `Log` can be rebuilt and
+//! replayed with `Game::replay` to confirm that the cards, outcomes and
+//! payouts were all fair
+use super::*;
+use crate::game::Error;
+use crate::replay::{Divergence, RoundRecord};
+use crate::rng::{Seed, SeededRng};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A single recorded round
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Round {
+    /// Sha256 of the deck after both shuffles, committed before the bet
+    pub deck: String,
+    pub bet: Money,
+    pub actions: Vec<Action>,
+    /// Final view of the table, once the dealer has played or the round
+    /// ended in an `Error`
+    pub view: View,
+    /// Player's chips after winnings have been paid out
+    pub chips: Money,
+}
+
+/// A session of rounds, dealt in order from the same pair of seeds
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Log {
+    pub rules: Ruleset,
+    /// Sha256 of the server's seed, published before the first round
+    pub server: String,
+    /// Player's chips before the first round
+    pub chips: Money,
+    pub rounds: Vec<Round>,
+}
+
+/// The first discrepancy found while replaying a `Log`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mismatch {
+    /// The revealed server seed does not match the published commitment
+    ServerSeed { expected: String, found: String },
+    Deck {
+        round: usize,
+        expected: String,
+        found: String,
+    },
+    /// A recorded action was rejected by the engine
+    Action {
+        round: usize,
+        action: usize,
+        error: Error,
+    },
+    /// The engine failed outside of a player action (bet, dealer, finish)
+    Engine { round: usize, error: Error },
+    /// The recorded actions ended while it was still the player's turn
+    Incomplete { round: usize },
+    Outcome {
+        round: usize,
+        expected: Vec<Outcome>,
+        found: Vec<Outcome>,
+    },
+    /// Cards or bets in the recorded view differ from the replay
+    View { round: usize },
+    Payout {
+        round: usize,
+        expected: Money,
+        found: Money,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::ServerSeed { expected, found } => write!(
+                f,
+                "server seed hashes to {}, but {} was committed",
+                found, expected
+            ),
+            Mismatch::Deck {
+                round,
+                expected,
+                found,
+            } => write!(
+                f,
+                "round {}: deck hashes to {}, but {} was committed",
+                round, found, expected
+            ),
+            Mismatch::Action {
+                round,
+                action,
+                error,
+            } => write!(
+                f,
+                "round {}: action {} was rejected ({:?})",
+                round, action, error
+            ),
+            Mismatch::Engine { round, error } => write!(f, "round {}: {:?}", round, error),
+            Mismatch::Incomplete { round } => {
+                write!(f, "round {}: actions end during the player's turn", round)
+            }
+            Mismatch::Outcome {
+                round,
+                expected,
+                found,
+            } => write!(
+                f,
+                "round {}: recorded outcomes {:?}, replay gives {:?}",
+                round, expected, found
+            ),
+            Mismatch::View { round } => {
+                write!(f, "round {}: recorded cards differ from the replay", round)
+            }
+            Mismatch::Payout {
+                round,
+                expected,
+                found,
+            } => write!(
+                f,
+                "round {}: recorded {} chips, replay gives {}",
+                round, expected, found
+            ),
+        }
+    }
+}
+
+/// Replays rounds in order, carrying both RNGs and the player's chips
+/// from one round to the next exactly as the server did
+pub struct Verifier {
+    rules: Ruleset,
+    server: SeededRng,
+    client: SeededRng,
+    chips: Money,
+    /// Fraction of a chip owed to the player, under `Rounding::Carry`
+    carry: Money,
+    round: usize,
+}
+
+impl Verifier {
+    pub fn new(rules: Ruleset, server: &Seed, client: &Seed, chips: Money) -> Verifier {
+        Verifier {
+            rules,
+            server: server.rng(),
+            client: client.rng(),
+            chips,
+            carry: Money::ZERO,
+            round: 0,
+        }
+    }
+
+    /// Number of rounds successfully verified so far
+    pub fn verified(&self) -> usize {
+        self.round
+    }
+
+    /// Verify the next round in the session
+    pub fn verify(&mut self, round: &Round) -> Result<(), Mismatch> {
+        let idx = self.round;
+        let engine = |error| Mismatch::Engine { round: idx, error };
+
+        let record = RoundRecord {
+            rules: self.rules,
+            server: self.server.seed(),
+            client: self.client.seed(),
+            chips: self.chips,
+            carry: self.carry,
+            bet: round.bet,
+            actions: round.actions.clone(),
+            round: idx as u64,
+        };
+        let (server, client) = (self.server.clone(), self.client.clone());
+        let replay = Game::replay_with(&record, server, client).map_err(|d| match d {
+            Divergence::Action { index, error, .. } => Mismatch::Action {
+                round: idx,
+                action: index,
+                error,
+            },
+            Divergence::Incomplete { .. } => Mismatch::Incomplete { round: idx },
+            Divergence::Bet(error) | Divergence::Dealer(error) => engine(error),
+        })?;
+        let game = replay.game;
+
+        let deck = game.commitment();
+        if deck != round.deck {
+            return Err(Mismatch::Deck {
+                round: idx,
+                expected: round.deck.clone(),
+                found: deck.into(),
+            });
+        }
+
+        let view = game.view();
+        if view.scores != round.view.scores {
+            return Err(Mismatch::Outcome {
+                round: idx,
+                expected: round.view.scores.clone(),
+                found: view.scores,
+            });
+        }
+        if view != round.view {
+            return Err(Mismatch::View { round: idx });
+        }
+
+        // A round where the shoe ran out has already refunded the bet
+        let player = if view.state == State::Error {
+            game.player
+        } else {
+            game.finish().map_err(engine)?
+        };
+        if player.chips != round.chips {
+            return Err(Mismatch::Payout {
+                round: idx,
+                expected: round.chips,
+                found: player.chips,
+            });
+        }
+
+        self.server = replay.server;
+        self.client = replay.client;
+        self.chips = player.chips;
+        self.carry = player.carry;
+        self.round += 1;
+        Ok(())
+    }
+}
+
+/// Check the revealed server seed against the Sha256 published before the
+/// first round
+pub fn check_seed(commitment: &str, server: &Seed) -> Result<(), Mismatch> {
+    let found = server.commit();
+    if found != commitment {
+        return Err(Mismatch::ServerSeed {
+            expected: commitment.into(),
+            found,
+        });
+    }
+    Ok(())
+}
+
+/// Verify every round in a `Log`, stopping at the first mismatch.
+/// Returns the number of rounds verified
+pub fn verify(log: &Log, server: &Seed, client: &Seed) -> Result<usize, Mismatch> {
+    check_seed(&log.server, server)?;
+    let mut verifier = Verifier::new(log.rules, server, client, log.chips);
+    for round in &log.rounds {
+        verifier.verify(round)?;
+    }
+    Ok(verifier.verified())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Backend;
+    use crate::session::Session;
+
+    /// Play `rounds` rounds, doubling and splitting where allowed, and
+    /// return the log with a record of every round
+    fn play(server: &Seed, client: &Seed, rounds: usize) -> (Log, Vec<RoundRecord>) {
+        play_with(Ruleset::default().decks(2), server, client, rounds)
+    }
+
+    fn play_with(
+        rules: Ruleset,
+        server: &Seed,
+        client: &Seed,
+        rounds: usize,
+    ) -> (Log, Vec<RoundRecord>) {
+        let player = Player::new(Money::chips(1000));
+        let mut session = Session::new(rules, player, server.rng(), client.rng(), server.commit());
+        let mut records = Vec::new();
+        for round in 0..rounds {
+            let (server, client) = session.rngs();
+            let mut record = RoundRecord {
+                rules,
+                server: server.seed(),
+                client: client.seed(),
+                chips: session.chips(),
+                carry: session.carry(),
+                bet: Money::chips(5),
+                actions: Vec::new(),
+                round: round as u64,
+            };
+            let mut view = session.start(record.bet).unwrap();
+            while let State::Player(idx) = view.state {
+                let hand = &view.player.hands[idx];
+                let action = if view.valid_action(Action::Split).is_ok() {
+                    Action::Split
+                } else if hand.score() == 11 && view.valid_action(Action::Double).is_ok() {
+                    Action::Double
+                } else if hand.score() < 17 {
+                    Action::Hit
+                } else {
+                    Action::Stand
+                };
+                record.actions.push(action);
+                view = session.player(action).unwrap();
+            }
+            if view.state == State::Dealer {
+                session.dealer().unwrap();
+            }
+            session.finish().unwrap();
+            records.push(record);
+        }
+        (session.log().clone(), records)
+    }
+
+    #[test]
+    fn verifies_every_backend() {
+        for &backend in &[
+            Backend::Pcg32,
+            Backend::Pcg64,
+            Backend::Xoshiro256PlusPlus,
+            Backend::ChaCha20,
+        ] {
+            let server = Seed::generate(backend);
+            let client = Seed::generate(Backend::ChaCha20);
+            let (log, records) = play(&server, &client, 40);
+            assert_eq!(verify(&log, &server, &client), Ok(40), "{}", backend);
+
+            // Any single round replays on its own from its record
+            for (record, round) in records.iter().zip(&log.rounds).skip(25) {
+                let replay = Game::replay(record).unwrap();
+                assert_eq!(replay.game.view(), round.view);
+            }
+        }
+    }
+
+    #[test]
+    fn verifies_infinite_deck_rounds_by_nonce() {
+        let server = Seed::generate(Backend::ChaCha20);
+        let client = Seed::generate(Backend::ChaCha20);
+        let (log, records) = play_with(Ruleset::default().infinite(), &server, &client, 10);
+        assert_eq!(verify(&log, &server, &client), Ok(10));
+
+        // A record replayed as another round of the session deals other cards
+        let record = &records[5];
+        assert_eq!(
+            Game::replay(record).unwrap().game.view(),
+            log.rounds[5].view
+        );
+        let moved = RoundRecord {
+            round: 6,
+            ..record.clone()
+        };
+        if let Ok(replay) = Game::replay(&moved) {
+            assert_ne!(replay.game.view(), log.rounds[5].view);
+        }
+    }
+
+    #[test]
+    fn verifies_rounds_where_the_shoe_ran_out() {
+        // No cards at all, so every round ends in an error with the bet
+        // refunded, and still moves both RNGs on
+        let rules = Ruleset {
+            decks: Decks::Finite(0),
+            ..Ruleset::default()
+        };
+        let server = Seed::generate(Backend::ChaCha20);
+        let client = Seed::generate(Backend::ChaCha20);
+        let (mut server_rng, mut client_rng) = (server.rng(), client.rng());
+        let mut log = Log {
+            rules,
+            server: server.commit(),
+            chips: Money::chips(100),
+            rounds: Vec::new(),
+        };
+        for round in 0..2 {
+            let record = RoundRecord {
+                rules,
+                server: server_rng.seed(),
+                client: client_rng.seed(),
+                chips: log.chips,
+                carry: Money::ZERO,
+                bet: Money::chips(5),
+                actions: Vec::new(),
+                round,
+            };
+            let replay = Game::replay(&record).unwrap();
+            let view = replay.game.view();
+            assert_eq!(view.state, State::Error);
+            log.rounds.push(Round {
+                deck: replay.game.commitment().into(),
+                bet: record.bet,
+                actions: Vec::new(),
+                view,
+                chips: replay.game.player.chips,
+            });
+            server_rng = replay.server;
+            client_rng = replay.client;
+        }
+        assert_eq!(log.rounds[1].chips, Money::chips(100));
+        assert_eq!(verify(&log, &server, &client), Ok(2));
+
+        log.rounds[1].chips = Money::chips(95);
+        assert!(matches!(
+            verify(&log, &server, &client),
+            Err(Mismatch::Payout { round: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_wrong_seeds() {
+        let server = Seed::generate(Backend::ChaCha20);
+        let client = Seed::generate(Backend::ChaCha20);
+        let (log, _) = play(&server, &client, 3);
+
+        let other = Seed::generate(Backend::ChaCha20);
+        assert!(matches!(
+            verify(&log, &other, &client),
+            Err(Mismatch::ServerSeed { .. })
+        ));
+        // The other shoe may break the recorded actions before its
+        // commitment is compared
+        assert!(matches!(
+            verify(&log, &server, &other),
+            Err(Mismatch::Deck { round: 0, .. })
+                | Err(Mismatch::Action { round: 0, .. })
+                | Err(Mismatch::Incomplete { round: 0 })
+        ));
+    }
+}
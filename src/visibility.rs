@@ -0,0 +1,177 @@
+//! What each kind of observer of a game is allowed to see
+//!
+//! `Game::view` is the player's view: their own cards and chips, and the
+//! dealer's upcard until the dealer plays. `Game::project` produces the view
+//! for any `VisibilityPolicy`. A spectator sees every card at the table but
+//! not the player's bankroll. A counting trainer also sees how many of each
+//! rank are still unseen, counting the hole card as unseen. Only an auditor
+//! sees the hole card early and the order of the shoe. The seeds are never
+//! part of a game, so once they have been revealed `Game::audit` adds them,
+//! with the commitment to the server's seed, to the auditor's projection
+use super::rng::Seed;
+use super::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum VisibilityPolicy {
+    Player,
+    Spectator,
+    Trainer,
+    Auditor,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Projection {
+    pub policy: VisibilityPolicy,
+    pub view: View,
+    /// Number of each rank not yet seen, in the order of `RANKS`. Only for
+    /// `Trainer` and `Auditor`, and never for an infinite deck
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unseen: Option<[usize; 13]>,
+    /// The rest of the shoe, in dealing order. Only for `Auditor`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shoe: Option<Shoe>,
+    /// Sha256 of the shoe when the bet was placed. Only for `Auditor`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commitment: Option<String>,
+    /// The round's revealed seeds. Only for `Auditor`, from `Game::audit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seeds: Option<Seeds>,
+}
+
+/// Both RNGs at the start of a round, as in its `RoundRecord`
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Seeds {
+    pub server: Seed,
+    pub client: Seed,
+    /// Sha256 of the server's seed, published before the session
+    pub commitment: String,
+}
+
+impl Game {
+    /// The game as seen under `policy`
+    pub fn project(&self, policy: VisibilityPolicy) -> Projection {
+        let mut view = self.view();
+        let mut projection = Projection {
+            policy,
+            view: view.clone(),
+            unseen: None,
+            shoe: None,
+            commitment: None,
+            seeds: None,
+        };
+        match policy {
+            VisibilityPolicy::Player => {}
+            VisibilityPolicy::Spectator => {
+                view.player.chips = Money::ZERO;
+                view.player.carry = Money::ZERO;
+                view.player.account = None;
+                view.player.lock = None;
+                projection.view = view;
+            }
+            VisibilityPolicy::Trainer => projection.unseen = unseen(&view),
+            VisibilityPolicy::Auditor => {
+                view.dealer = self.dealer.clone();
+                projection.unseen = unseen(&view);
+                projection.view = view;
+                projection.shoe = Some(self.shoe.clone());
+                projection.commitment = Some(self.commitment.clone());
+            }
+        }
+        projection
+    }
+
+    /// The auditor's projection, with the seeds the round was dealt from
+    /// once they have been revealed
+    pub fn audit(&self, server: &Seed, client: &Seed) -> Projection {
+        Projection {
+            seeds: Some(Seeds {
+                server: server.clone(),
+                client: client.clone(),
+                commitment: server.commit(),
+            }),
+            ..self.project(VisibilityPolicy::Auditor)
+        }
+    }
+}
+
+/// Cards of each rank in a full shoe, less those visible in `view`
+fn unseen(view: &View) -> Option<[usize; 13]> {
+    let decks = view.rules.decks.count()?;
+    let mut counts = [4 * decks; 13];
+    let hands = view.player.hands.iter().chain(Some(&view.dealer));
+    for card in hands.flat_map(|hand| &hand.cards) {
+        counts[card.rank as usize] -= 1;
+    }
+    Some(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Backend;
+
+    #[test]
+    fn hole_card_is_hidden_until_the_dealer_plays() {
+        // Player 10h 9s, dealer Qd in the hole and 5c up
+        let shoe = Shoe::Deck(Deck::stacked("10h Qd 9s 5c 7h"));
+        let mut game = Game::with_shoe(Ruleset::default(), Player::new(Money::chips(10)), shoe);
+        game.bet(Money::chips(2)).unwrap();
+        assert_eq!(game.state, State::Player(0));
+        let hole = serde_json::to_value(game.dealer.cards[0]).unwrap();
+
+        for &policy in &[
+            VisibilityPolicy::Player,
+            VisibilityPolicy::Spectator,
+            VisibilityPolicy::Trainer,
+        ] {
+            let json = serde_json::to_value(game.project(policy)).unwrap();
+            let dealer = json["view"]["dealer"]["cards"].as_array().unwrap();
+            assert_eq!(dealer.len(), 1, "{:?}", policy);
+            assert!(!dealer.contains(&hole), "{:?}", policy);
+            assert_ne!(json["view"]["last"]["Dealer"], hole, "{:?}", policy);
+            assert!(
+                !json.to_string().contains(&hole.to_string()),
+                "{:?}",
+                policy
+            );
+        }
+
+        let json = serde_json::to_value(game.project(VisibilityPolicy::Auditor)).unwrap();
+        let dealer = json["view"]["dealer"]["cards"].as_array().unwrap();
+        assert_eq!(dealer.len(), 2);
+        assert_eq!(dealer[0], hole);
+    }
+
+    #[test]
+    fn auditor_sees_the_seeds() {
+        let server = Seed::new(Backend::ChaCha20, &[3; 32]).unwrap();
+        let client = Seed::new(Backend::ChaCha20, &[4; 32]).unwrap();
+        let deal = || {
+            let player = Player::new(Money::chips(10));
+            let mut game = Game::init(Ruleset::default(), player, &mut server.rng(), 0);
+            game.player_shuffle(&mut client.rng()).unwrap();
+            game.bet(Money::chips(2)).unwrap();
+            game
+        };
+        let game = deal();
+        for &policy in &[
+            VisibilityPolicy::Player,
+            VisibilityPolicy::Spectator,
+            VisibilityPolicy::Trainer,
+            VisibilityPolicy::Auditor,
+        ] {
+            assert_eq!(game.project(policy).seeds, None, "{:?}", policy);
+        }
+
+        let audit = game.audit(&server, &client);
+        let seeds = audit.seeds.unwrap();
+        assert_eq!(seeds.commitment, server.commit());
+        // The seeds are enough to deal the round again, shoe and all
+        assert_eq!(seeds.server, server);
+        assert_eq!(seeds.client, client);
+        assert_eq!(audit.commitment.unwrap(), deal().commitment());
+        assert_eq!(audit.shoe, Some(deal().shoe));
+        assert_eq!(audit.view.dealer, game.dealer);
+    }
+}
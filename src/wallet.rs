@@ -0,0 +1,619 @@
+//! Player accounts, and a wallet holding their money away from the table
+//!
+//! Every change to an account is a `Transaction`, appended to a JSON lines
+//! file and synced to disk before it takes effect, so the file is the
+//! wallet's history and its state. `Wallet::load` rebuilds the accounts by replaying
+//! it, checking the balance recorded after every transaction along the way.
+//!
+//! To play, `Wallet::sit` locks part of an account's balance and seats a
+//! `Player` with it as chips. The game takes its stakes from those chips and
+//! pays back into them, and `Wallet::settle` finishes the game and returns
+//! whatever the player holds to the account, or `Wallet::refund` returns the
+//! locked funds of a round that was never settled. Either way the amount
+//! comes from the game itself, which must have been started with the player
+//! as seated. Locked funds can't be withdrawn or locked again until then
+use super::event::GameEvent;
+use super::game::Error;
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Seek, SeekFrom, Write};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct AccountId(pub u64);
+
+impl fmt::Display for AccountId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Account {
+    /// Funds that can be withdrawn or taken to the table
+    pub balance: Money,
+    /// Funds at the table for a round in progress
+    pub locked: Money,
+    /// Index of the transaction that locked them
+    #[serde(default)]
+    pub lock: Option<u64>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Kind {
+    Open,
+    Deposit(Money),
+    Withdrawal(Money),
+    /// Funds taken to the table
+    Lock(Money),
+    /// The player left the table with `returned`, releasing `locked`,
+    /// which was taken by transaction `lock`
+    Unlock {
+        lock: u64,
+        locked: Money,
+        returned: Money,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Transaction {
+    /// Position in the log, starting from 0
+    pub index: u64,
+    pub account: AccountId,
+    pub kind: Kind,
+    /// The account after the transaction
+    pub after: Account,
+}
+
+#[derive(Debug)]
+pub enum WalletError {
+    /// No account with this id has been opened
+    Unknown(AccountId),
+    /// An account with this id is already open
+    Exists(AccountId),
+    /// The account's balance is short by this much
+    Insufficient(Money),
+    /// The account already has funds at the table
+    Seated(AccountId),
+    /// The account has no funds at the table, the game's player isn't
+    /// seated from an account with the funds locked for them, or their
+    /// funds have already been released
+    NotSeated,
+    /// Amounts moved must be positive
+    Zero,
+    /// The game could not be finished, or a settled game was to be refunded
+    Game(Error),
+    /// Transaction `index` in a loaded log is out of order, or records a
+    /// different result than replaying it gives
+    Corrupt(u64),
+    Io(io::Error),
+}
+
+impl From<io::Error> for WalletError {
+    fn from(e: io::Error) -> WalletError {
+        WalletError::Io(e)
+    }
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WalletError::Unknown(id) => write!(f, "no account {}", id),
+            WalletError::Exists(id) => write!(f, "account {} already exists", id),
+            WalletError::Insufficient(short) => write!(f, "balance is {} short", short),
+            WalletError::Seated(id) => write!(f, "account {} is already at the table", id),
+            WalletError::NotSeated => write!(f, "player is not seated from an account"),
+            WalletError::Zero => write!(f, "amount must be positive"),
+            WalletError::Game(e) => write!(f, "game could not be finished ({:?})", e),
+            WalletError::Corrupt(index) => write!(f, "transaction {} does not replay", index),
+            WalletError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for WalletError {}
+
+/// A writer whose output can be synced to disk, as a wallet's log must be
+/// before its transactions take effect, and cut back after a failed write
+pub trait SyncWrite: Write {
+    /// Flush everything written so far, and sync it to disk
+    fn sync(&mut self) -> io::Result<()>;
+
+    /// Length of the output, where the next write goes
+    fn end(&mut self) -> io::Result<u64>;
+
+    /// Drop the output past `len`, and carry on writing from there
+    fn truncate(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl SyncWrite for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_data()
+    }
+
+    fn end(&mut self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.set_len(len)?;
+        self.seek(SeekFrom::Start(len))?;
+        Ok(())
+    }
+}
+
+impl SyncWrite for BufWriter<File> {
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.get_ref().sync_data()
+    }
+
+    fn end(&mut self) -> io::Result<u64> {
+        self.flush()?;
+        self.get_mut().end()
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        // Whatever is still buffered belongs to the failed write
+        let file = BufWriter::with_capacity(self.capacity(), self.get_ref().try_clone()?);
+        drop(std::mem::replace(self, file).into_parts());
+        self.get_mut().truncate(len)
+    }
+}
+
+/// Nothing to sync in memory
+impl SyncWrite for Vec<u8> {
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn end(&mut self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        Vec::truncate(self, len as usize);
+        Ok(())
+    }
+}
+
+pub struct Wallet<W: SyncWrite> {
+    out: W,
+    accounts: BTreeMap<AccountId, Account>,
+    len: u64,
+}
+
+impl<W: SyncWrite> Wallet<W> {
+    /// Start a new wallet with no accounts
+    pub fn new(out: W) -> Wallet<W> {
+        Wallet {
+            out,
+            accounts: BTreeMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Rebuild a wallet from its transactions, as read by `read`, and
+    /// continue appending to `out`
+    pub fn load(out: W, transactions: &[Transaction]) -> Result<Wallet<W>, WalletError> {
+        let mut wallet = Wallet::new(out);
+        for tx in transactions {
+            let after = wallet.apply(tx.account, tx.kind);
+            if tx.index != wallet.len || after.ok() != Some(tx.after) {
+                return Err(WalletError::Corrupt(tx.index));
+            }
+            wallet.accounts.insert(tx.account, tx.after);
+            wallet.len += 1;
+        }
+        Ok(wallet)
+    }
+
+    pub fn account(&self, id: AccountId) -> Option<Account> {
+        self.accounts.get(&id).copied()
+    }
+
+    /// Number of transactions in the log
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn open(&mut self, id: AccountId) -> Result<Transaction, WalletError> {
+        self.commit(id, Kind::Open)
+    }
+
+    pub fn deposit(&mut self, id: AccountId, amount: Money) -> Result<Transaction, WalletError> {
+        self.commit(id, Kind::Deposit(amount))
+    }
+
+    pub fn withdraw(&mut self, id: AccountId, amount: Money) -> Result<Transaction, WalletError> {
+        self.commit(id, Kind::Withdrawal(amount))
+    }
+
+    /// Lock `amount` of the account's balance, and seat a player with it
+    pub fn sit(&mut self, id: AccountId, amount: Money) -> Result<Player, WalletError> {
+        let tx = self.commit(id, Kind::Lock(amount))?;
+        Ok(Player {
+            account: Some(id),
+            lock: Some(tx.index),
+            ..Player::new(amount)
+        })
+    }
+
+    /// Finish a game in the `Final` state and return the player's chips,
+    /// including the payout, to their account. A game that ended in an
+    /// `Error` has already refunded the bet, and returns the chips as they
+    /// are. Refused if the funds locked for the game were already released,
+    /// even if the account has been seated again since
+    pub fn settle(&mut self, game: Game) -> Result<Transaction, WalletError> {
+        let (id, lock, locked) = self.seated(&game)?;
+        let returned = if game.state == State::Error {
+            game.player.chips
+        } else {
+            game.finish().map_err(WalletError::Game)?.chips
+        };
+        self.unlock(id, lock, locked, returned)
+    }
+
+    /// Return the funds locked for a game that will not be settled, such as
+    /// one interrupted during the player's turn. Refused for a game in the
+    /// `Final` state, which must be settled
+    pub fn refund(&mut self, game: Game) -> Result<Transaction, WalletError> {
+        let (id, lock, locked) = self.seated(&game)?;
+        if game.state == State::Final {
+            return Err(WalletError::Game(Error::InvalidAction));
+        }
+        self.unlock(id, lock, locked, locked)
+    }
+
+    /// Sum of every account's balance and locked funds
+    pub fn total(&self) -> Money {
+        self.accounts.values().map(|a| a.balance + a.locked).sum()
+    }
+
+    /// The account, lock and locked funds the game's player was seated
+    /// with. The game must have started with exactly the player `sit` gave,
+    /// and the lock must still be held
+    fn seated(&self, game: &Game) -> Result<(AccountId, u64, Money), WalletError> {
+        let player = match game.events().first() {
+            Some(GameEvent::Created { player, .. }) => player,
+            _ => return Err(WalletError::NotSeated),
+        };
+        let id = player.account.ok_or(WalletError::NotSeated)?;
+        let account = self.accounts.get(&id).ok_or(WalletError::Unknown(id))?;
+        let seated = Player {
+            account: Some(id),
+            lock: account.lock,
+            ..Player::new(account.locked)
+        };
+        match account.lock {
+            Some(lock) if *player == seated => Ok((id, lock, account.locked)),
+            _ => Err(WalletError::NotSeated),
+        }
+    }
+
+    fn unlock(
+        &mut self,
+        id: AccountId,
+        lock: u64,
+        locked: Money,
+        returned: Money,
+    ) -> Result<Transaction, WalletError> {
+        let kind = Kind::Unlock {
+            lock,
+            locked,
+            returned,
+        };
+        self.commit(id, kind)
+    }
+
+    /// Write a transaction to the log, then apply it. A transaction that
+    /// can't be written in full is cut from the log again
+    fn commit(&mut self, id: AccountId, kind: Kind) -> Result<Transaction, WalletError> {
+        let after = self.apply(id, kind)?;
+        let tx = Transaction {
+            index: self.len,
+            account: id,
+            kind,
+            after,
+        };
+        let mut line = serde_json::to_vec(&tx).map_err(io::Error::from)?;
+        line.push(b'\n');
+        let end = self.out.end()?;
+        if let Err(e) = self.out.write_all(&line).and_then(|()| self.out.sync()) {
+            self.out.truncate(end)?;
+            return Err(e.into());
+        }
+        self.accounts.insert(id, after);
+        self.len += 1;
+        Ok(tx)
+    }
+
+    /// The account after `kind`, if it is allowed
+    fn apply(&self, id: AccountId, kind: Kind) -> Result<Account, WalletError> {
+        let account = self.accounts.get(&id).copied();
+        let mut account = match (kind, account) {
+            (Kind::Open, None) => return Ok(Account::default()),
+            (Kind::Open, Some(_)) => return Err(WalletError::Exists(id)),
+            (_, None) => return Err(WalletError::Unknown(id)),
+            (_, Some(account)) => account,
+        };
+        let take = |balance: Money, amount: Money| {
+            balance
+                .checked_sub(amount)
+                .ok_or_else(|| WalletError::Insufficient(amount - balance))
+        };
+        match kind {
+            Kind::Open => unreachable!(),
+            Kind::Deposit(amount) | Kind::Withdrawal(amount) | Kind::Lock(amount)
+                if amount.is_zero() =>
+            {
+                return Err(WalletError::Zero)
+            }
+            Kind::Deposit(amount) => account.balance += amount,
+            Kind::Withdrawal(amount) => account.balance = take(account.balance, amount)?,
+            Kind::Lock(_) if !account.locked.is_zero() => return Err(WalletError::Seated(id)),
+            Kind::Lock(amount) => {
+                account.balance = take(account.balance, amount)?;
+                account.locked = amount;
+                account.lock = Some(self.len);
+            }
+            Kind::Unlock {
+                lock,
+                locked,
+                returned,
+            } => {
+                if account.lock != Some(lock) || locked != account.locked {
+                    return Err(WalletError::NotSeated);
+                }
+                account.locked = Money::ZERO;
+                account.lock = None;
+                account.balance += returned;
+            }
+        }
+        Ok(account)
+    }
+}
+
+/// Read the transactions written by a `Wallet`, one per line
+pub fn read<R: BufRead>(reader: R) -> serde_json::Result<Vec<Transaction>> {
+    serde_json::Deserializer::from_reader(reader)
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: AccountId = AccountId(1);
+
+    /// A wallet with 100 chips in account `ID`
+    fn funded() -> Wallet<Vec<u8>> {
+        let mut wallet = Wallet::new(Vec::new());
+        wallet.open(ID).unwrap();
+        wallet.deposit(ID, Money::chips(100)).unwrap();
+        wallet
+    }
+
+    /// A round the player wins, betting 2 chips
+    fn won(player: Player) -> Game {
+        let shoe = Shoe::Deck(Deck::stacked("10h 6c 9s 10d Kc"));
+        let mut game = Game::with_shoe(Ruleset::default(), player, shoe);
+        game.bet(Money::chips(2)).unwrap();
+        game.player(Action::Stand).unwrap();
+        game.dealer().unwrap();
+        game
+    }
+
+    /// Fails to sync once, after writing part of a line
+    struct Flaky {
+        out: Vec<u8>,
+        fail: bool,
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.out.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SyncWrite for Flaky {
+        fn sync(&mut self) -> io::Result<()> {
+            if std::mem::replace(&mut self.fail, false) {
+                return Err(io::Error::other("disk full"));
+            }
+            Ok(())
+        }
+
+        fn end(&mut self) -> io::Result<u64> {
+            self.out.end()
+        }
+
+        fn truncate(&mut self, len: u64) -> io::Result<()> {
+            SyncWrite::truncate(&mut self.out, len)
+        }
+    }
+
+    #[test]
+    fn deposits_and_withdrawals() {
+        let mut wallet = funded();
+        wallet.withdraw(ID, Money::chips(30)).unwrap();
+        assert_eq!(wallet.account(ID).unwrap().balance, Money::chips(70));
+        assert!(matches!(
+            wallet.withdraw(ID, Money::chips(80)),
+            Err(WalletError::Insufficient(short)) if short == Money::chips(10)
+        ));
+        assert!(matches!(
+            wallet.deposit(ID, Money::ZERO),
+            Err(WalletError::Zero)
+        ));
+        assert!(matches!(
+            wallet.deposit(AccountId(2), Money::chips(1)),
+            Err(WalletError::Unknown(AccountId(2)))
+        ));
+        assert!(matches!(wallet.open(ID), Err(WalletError::Exists(ID))));
+        assert_eq!(wallet.len(), 3);
+        assert_eq!(wallet.total(), Money::chips(70));
+    }
+
+    #[test]
+    fn locked_funds_stay_at_the_table() {
+        let mut wallet = funded();
+        wallet.sit(ID, Money::chips(60)).unwrap();
+        assert!(matches!(
+            wallet.sit(ID, Money::chips(10)),
+            Err(WalletError::Seated(ID))
+        ));
+        assert!(matches!(
+            wallet.withdraw(ID, Money::chips(50)),
+            Err(WalletError::Insufficient(short)) if short == Money::chips(10)
+        ));
+        assert_eq!(wallet.total(), Money::chips(100));
+    }
+
+    #[test]
+    fn settle_returns_what_the_game_paid() {
+        let mut wallet = funded();
+        let game = won(wallet.sit(ID, Money::chips(10)).unwrap());
+        // Chips at the table are still counted while the round is played
+        assert_eq!(wallet.total(), Money::chips(100));
+        let net = game.winnings() - game.stake();
+
+        let tx = wallet.settle(game).unwrap();
+        assert_eq!(
+            tx.kind,
+            Kind::Unlock {
+                lock: 2,
+                locked: Money::chips(10),
+                returned: Money::chips(12),
+            }
+        );
+        assert_eq!(wallet.total(), Money::chips(100) + net);
+        assert_eq!(wallet.account(ID).unwrap().balance, Money::chips(102));
+    }
+
+    #[test]
+    fn refund_returns_the_locked_funds() {
+        let mut wallet = funded();
+        let player = wallet.sit(ID, Money::chips(10)).unwrap();
+        let shoe = Shoe::Deck(Deck::stacked("10h 6c 9s 10d"));
+        let mut game = Game::with_shoe(Ruleset::default(), player.clone(), shoe);
+        game.bet(Money::chips(2)).unwrap();
+        wallet.refund(game).unwrap();
+        assert_eq!(wallet.account(ID).unwrap().balance, Money::chips(100));
+
+        // A settled round can't be refunded instead
+        let player = wallet.sit(ID, Money::chips(10)).unwrap();
+        assert!(matches!(
+            wallet.refund(won(player)),
+            Err(WalletError::Game(Error::InvalidAction))
+        ));
+    }
+
+    #[test]
+    fn settle_after_the_shoe_ran_out() {
+        let mut wallet = funded();
+        let player = wallet.sit(ID, Money::chips(10)).unwrap();
+        let shoe = Shoe::Deck(Deck::stacked("9h"));
+        let mut game = Game::with_shoe(Ruleset::default(), player, shoe);
+        assert_eq!(game.bet(Money::chips(2)), Err(Error::Fatal));
+
+        let tx = wallet.settle(game).unwrap();
+        assert!(matches!(tx.kind, Kind::Unlock { returned, .. } if returned == Money::chips(10)));
+        assert_eq!(wallet.total(), Money::chips(100));
+    }
+
+    #[test]
+    fn settle_only_trusts_the_seated_player() {
+        let mut wallet = funded();
+        let player = wallet.sit(ID, Money::chips(10)).unwrap();
+
+        // More chips than were locked
+        let forged = Player {
+            chips: Money::chips(1000),
+            ..player.clone()
+        };
+        assert!(matches!(
+            wallet.settle(won(forged)),
+            Err(WalletError::NotSeated)
+        ));
+        // Not seated from an account at all
+        assert!(matches!(
+            wallet.settle(won(Player::new(Money::chips(10)))),
+            Err(WalletError::NotSeated)
+        ));
+        assert_eq!(wallet.total(), Money::chips(100));
+        wallet.settle(won(player)).unwrap();
+    }
+
+    #[test]
+    fn settle_only_releases_its_own_lock() {
+        let path = std::env::temp_dir().join(format!("wallet-{}.jsonl", std::process::id()));
+        let mut wallet = Wallet::new(File::create(&path).unwrap());
+        wallet.open(ID).unwrap();
+        wallet.deposit(ID, Money::chips(100)).unwrap();
+
+        let player = wallet.sit(ID, Money::chips(10)).unwrap();
+        wallet.settle(won(player.clone())).unwrap();
+        wallet.sit(ID, Money::chips(10)).unwrap();
+        // The same round again, as after it was recovered twice
+        assert!(matches!(
+            wallet.settle(won(player)),
+            Err(WalletError::NotSeated)
+        ));
+
+        let file = std::io::BufReader::new(File::open(&path).unwrap());
+        let transactions = read(file).unwrap();
+        let out = std::fs::OpenOptions::new().append(true).open(&path);
+        let loaded = Wallet::load(out.unwrap(), &transactions);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().account(ID), wallet.account(ID));
+    }
+
+    #[test]
+    fn load_checks_every_balance() {
+        let wallet = funded();
+        let mut transactions = read(&wallet.out[..]).unwrap();
+        assert!(Wallet::load(Vec::new(), &transactions).is_ok());
+
+        transactions[1].after.balance = Money::chips(1000);
+        assert!(matches!(
+            Wallet::load(Vec::new(), &transactions),
+            Err(WalletError::Corrupt(1))
+        ));
+    }
+
+    #[test]
+    fn failed_write_is_cut_from_the_log() {
+        let mut wallet = Wallet::new(Flaky {
+            out: Vec::new(),
+            fail: false,
+        });
+        wallet.open(ID).unwrap();
+        let len = wallet.out.out.len();
+
+        wallet.out.fail = true;
+        assert!(matches!(
+            wallet.deposit(ID, Money::chips(100)),
+            Err(WalletError::Io(_))
+        ));
+        assert_eq!(wallet.out.out.len(), len);
+        assert_eq!(wallet.account(ID).unwrap().balance, Money::ZERO);
+
+        // The log carries on where it was cut, and still loads
+        wallet.deposit(ID, Money::chips(5)).unwrap();
+        let transactions = read(&wallet.out.out[..]).unwrap();
+        let loaded = Wallet::load(Vec::new(), &transactions).unwrap();
+        assert_eq!(loaded.account(ID).unwrap().balance, Money::chips(5));
+    }
+}
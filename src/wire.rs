@@ -0,0 +1,506 @@
+//! The versioned wire format sent to and received from clients
+//!
+//! The engine's own types serialize however their derives happen to, which
+//! changes whenever they are refactored. The types here are the contract with
+//! front ends instead: every field and enum encoding is spelled out, they are
+//! only built through conversions from the engine's types, and `schema`
+//! describes them as JSON Schema. Any change to the encoding must bump
+//! `VERSION`.
+//!
+//! Amounts are integers in minor units, `Money::SCALE` to the chip. Enums
+//! with data are tagged objects, so that they map onto TypeScript
+//! discriminated unions
+use super::game::{self, Last};
+use super::limits::Limit;
+use super::responsible::Breach;
+use super::*;
+use schemars::gen::SchemaSettings;
+use schemars::schema::{RootSchema, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Version of the wire format. Version 2 added the `surrender` outcome
+pub const VERSION: u32 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Hit,
+    Stand,
+    Split,
+    Double,
+    Surrender,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum Rank {
+    #[serde(rename = "2")]
+    Two,
+    #[serde(rename = "3")]
+    Three,
+    #[serde(rename = "4")]
+    Four,
+    #[serde(rename = "5")]
+    Five,
+    #[serde(rename = "6")]
+    Six,
+    #[serde(rename = "7")]
+    Seven,
+    #[serde(rename = "8")]
+    Eight,
+    #[serde(rename = "9")]
+    Nine,
+    #[serde(rename = "10")]
+    Ten,
+    #[serde(rename = "J")]
+    Jack,
+    #[serde(rename = "Q")]
+    Queen,
+    #[serde(rename = "K")]
+    King,
+    #[serde(rename = "A")]
+    Ace,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Suit {
+    Hearts,
+    Spades,
+    Clubs,
+    Diamonds,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Card {
+    pub rank: Rank,
+    pub suit: Suit,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Hand {
+    pub cards: Vec<Card>,
+    /// Best total of the cards, counting aces as 1 where needed
+    pub score: u8,
+    pub soft: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum State {
+    /// Waiting for a bet
+    Ready,
+    /// The player is to act on hand `hand`
+    Player {
+        hand: u32,
+    },
+    Dealer,
+    /// The round was abandoned and the bet returned
+    Error,
+    Final,
+}
+
+/// Where the last card was dealt
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "seat", rename_all = "snake_case")]
+pub enum LastCard {
+    Dealer { card: Card },
+    Player { card: Card },
+}
+
+/// Result of one of the player's hands. `amount` is what was paid back,
+/// including the returned stake, or what was lost. A surrendered hand paid
+/// back half the bet
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Outcome {
+    Lose { amount: u64 },
+    Win { amount: u64 },
+    Blackjack { amount: u64 },
+    Push { amount: u64 },
+    Surrender { amount: u64 },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    Down,
+    HalfChip,
+    Carry,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Rules {
+    /// Number of decks in the shoe, or `null` for an infinite deck
+    pub decks: Option<u32>,
+    pub dealer_stands_soft_17: bool,
+    pub double_after_split: bool,
+    pub surrender: bool,
+    pub rounding: Rounding,
+    pub min_bet: u64,
+    pub max_bet: Option<u64>,
+    pub bet_increment: u64,
+}
+
+/// The table as the player sees it
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct View {
+    /// Always `VERSION`
+    pub version: u32,
+    pub rules: Rules,
+    pub state: State,
+    /// Bet on the hand being played, or on the last hand once the player's
+    /// turn is over
+    pub bet: u64,
+    /// Player's chips, not counting the bet
+    pub chips: u64,
+    /// The dealer's cards, without the hole card until the dealer plays
+    pub dealer: Hand,
+    pub hands: Vec<Hand>,
+    pub last: LastCard,
+    /// Outcome of each hand, in order, once the round is over
+    pub outcomes: Vec<Outcome>,
+}
+
+/// Why a request was refused
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Error {
+    InvalidAction,
+    /// The engine failed, and the round can't continue
+    Fatal,
+    DoubleAfterSplit,
+    InsufficientFunds {
+        short: u64,
+    },
+    BelowMinimum {
+        minimum: u64,
+    },
+    AboveMaximum {
+        maximum: u64,
+    },
+    BetIncrement {
+        increment: u64,
+    },
+    DoubleLimit {
+        maximum: u64,
+    },
+    SplitLimit {
+        maximum: u64,
+    },
+    SideBetMinimum {
+        minimum: u64,
+    },
+    SideBetMaximum {
+        maximum: u64,
+    },
+    LossLimit {
+        limit: u64,
+    },
+    WagerLimit {
+        limit: u64,
+    },
+    SessionTime {
+        seconds: u64,
+    },
+    CoolingOff {
+        seconds: u64,
+    },
+}
+
+impl From<Action> for game::Action {
+    fn from(action: Action) -> game::Action {
+        match action {
+            Action::Hit => game::Action::Hit,
+            Action::Stand => game::Action::Stand,
+            Action::Split => game::Action::Split,
+            Action::Double => game::Action::Double,
+            Action::Surrender => game::Action::Surrender,
+        }
+    }
+}
+
+impl From<game::Action> for Action {
+    fn from(action: game::Action) -> Action {
+        match action {
+            game::Action::Hit => Action::Hit,
+            game::Action::Stand => Action::Stand,
+            game::Action::Split => Action::Split,
+            game::Action::Double => Action::Double,
+            game::Action::Surrender => Action::Surrender,
+        }
+    }
+}
+
+impl From<card::Card> for Card {
+    fn from(card: card::Card) -> Card {
+        let rank = match card.rank {
+            card::Rank::Two => Rank::Two,
+            card::Rank::Three => Rank::Three,
+            card::Rank::Four => Rank::Four,
+            card::Rank::Five => Rank::Five,
+            card::Rank::Six => Rank::Six,
+            card::Rank::Seven => Rank::Seven,
+            card::Rank::Eight => Rank::Eight,
+            card::Rank::Nine => Rank::Nine,
+            card::Rank::Ten => Rank::Ten,
+            card::Rank::Jack => Rank::Jack,
+            card::Rank::Queen => Rank::Queen,
+            card::Rank::King => Rank::King,
+            card::Rank::Ace => Rank::Ace,
+        };
+        let suit = match card.suit {
+            card::Suit::Hearts => Suit::Hearts,
+            card::Suit::Spades => Suit::Spades,
+            card::Suit::Clubs => Suit::Clubs,
+            card::Suit::Diamonds => Suit::Diamonds,
+        };
+        Card { rank, suit }
+    }
+}
+
+impl From<&player::Hand> for Hand {
+    fn from(hand: &player::Hand) -> Hand {
+        Hand {
+            cards: hand.cards.iter().map(|&card| card.into()).collect(),
+            score: hand.score(),
+            soft: hand.soft(),
+        }
+    }
+}
+
+impl From<game::State> for State {
+    fn from(state: game::State) -> State {
+        match state {
+            game::State::Ready => State::Ready,
+            game::State::Player(idx) => State::Player { hand: idx as u32 },
+            game::State::Dealer => State::Dealer,
+            game::State::Error => State::Error,
+            game::State::Final => State::Final,
+        }
+    }
+}
+
+impl From<game::Outcome> for Outcome {
+    fn from(outcome: game::Outcome) -> Outcome {
+        match outcome {
+            game::Outcome::Lose(m) => Outcome::Lose {
+                amount: m.as_minor(),
+            },
+            game::Outcome::Win(m) => Outcome::Win {
+                amount: m.as_minor(),
+            },
+            game::Outcome::Blackjack(m) => Outcome::Blackjack {
+                amount: m.as_minor(),
+            },
+            game::Outcome::Push(m) => Outcome::Push {
+                amount: m.as_minor(),
+            },
+            game::Outcome::Surrender(m) => Outcome::Surrender {
+                amount: m.as_minor(),
+            },
+        }
+    }
+}
+
+impl From<&Ruleset> for Rules {
+    fn from(rules: &Ruleset) -> Rules {
+        Rules {
+            decks: rules.decks.count().map(|n| n as u32),
+            dealer_stands_soft_17: rules.stand,
+            double_after_split: rules.double_after_split,
+            surrender: rules.surrender,
+            rounding: match rules.rounding {
+                money::Rounding::Down => Rounding::Down,
+                money::Rounding::HalfChip => Rounding::HalfChip,
+                money::Rounding::Carry => Rounding::Carry,
+            },
+            min_bet: rules.limits.min.as_minor(),
+            max_bet: rules.limits.max.map(Money::as_minor),
+            bet_increment: rules.limits.increment.as_minor(),
+        }
+    }
+}
+
+impl From<&game::View> for View {
+    fn from(view: &game::View) -> View {
+        View {
+            version: VERSION,
+            rules: (&view.rules).into(),
+            state: view.state.into(),
+            bet: view.bet.as_minor(),
+            chips: view.player.chips.as_minor(),
+            dealer: (&view.dealer).into(),
+            hands: view.player.hands.iter().map(Hand::from).collect(),
+            last: match view.last {
+                Last::Dealer(card) => LastCard::Dealer { card: card.into() },
+                Last::Player(card) => LastCard::Player { card: card.into() },
+            },
+            outcomes: view.scores.iter().map(|&o| o.into()).collect(),
+        }
+    }
+}
+
+impl From<game::Error> for Error {
+    fn from(error: game::Error) -> Error {
+        match error {
+            game::Error::InvalidAction => Error::InvalidAction,
+            game::Error::Fatal | game::Error::Journal | game::Error::Integrity => Error::Fatal,
+            game::Error::DoubleAfterSplit => Error::DoubleAfterSplit,
+            game::Error::Money(m) => Error::InsufficientFunds {
+                short: m.as_minor(),
+            },
+            game::Error::Limit(limit) => match limit {
+                Limit::Minimum(m) => Error::BelowMinimum {
+                    minimum: m.as_minor(),
+                },
+                Limit::Maximum(m) => Error::AboveMaximum {
+                    maximum: m.as_minor(),
+                },
+                Limit::Increment(m) => Error::BetIncrement {
+                    increment: m.as_minor(),
+                },
+                Limit::Double(m) => Error::DoubleLimit {
+                    maximum: m.as_minor(),
+                },
+                Limit::Split(m) => Error::SplitLimit {
+                    maximum: m.as_minor(),
+                },
+                Limit::SideMinimum(m) => Error::SideBetMinimum {
+                    minimum: m.as_minor(),
+                },
+                Limit::SideMaximum(m) => Error::SideBetMaximum {
+                    maximum: m.as_minor(),
+                },
+            },
+            game::Error::PlayerLimit(breach) => match breach {
+                Breach::Loss(m) => Error::LossLimit {
+                    limit: m.as_minor(),
+                },
+                Breach::Wager(m) => Error::WagerLimit {
+                    limit: m.as_minor(),
+                },
+                Breach::Duration(d) => Error::SessionTime {
+                    seconds: d.as_secs(),
+                },
+                Breach::CoolOff(d) => Error::CoolingOff {
+                    seconds: d.as_secs(),
+                },
+            },
+        }
+    }
+}
+
+/// JSON Schema of the wire format, with `View`, `Action`, `Outcome` and
+/// `Error` among its definitions
+pub fn schema() -> RootSchema {
+    let mut gen = SchemaSettings::draft07().into_generator();
+    let refs: Vec<Schema> = vec![
+        gen.subschema_for::<View>(),
+        gen.subschema_for::<Action>(),
+        gen.subschema_for::<Outcome>(),
+        gen.subschema_for::<Error>(),
+    ];
+    let mut schema = SchemaObject::default();
+    let metadata = schema.metadata();
+    metadata.title = Some(format!("blackjack wire format v{}", VERSION));
+    metadata.description = Some("Amounts are in minor units".into());
+    schema.subschemas().any_of = Some(refs);
+    RootSchema {
+        meta_schema: gen.settings().meta_schema.clone(),
+        schema,
+        definitions: gen.take_definitions(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A fixed view exercising every part of the format, to check the encoding
+    /// against a stored copy
+    fn sample() -> View {
+        let card = |rank, suit| card::Card { rank, suit };
+        let rules = Ruleset::default()
+            .decks(6)
+            .limits(limits::Limits::new(Money::chips(5), Money::chips(500)));
+        let view = game::View {
+            bet: Money::chips(10),
+            rules,
+            dealer: player::Hand {
+                cards: vec![
+                    card(card::Rank::Ace, card::Suit::Spades),
+                    card(card::Rank::Seven, card::Suit::Clubs),
+                ],
+            },
+            player: Player {
+                hands: vec![
+                    player::Hand {
+                        cards: vec![
+                            card(card::Rank::Eight, card::Suit::Hearts),
+                            card(card::Rank::Ace, card::Suit::Clubs),
+                        ],
+                    },
+                    player::Hand {
+                        cards: vec![
+                            card(card::Rank::Eight, card::Suit::Diamonds),
+                            card(card::Rank::Ten, card::Suit::Clubs),
+                            card(card::Rank::King, card::Suit::Hearts),
+                        ],
+                    },
+                ],
+                ..Player::new(Money::minor(12_350))
+            },
+            state: game::State::Final,
+            last: Last::Player(card(card::Rank::King, card::Suit::Hearts)),
+            scores: vec![
+                game::Outcome::Win(Money::chips(20)),
+                game::Outcome::Lose(Money::chips(10)),
+            ],
+        };
+        (&view).into()
+    }
+
+    fn stored(name: String) -> String {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("schema")
+            .join(name);
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+    }
+
+    fn pretty<T: Serialize>(value: &T) -> String {
+        serde_json::to_string_pretty(value).unwrap() + "\n"
+    }
+
+    #[test]
+    fn schema_matches_stored_copy() {
+        let name = format!("wire-v{}.schema.json", VERSION);
+        assert!(
+            stored(name.clone()) == pretty(&schema()),
+            "schema/{} no longer matches the wire format. Bump `VERSION` and \
+             write the new schema with `blackjack-schema`",
+            name
+        );
+    }
+
+    #[test]
+    fn sample_matches_stored_copy() {
+        let name = format!("view-v{}.json", VERSION);
+        let stored = stored(name.clone());
+        let sample = sample();
+        assert!(
+            stored == pretty(&sample),
+            "schema/{} no longer matches the sample view, which encodes as:\n{}",
+            name,
+            pretty(&sample)
+        );
+        // Clients of this version must still accept it
+        assert_eq!(serde_json::from_str::<View>(&stored).unwrap(), sample);
+    }
+}